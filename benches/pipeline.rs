@@ -0,0 +1,373 @@
+//! Benchmarks of the ripping pipeline against a local mock tile server.
+//!
+//! The server serves a `/img` image whose zoom level `z` (up to [`MAX_ZOOM`]) is a grid of
+//! 2<sup>z</sup>&times;2<sup>z</sup> tiles (or some multiple of 2<sup>z</sup> rows, for a tall
+//! image), optionally delaying every response to simulate the latency of the network.
+
+use std::{
+	io::{BufRead, BufReader, Write},
+	net::{TcpListener, TcpStream},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use deathrip::{RetryPolicy, RipOptions, TileSource, Zoom};
+
+/// The highest zoom level of the mock image.
+const MAX_ZOOM: usize = 4;
+/// The width and height of the mock tiles.
+const TILE_SIZE: u32 = 256;
+
+/// The ratio of the rows to the columns of the tall mock image.
+const TALL_ASPECT: usize = 16;
+/// The ratio of the rows to the columns of the large mock image, whose highest zoom level takes
+/// 256MiB as a full RGBA buffer.
+const LARGE_ASPECT: usize = 4;
+
+/// A mock tile server on a local port, serving on background threads.
+struct MockServer {
+	source:   TileSource,
+	/// The amount of requests served so far.
+	requests: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+	/// Starts a server that delays every response by the given latency, of an image with `aspect`
+	/// times as many rows as columns.
+	fn start(latency: Duration, aspect: usize) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let tile = Arc::new(encode_tile());
+		let requests = Arc::new(AtomicUsize::new(0));
+		{
+			let requests = Arc::clone(&requests);
+			std::thread::spawn(move || {
+				for stream in listener.incoming().flatten() {
+					let (tile, requests) = (Arc::clone(&tile), Arc::clone(&requests));
+					std::thread::spawn(move || serve(stream, &tile, &requests, latency, aspect));
+				}
+			});
+		}
+		Self {
+			source: TileSource::from(format!("http://{address}/img")),
+			requests,
+		}
+	}
+
+	fn requests(&self) -> usize { self.requests.load(Ordering::SeqCst) }
+}
+
+/// Serves the requests of a (keep-alive) connection.
+fn serve(stream: TcpStream, tile: &[u8], requests: &AtomicUsize, latency: Duration, aspect: usize) {
+	let mut reader = BufReader::new(stream.try_clone().unwrap());
+	let mut writer = stream;
+	loop {
+		let mut request_line = String::new();
+		if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+			return;
+		}
+		// Skip the headers.
+		loop {
+			let mut header = String::new();
+			if reader.read_line(&mut header).unwrap_or(0) == 0 {
+				return;
+			}
+			if header == "\r\n" {
+				break;
+			}
+		}
+		requests.fetch_add(1, Ordering::SeqCst);
+		std::thread::sleep(latency);
+		let mut parts = request_line.split_whitespace();
+		let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+		let body = tile_exists(path, aspect).then_some(tile);
+		let status = if body.is_some() {
+			"200 OK"
+		} else {
+			"404 Not Found"
+		};
+		let body = body.unwrap_or_default();
+		let head = format!(
+			"HTTP/1.1 {status}\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+			body.len()
+		);
+		let written = writer
+			.write_all(head.as_bytes())
+			.and_then(|()| match method {
+				"HEAD" => Ok(()),
+				_ => writer.write_all(body),
+			});
+		if written.is_err() {
+			return;
+		}
+	}
+}
+
+/// Whether the path is of a tile of the mock image (with `aspect` times as many rows as columns),
+/// e.g. `/img=x1-y2-z3`.
+fn tile_exists(path: &str, aspect: usize) -> bool {
+	let Some(params) = path.strip_prefix("/img=") else {
+		return false;
+	};
+	let (mut x, mut y, mut z) = (0, 0, 0);
+	for param in params.split('-') {
+		let (axis, value) = param.split_at(1);
+		let Ok(value) = value.parse::<usize>() else {
+			return false;
+		};
+		match axis {
+			"x" => x = value,
+			"y" => y = value,
+			"z" => z = value,
+			_ => return false,
+		}
+	}
+	z <= MAX_ZOOM && x < 1 << z && y < aspect << z
+}
+
+/// Encodes a noisy tile, so that it costs about as much to decode as a real one.
+fn encode_tile() -> Vec<u8> {
+	let mut state = 0x2545_f491_u32;
+	let tile = image::RgbImage::from_fn(TILE_SIZE, TILE_SIZE, |x, y| {
+		state ^= state << 13;
+		state ^= state >> 17;
+		state ^= state << 5;
+		let noise = (state % 32) as u8;
+		image::Rgb([
+			(x as u8).wrapping_add(noise),
+			(y as u8).wrapping_add(noise),
+			noise,
+		])
+	});
+	let mut data = Vec::new();
+	image::DynamicImage::ImageRgb8(tile)
+		.write_to(
+			&mut std::io::Cursor::new(&mut data),
+			image::ImageOutputFormat::Png,
+		)
+		.unwrap();
+	data
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+	tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.unwrap()
+}
+
+/// The options to rip the highest zoom level of the mock image, whose grid is known up front.
+fn rip_options() -> RipOptions {
+	let mut options = RipOptions::new(Zoom::new(MAX_ZOOM).unwrap());
+	options.grid = Some((1 << MAX_ZOOM, 1 << MAX_ZOOM));
+	options.retry = RetryPolicy::NONE;
+	options
+}
+
+/// Probing the zoom level and the dimensions, whose request counts are printed once up front.
+fn probe(c: &mut Criterion) {
+	let runtime = runtime();
+	let server = MockServer::start(Duration::from_millis(1), 1);
+	let client = Arc::new(reqwest::Client::new());
+	let zoom = Zoom::new(MAX_ZOOM).unwrap();
+	let retry = RetryPolicy::NONE;
+
+	let mut group = c.benchmark_group("probe");
+	let requests = server.requests();
+	runtime
+		.block_on(deathrip::determine_max_zoom(
+			Arc::clone(&client),
+			&server.source,
+			4,
+			retry,
+		))
+		.unwrap();
+	println!("max zoom probe: {} requests", server.requests() - requests);
+	group.bench_function("max_zoom", |b| {
+		b.to_async(&runtime)
+			.iter(|| deathrip::determine_max_zoom(Arc::clone(&client), &server.source, 4, retry));
+	});
+
+	let requests = server.requests();
+	runtime
+		.block_on(deathrip::determine_dimensions(
+			Arc::clone(&client),
+			&server.source,
+			zoom,
+			2,
+			retry,
+		))
+		.unwrap();
+	println!(
+		"dimensions probe: {} requests",
+		server.requests() - requests
+	);
+	group.bench_function("dimensions", |b| {
+		b.to_async(&runtime).iter(|| {
+			deathrip::determine_dimensions(Arc::clone(&client), &server.source, zoom, 2, retry)
+		});
+	});
+
+	// The columns of a tall image are done long before its rows, whose probing their workers join.
+	let tall = MockServer::start(Duration::from_millis(1), TALL_ASPECT);
+	group.bench_function("dimensions_tall", |b| {
+		b.to_async(&runtime).iter(|| {
+			deathrip::determine_dimensions(Arc::clone(&client), &tall.source, zoom, 2, retry)
+		});
+	});
+	group.finish();
+}
+
+/// Fetching the tiles at various concurrency levels, and with the default 16 tile workers over
+/// connection pools of various sizes.
+fn fetch(c: &mut Criterion) {
+	let runtime = runtime();
+	let server = MockServer::start(Duration::from_millis(2), 1);
+	let client = Arc::new(reqwest::Client::new());
+
+	let mut group = c.benchmark_group("fetch");
+	group.sample_size(10);
+	group.throughput(Throughput::Elements(1 << (2 * MAX_ZOOM)));
+	for tile_workers in [1, 4, 16, 64] {
+		let mut options = rip_options();
+		options.tile_workers = tile_workers;
+		group.bench_with_input(
+			BenchmarkId::new("tile_workers", tile_workers),
+			&options,
+			|b, options| {
+				b.to_async(&runtime)
+					.iter(|| deathrip::rip(Arc::clone(&client), &server.source, options));
+			},
+		);
+	}
+	// A pool smaller than the tile workers makes the excess workers reconnect for every tile.
+	let options = rip_options();
+	for pool_size in [1, 4, 16] {
+		let client = Arc::new(
+			reqwest::Client::builder()
+				.pool_max_idle_per_host(pool_size)
+				.build()
+				.unwrap(),
+		);
+		group.bench_with_input(
+			BenchmarkId::new("pool_size", pool_size),
+			&options,
+			|b, options| {
+				b.to_async(&runtime)
+					.iter(|| deathrip::rip(Arc::clone(&client), &server.source, options));
+			},
+		);
+	}
+	group.finish();
+}
+
+/// Stitching tiles that are already in memory, so that it's bound by the decoding and the
+/// contention over the canvas rather than by the network.
+fn stitch(c: &mut Criterion) {
+	let runtime = runtime();
+	let server = MockServer::start(Duration::ZERO, 1);
+	let client = Arc::new(reqwest::Client::new());
+	let mut options = rip_options();
+	options.tile_store = Some(Arc::new(deathrip::MemoryStore::default()));
+	// Fill the store.
+	runtime
+		.block_on(deathrip::rip(Arc::clone(&client), &server.source, &options))
+		.unwrap();
+
+	let mut group = c.benchmark_group("stitch");
+	group.sample_size(10);
+	group.throughput(Throughput::Elements(1 << (2 * MAX_ZOOM)));
+	for tile_workers in [1, 16] {
+		options.tile_workers = tile_workers;
+		group.bench_with_input(
+			BenchmarkId::new("tile_workers", tile_workers),
+			&options,
+			|b, options| {
+				b.to_async(&runtime)
+					.iter(|| deathrip::rip(Arc::clone(&client), &server.source, options));
+			},
+		);
+	}
+	group.finish();
+}
+
+/// The peak resident set size of the process since it was last [reset](reset_peak_rss), on Linux.
+fn peak_rss() -> Option<u64> {
+	let status = std::fs::read_to_string("/proc/self/status").ok()?;
+	let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+	let kib = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+	Some(kib * 1024)
+}
+
+/// Resets the [peak resident set size](peak_rss) to the current one, on Linux.
+fn reset_peak_rss() { let _ = std::fs::write("/proc/self/clear_refs", "5"); }
+
+/// Rips the image into a PNG of a full buffer of the image, rather than a tile window.
+async fn rip_full_buffer(
+	client: Arc<reqwest::Client>,
+	source: &TileSource,
+	options: &RipOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+	use image::ImageEncoder as _;
+	let image = deathrip::rip(client, source, options).await?.into_rgba8();
+	image::codecs::png::PngEncoder::new(std::io::sink()).write_image(
+		image.as_raw(),
+		image.width(),
+		image.height(),
+		image::ColorType::Rgba8,
+	)?;
+	Ok(())
+}
+
+/// Ripping a large image in the tile window mode versus into a full buffer, whose peak memory
+/// usages are printed once up front.
+fn memory(c: &mut Criterion) {
+	let runtime = runtime();
+	let server = MockServer::start(Duration::ZERO, LARGE_ASPECT);
+	let client = Arc::new(reqwest::Client::new());
+	let mut options = rip_options();
+	options.grid = Some((1 << MAX_ZOOM, LARGE_ASPECT << MAX_ZOOM));
+	let tiles = options.grid.map_or(0, |(columns, rows)| columns * rows);
+
+	let tile_window = || {
+		deathrip::rip_png_stream(
+			Arc::clone(&client),
+			&server.source,
+			&options,
+			std::io::sink(),
+		)
+	};
+	let full_buffer = || rip_full_buffer(Arc::clone(&client), &server.source, &options);
+	let mib = |bytes: Option<u64>| {
+		bytes.map_or_else(|| "?".to_owned(), |bytes| (bytes >> 20).to_string())
+	};
+	reset_peak_rss();
+	runtime.block_on(tile_window()).unwrap();
+	println!("tile window peak RSS: {}MiB", mib(peak_rss()));
+	reset_peak_rss();
+	runtime.block_on(full_buffer()).unwrap();
+	println!("full buffer peak RSS: {}MiB", mib(peak_rss()));
+
+	let mut group = c.benchmark_group("memory");
+	group.sample_size(10);
+	group.measurement_time(Duration::from_secs(30));
+	group.throughput(Throughput::Elements(tiles as u64));
+	group.bench_function("tile_window", |b| b.to_async(&runtime).iter(tile_window));
+	group.bench_function("full_buffer", |b| b.to_async(&runtime).iter(full_buffer));
+	group.finish();
+}
+
+/// Decoding a single tile, the CPU cost the pipeline pays per tile.
+fn decode(c: &mut Criterion) {
+	let tile = encode_tile();
+	c.bench_function("decode", |b| {
+		b.iter(|| image::load_from_memory(&tile).unwrap());
+	});
+}
+
+criterion_group!(benches, probe, fetch, stitch, memory, decode);
+criterion_main!(benches);
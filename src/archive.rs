@@ -0,0 +1,120 @@
+//! The `--archive` file: the outputs of a rip (its tiles, the image, and the image's sidecar files)
+//! in a single zip or tar file, for sharing or distributing them as a dataset.
+
+use std::{
+	fs::File,
+	io::{BufWriter, Write as _},
+	path::Path,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex,
+	},
+	time::SystemTime,
+};
+
+/// The writer of an archive's container format.
+enum Writer {
+	Zip(zip::ZipWriter<File>),
+	Tar(tar::Builder<BufWriter<File>>),
+}
+
+/// An archive that files are streamed into as they're produced.
+pub struct Archive {
+	/// The writer, until the archive is [finished](Archive::finish).
+	writer:       Mutex<Option<Writer>>,
+	/// The amount of tiles that failed to be added.
+	failed_tiles: AtomicUsize,
+}
+
+impl Archive {
+	/// Creates the archive at the path, of the container format of its extension (zip or tar).
+	pub fn create(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		let extension = path
+			.extension()
+			.and_then(|extension| extension.to_str())
+			.map(str::to_ascii_lowercase);
+		let writer = match extension.as_deref() {
+			Some("zip") => Writer::Zip(zip::ZipWriter::new(File::create(path)?)),
+			Some("tar") => Writer::Tar(tar::Builder::new(BufWriter::new(File::create(path)?))),
+			_ => return Err("the archive should be a .zip or .tar file".into()),
+		};
+		Ok(Self {
+			writer:       Mutex::new(Some(writer)),
+			failed_tiles: AtomicUsize::new(0),
+		})
+	}
+
+	/// The tile callback that adds the tiles as they're served to the `tiles` directory of the
+	/// archive, named like [`deathrip::TileDir`] expects them (`x<column>_y<row>_z<zoom>.<extension>`),
+	/// see [`deathrip::RipOptions::on_tile`].
+	pub fn callback(self: &Arc<Self>) -> deathrip::TileCallback {
+		let archive = Arc::clone(self);
+		Arc::new(move |tile| {
+			let (x, y) = tile.position;
+			let extension =
+				image::guess_format(tile.data).map_or("bin", |format| format.extensions_str()[0]);
+			let name = format!(
+				"tiles/{}",
+				deathrip::TileDir::file_name(tile.position, tile.zoom, extension)
+			);
+			if let Err(e) = archive.add(&name, tile.data) {
+				tracing::warn!("failed to archive the tile ({x},{y}): {e}");
+				archive.failed_tiles.fetch_add(1, Ordering::Relaxed);
+			}
+		})
+	}
+
+	/// Adds the data to the archive as a file of the given name.
+	pub fn add(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+		match self.writer.lock().unwrap().as_mut() {
+			Some(Writer::Zip(zip)) => {
+				zip.start_file(name, zip::write::FileOptions::default())?;
+				zip.write_all(data)
+			}
+			Some(Writer::Tar(tar)) => {
+				let mut header = tar::Header::new_gnu();
+				header.set_size(data.len() as u64);
+				header.set_mode(0o644);
+				header.set_mtime(
+					SystemTime::now()
+						.duration_since(SystemTime::UNIX_EPOCH)
+						.map_or(0, |time| time.as_secs()),
+				);
+				tar.append_data(&mut header, name, data)
+			}
+			None => Err(finished()),
+		}
+	}
+
+	/// Adds the file to the root of the archive under its file name, streaming it from the disk.
+	pub fn add_file(&self, path: &Path) -> std::io::Result<()> {
+		let name = path.file_name().unwrap_or_default().to_string_lossy();
+		match self.writer.lock().unwrap().as_mut() {
+			Some(Writer::Zip(zip)) => {
+				zip.start_file(name, zip::write::FileOptions::default())?;
+				std::io::copy(&mut File::open(path)?, zip).map(drop)
+			}
+			Some(Writer::Tar(tar)) => tar.append_path_with_name(path, name.as_ref()),
+			None => Err(finished()),
+		}
+	}
+
+	/// Finishes writing the archive, failing if it's missing tiles.
+	pub fn finish(&self) -> Result<(), Box<dyn std::error::Error>> {
+		match self.writer.lock().unwrap().take() {
+			Some(Writer::Zip(mut zip)) => zip.finish()?.sync_all()?,
+			Some(Writer::Tar(tar)) => {
+				let file = tar.into_inner()?.into_inner().map_err(|e| e.into_error())?;
+				file.sync_all()?;
+			}
+			None => {}
+		}
+		match self.failed_tiles.load(Ordering::Relaxed) {
+			0 => Ok(()),
+			failed => Err(format!("failed to archive {failed} tiles").into()),
+		}
+	}
+}
+
+/// The error of adding to an archive that was already finished.
+fn finished() -> std::io::Error { std::io::Error::other("the archive was already finished") }
@@ -0,0 +1,107 @@
+//! The `--tile-audit` sidecar: a record of every tile of a rip, for provenance.
+
+use std::{
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+};
+
+/// The record of a tile.
+#[derive(Debug, Clone, serde::Serialize)]
+struct TileRecord {
+	x:           usize,
+	y:           usize,
+	url:         String,
+	/// The HTTP status of the response, or `None` if the tile came from the cache.
+	status:      Option<u16>,
+	bytes:       u64,
+	/// The SHA-256 of the encoded tile, in hexadecimal.
+	sha256:      String,
+	/// The time it took to get the tile (including retries) in milliseconds.
+	duration_ms: u64,
+}
+
+/// Records the tiles of a rip.
+#[derive(Debug, Default)]
+pub struct TileAudit {
+	records: Mutex<Vec<TileRecord>>,
+}
+
+impl TileAudit {
+	/// The tile callback that records the tiles, see [`deathrip::RipOptions::on_tile`].
+	pub fn callback(self: &Arc<Self>) -> deathrip::TileCallback {
+		let audit = Arc::clone(self);
+		Arc::new(move |tile| audit.record(tile))
+	}
+
+	fn record(&self, tile: &deathrip::FetchedTile) {
+		use sha2::Digest as _;
+		let (x, y) = tile.position;
+		let record = TileRecord {
+			x,
+			y,
+			url: tile.url.to_owned(),
+			status: tile.status.map(|status| status.as_u16()),
+			bytes: tile.data.len() as u64,
+			sha256: sha2::Sha256::digest(tile.data)
+				.iter()
+				.map(|byte| format!("{byte:02x}"))
+				.collect(),
+			duration_ms: u64::try_from(tile.duration.as_millis()).unwrap_or(u64::MAX),
+		};
+		self.records.lock().unwrap().push(record);
+	}
+
+	/// Writes the records (in column-major order) as a JSON array to a sidecar file next to the
+	/// given path, resolving to the sidecar's path.
+	pub fn write_beside(&self, path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+		let mut records = self.records.lock().unwrap().clone();
+		records.sort_by_key(|record| (record.x, record.y));
+		let mut audit_path = path.as_os_str().to_owned();
+		audit_path.push(".tiles.json");
+		let audit_path = PathBuf::from(audit_path);
+		let file = std::fs::File::create(&audit_path)?;
+		serde_json::to_writer_pretty(std::io::BufWriter::new(file), &records)?;
+		Ok(audit_path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use sha2::Digest as _;
+
+	use super::*;
+	use crate::mock::{self, MockServer, TempDir};
+
+	#[tokio::test]
+	async fn records_each_tile() {
+		let server = MockServer::image(3, 2, 0);
+		let source = deathrip::TileSource::from(server.base_url());
+		let audit = Arc::new(TileAudit::default());
+		let options = deathrip::RipOptions {
+			on_tile: Some(audit.callback()),
+			..deathrip::RipOptions::new(deathrip::Zoom::new(0).unwrap())
+		};
+		deathrip::rip(Arc::new(reqwest::Client::new()), &source, &options)
+			.await
+			.unwrap();
+		let dir = TempDir::new("audit");
+		let path = audit.write_beside(&dir.join("image.png")).unwrap();
+		assert_eq!(path, dir.join("image.png.tiles.json"));
+		let records: Vec<serde_json::Value> =
+			serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+		assert_eq!(records.len(), 3 * 2);
+		let positions = (0..3).flat_map(|x| (0..2).map(move |y| (x, y)));
+		for (record, (x, y)) in records.iter().zip(positions) {
+			assert_eq!([&record["x"], &record["y"]], [x, y]);
+			assert_eq!(record["url"], source.tile_url(x, y, options.zoom));
+			assert_eq!(record["status"], 200);
+			let tile = mock::tile(x, y);
+			assert_eq!(record["bytes"], tile.len());
+			let sha256 = sha2::Sha256::digest(&tile)
+				.iter()
+				.map(|byte| format!("{byte:02x}"))
+				.collect::<String>();
+			assert_eq!(record["sha256"], sha256, "tile ({x},{y})");
+		}
+	}
+}
@@ -0,0 +1,183 @@
+//! The `--batch-csv` mode: ripping the images listed in a CSV file, each with its own settings.
+
+use std::{error::Error, path::Path, sync::Arc};
+
+use reqwest::Client;
+use tracing::Instrument;
+
+use crate::{events::EventWriter, Cli, Item};
+
+/// A row of the input CSV.
+#[derive(Debug, serde::Deserialize)]
+struct Row {
+	input:  String,
+	zoom:   Option<deathrip::Zoom>,
+	format: Option<String>,
+	output: Option<std::path::PathBuf>,
+}
+
+/// A row of the results CSV.
+#[derive(Debug, serde::Serialize)]
+struct Outcome<'a> {
+	input:      &'a str,
+	/// `ok`, `failed`, or `panicked`.
+	status:     &'static str,
+	output:     Option<String>,
+	/// In the form `<width>x<height>`.
+	dimensions: Option<String>,
+	bytes:      Option<u64>,
+	error:      Option<String>,
+}
+
+/// Rips the images listed in the CSV file, writing the results as CSV to the output (the standard
+/// output of the CLI).
+pub async fn run(
+	cli: &Cli,
+	client: Arc<Client>,
+	path: &Path,
+	events: Option<&EventWriter>,
+	output: impl std::io::Write,
+) -> Result<(), Box<dyn Error>> {
+	let mut reader = csv::ReaderBuilder::new()
+		.trim(csv::Trim::All)
+		.from_path(path)?;
+	let mut results = csv::Writer::from_writer(output);
+	let (mut total, mut failures) = (0, 0);
+	for (i, row) in reader.deserialize::<Row>().enumerate() {
+		if i > 0 {
+			if let Some(delay) = cli.between_items_delay {
+				tracing::info!("waiting {}ms before the next image", delay.as_millis());
+				tokio::time::sleep(delay).await;
+			}
+		}
+		total += 1;
+		let row = match row {
+			Ok(row) => row,
+			Err(e) => {
+				tracing::error!("failed to read row {}: {e}", i + 1);
+				failures += 1;
+				continue;
+			}
+		};
+		let span_image = tracing::info_span!("image", image = row.input.as_str());
+		let result = async {
+			let format = match &row.format {
+				Some(format) => crate::parse_format(format)?,
+				None => cli.format.clone(),
+			};
+			let item = Item {
+				image: &row.input,
+				zoom: row.zoom.map(crate::Zoom::Level).or(cli.zoom),
+				format,
+				output: row.output.clone(),
+				to_file: true,
+				manifest: None,
+				plate: None,
+				stamp: None,
+			};
+			crate::rip_isolated(cli, Arc::clone(&client), &item, events).await
+		}
+		.instrument(span_image)
+		.await;
+		let outcome = match result {
+			Ok(Ok(ripped)) => Outcome {
+				input:      &row.input,
+				status:     "ok",
+				output:     ripped.path.map(|path| path.display().to_string()),
+				dimensions: Some(format!("{}x{}", ripped.dimensions.0, ripped.dimensions.1)),
+				bytes:      ripped.bytes,
+				error:      None,
+			},
+			Ok(Err(panic)) => {
+				tracing::error!("ripping {} panicked: {panic}", row.input);
+				failures += 1;
+				Outcome {
+					input:      &row.input,
+					status:     "panicked",
+					output:     None,
+					dimensions: None,
+					bytes:      None,
+					error:      Some(panic),
+				}
+			}
+			Err(e) => {
+				tracing::error!("failed to rip {}: {e}", row.input);
+				failures += 1;
+				Outcome {
+					input:      &row.input,
+					status:     "failed",
+					output:     None,
+					dimensions: None,
+					bytes:      None,
+					error:      Some(e.to_string()),
+				}
+			}
+		};
+		results.serialize(outcome)?;
+		results.flush()?;
+	}
+
+	match failures {
+		0 => Ok(()),
+		failures => Err(format!("failed to rip {failures} of {total} images").into()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use clap::Parser as _;
+
+	use super::*;
+	use crate::mock::{MockServer, TempDir};
+
+	#[tokio::test]
+	async fn reports_each_row() {
+		let server = MockServer::image(3, 2, 0);
+		let dir = TempDir::new("batch");
+		let output = dir.join("image.png");
+		let csv = dir.join("batch.csv");
+		std::fs::write(
+			&csv,
+			format!(
+				"input,zoom,format,output\n\
+				 {},0,,{}\n\
+				 {},0,,{}\n\
+				 {},0,bmp-but-not-really,\n\
+				 {},not-a-zoom,,\n",
+				server.base_url(),
+				output.display(),
+				server.url("/ggpht.com/missing"),
+				dir.join("missing.png").display(),
+				server.base_url(),
+				server.base_url(),
+			),
+		)
+		.unwrap();
+		let cli = Cli::parse_from(["deathrip".as_ref(), "--batch-csv".as_ref(), csv.as_os_str()]);
+		let client = Arc::new(crate::client(&cli).unwrap());
+		let mut results = Vec::new();
+		let error = run(&cli, client, &csv, None, &mut results)
+			.await
+			.unwrap_err();
+		assert_eq!(error.to_string(), "failed to rip 3 of 4 images");
+
+		let mut results = csv::Reader::from_reader(results.as_slice());
+		let results = results
+			.records()
+			.map(|record| record.unwrap())
+			.collect::<Vec<_>>();
+		// The unreadable row has no result.
+		assert_eq!(results.len(), 3);
+		assert_eq!(&results[0][0], server.base_url());
+		assert_eq!(&results[0][1], "ok");
+		assert_eq!(&results[0][2], output.display().to_string());
+		assert_eq!(&results[0][3], "24x16");
+		assert!(results[0][4].parse::<u64>().unwrap() > 0);
+		assert!(image::open(&output).is_ok());
+		assert_eq!(&results[1][0], server.url("/ggpht.com/missing"));
+		assert_eq!(&results[1][1], "failed");
+		assert!(!results[1][5].is_empty());
+		assert_eq!(&results[2][1], "failed");
+		assert!(results[2][5].contains("format"), "{}", &results[2][5]);
+	}
+}
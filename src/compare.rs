@@ -0,0 +1,272 @@
+//! The `compare-zooms` subcommand: comparing two zoom levels of an image, to help choose one.
+
+use std::{error::Error, io::Write, sync::Arc};
+
+use image::GenericImageView;
+use reqwest::Client;
+
+use crate::{doctor::ReportFormat, Cli, Item, OutputStream, ScaleFilter};
+
+#[derive(clap::Args)]
+pub struct Args {
+	/// URL to the image page, image base, or item ID.
+	image:        String,
+	/// The lower zoom level.
+	low:          deathrip::Zoom,
+	/// The higher zoom level.
+	high:         deathrip::Zoom,
+	/// The amount of tiles of each zoom level to fetch to estimate its size in bytes.
+	#[clap(long, default_value_t = 8)]
+	samples:      usize,
+	/// Also rip both zoom levels and compute the similarity (SSIM) of the lower one to the higher one
+	/// scaled down to it, where 1 means the higher zoom level adds no detail.
+	#[clap(long)]
+	similarity:   bool,
+	/// The filter to scale the higher zoom level down to the lower one with for the similarity.
+	#[clap(long, value_enum, default_value_t = ScaleFilter::Triangle)]
+	scale_filter: ScaleFilter,
+	/// The format of the report.
+	#[clap(long, value_enum, default_value_t = ReportFormat::Human)]
+	format:       ReportFormat,
+}
+
+/// The measurements of a zoom level.
+#[derive(Debug, Clone, serde::Serialize)]
+struct Level {
+	zoom:            deathrip::Zoom,
+	width:           u32,
+	height:          u32,
+	columns:         usize,
+	rows:            usize,
+	/// The estimated size of all the tiles in bytes, extrapolated from the sampled tiles.
+	estimated_bytes: u64,
+	/// The amount of tiles the estimate is based on.
+	sampled_tiles:   usize,
+}
+
+/// The comparison of two zoom levels.
+#[derive(Debug, Clone, serde::Serialize)]
+struct Comparison {
+	low:         Level,
+	high:        Level,
+	/// The pixels of the higher zoom level per pixel of the lower one.
+	pixel_ratio: f64,
+	/// The estimated bytes of the higher zoom level per byte of the lower one.
+	byte_ratio:  f64,
+	/// The SSIM of the lower zoom level to the higher one scaled down to it, if computed.
+	similarity:  Option<f64>,
+}
+
+impl Comparison {
+	fn write_human(&self, mut out: impl Write) -> std::io::Result<()> {
+		for level in [&self.low, &self.high] {
+			writeln!(
+				out,
+				"zoom {}: {}\u{00D7}{} pixels, {}\u{00D7}{} tiles, ~{} (estimated from {} tiles)",
+				level.zoom,
+				level.width,
+				level.height,
+				level.columns,
+				level.rows,
+				deathrip::ByteSize(level.estimated_bytes),
+				level.sampled_tiles
+			)?;
+		}
+		writeln!(
+			out,
+			"zoom {} has {:.2}\u{00D7} the pixels and ~{:.2}\u{00D7} the bytes of zoom {}",
+			self.high.zoom, self.pixel_ratio, self.byte_ratio, self.low.zoom
+		)?;
+		if let Some(similarity) = self.similarity {
+			writeln!(
+				out,
+				"similarity of zoom {} to zoom {} scaled down: {similarity:.4} (SSIM, 1 is identical)",
+				self.low.zoom, self.high.zoom
+			)?;
+		}
+		Ok(())
+	}
+}
+
+/// Compares the zoom levels and prints the report.
+pub async fn run(cli: &Cli, args: &Args, client: Arc<Client>) -> Result<(), Box<dyn Error>> {
+	if args.low >= args.high {
+		return Err("the lower zoom level should be lower than the higher one".into());
+	}
+	let item = Item {
+		zoom: Some(crate::Zoom::Level(args.low)),
+		..Item::new(cli, &args.image)
+	};
+	let (_, source, _) = crate::resolve(cli, Arc::clone(&client), &item).await?;
+
+	let low = measure(&client, &source, args.low, args.samples).await?;
+	let high = measure(&client, &source, args.high, args.samples).await?;
+	let similarity = if args.similarity {
+		let rip = |level: &Level| {
+			let mut options = deathrip::RipOptions::new(level.zoom);
+			options.grid = Some((level.columns, level.rows));
+			options.tile_workers = cli.tile_workers;
+			let client = Arc::clone(&client);
+			let source = &source;
+			async move { deathrip::rip(client, source, &options).await }
+		};
+		tracing::info!("ripping zoom {}", low.zoom);
+		let low_image = rip(&low).await?;
+		tracing::info!("ripping zoom {}", high.zoom);
+		let high_image = rip(&high).await?;
+		Some(ssim(&low_image, &high_image, args.scale_filter))
+	} else {
+		None
+	};
+
+	let pixels = |level: &Level| f64::from(level.width) * f64::from(level.height);
+	let comparison = Comparison {
+		pixel_ratio: pixels(&high) / pixels(&low),
+		byte_ratio: high.estimated_bytes as f64 / low.estimated_bytes as f64,
+		low,
+		high,
+		similarity,
+	};
+	let mut output = OutputStream::lock();
+	match args.format {
+		ReportFormat::Human => comparison.write_human(output)?,
+		ReportFormat::Json => {
+			serde_json::to_writer_pretty(&mut output, &comparison)?;
+			writeln!(output)?;
+		}
+	}
+	Ok(())
+}
+
+/// Measures the zoom level, fetching the given amount of tiles (spread evenly across the image,
+/// starting with the first) to estimate its size.
+async fn measure(
+	client: &Arc<Client>,
+	source: &deathrip::TileSource,
+	zoom: deathrip::Zoom,
+	samples: usize,
+) -> Result<Level, Box<dyn Error>> {
+	let options = deathrip::RipOptions::new(zoom);
+	let (columns, rows) = deathrip::determine_dimensions(
+		Arc::clone(client),
+		source,
+		zoom,
+		options.num_workers_half,
+		options.retry,
+	)
+	.await?;
+	let tiles = columns * rows;
+	if tiles == 0 {
+		return Err(format!("zoom {zoom} has no tiles").into());
+	}
+	let samples = samples.clamp(1, tiles);
+	let (mut tile_size, mut bytes) = ((0, 0), 0);
+	for i in 0..samples {
+		let index = i * tiles / samples;
+		let (x, y) = (index % columns, index / columns);
+		let data = deathrip::send_request(client.get(source.tile_url(x, y, zoom)))
+			.await?
+			.error_for_status()?
+			.bytes()
+			.await?;
+		if i == 0 {
+			tile_size = image::load_from_memory(&data)?.dimensions();
+		}
+		bytes += data.len() as u64;
+	}
+	let (tile_width, tile_height) = tile_size;
+	Ok(Level {
+		zoom,
+		width: columns as u32 * tile_width,
+		height: rows as u32 * tile_height,
+		columns,
+		rows,
+		estimated_bytes: bytes * tiles as u64 / samples as u64,
+		sampled_tiles: samples,
+	})
+}
+
+/// The mean structural similarity (SSIM) of the luma of the image to the reference image scaled
+/// down to it with the filter, over windows of 8\u{00D7}8 pixels.
+fn ssim(image: &image::DynamicImage, reference: &image::DynamicImage, filter: ScaleFilter) -> f64 {
+	const WINDOW: u32 = 8;
+	const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+	const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+	let image = image.to_luma8();
+	let (width, height) = image.dimensions();
+	let reference = deathrip::downscale(&reference.to_luma8(), (width, height), filter.into());
+	let (mut total, mut windows) = (0.0, 0usize);
+	for window_y in (0..height.saturating_sub(WINDOW - 1)).step_by(WINDOW as usize) {
+		for window_x in (0..width.saturating_sub(WINDOW - 1)).step_by(WINDOW as usize) {
+			let samples = (0..WINDOW)
+				.flat_map(|y| (0..WINDOW).map(move |x| (x, y)))
+				.map(|(x, y)| {
+					(
+						f64::from(image.get_pixel(window_x + x, window_y + y)[0]),
+						f64::from(reference.get_pixel(window_x + x, window_y + y)[0]),
+					)
+				});
+			let n = f64::from(WINDOW * WINDOW);
+			let (sum_a, sum_b, sum_aa, sum_bb, sum_ab) = samples.fold(
+				(0.0, 0.0, 0.0, 0.0, 0.0),
+				|(sum_a, sum_b, sum_aa, sum_bb, sum_ab), (a, b)| {
+					(
+						sum_a + a,
+						sum_b + b,
+						sum_aa + a * a,
+						sum_bb + b * b,
+						sum_ab + a * b,
+					)
+				},
+			);
+			let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+			let variance_a = sum_aa / n - mean_a * mean_a;
+			let variance_b = sum_bb / n - mean_b * mean_b;
+			let covariance = sum_ab / n - mean_a * mean_b;
+			total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+				/ ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+			windows += 1;
+		}
+	}
+	if windows == 0 {
+		return 1.0;
+	}
+	total / windows as f64
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A smooth gradient, sampled at the given size.
+	fn gradient(size: u32) -> image::GrayImage {
+		image::GrayImage::from_fn(size, size, |x, y| {
+			image::Luma([(60 + 120 * (x + y) / (2 * size)) as u8])
+		})
+	}
+
+	#[test]
+	fn ssim_of_downscale() {
+		let low = image::DynamicImage::ImageLuma8(gradient(50));
+		// The gradient at a higher resolution, with detail that the lower one can't resolve.
+		let mut high = gradient(160);
+		for (x, y, pixel) in high.enumerate_pixels_mut() {
+			pixel[0] = if (x + y) % 2 == 0 {
+				pixel[0] + 40
+			} else {
+				pixel[0] - 40
+			};
+		}
+		let high = image::DynamicImage::ImageLuma8(high);
+
+		assert!((ssim(&low, &low, ScaleFilter::Nearest) - 1.0).abs() < 1e-9);
+		let nearest = ssim(&low, &high, ScaleFilter::Nearest);
+		let lanczos3 = ssim(&low, &high, ScaleFilter::Lanczos3);
+		assert!(
+			lanczos3 > nearest,
+			"lanczos3 {lanczos3} isn't above nearest {nearest}"
+		);
+		assert!(lanczos3 > 0.9, "{lanczos3}");
+	}
+}
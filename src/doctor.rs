@@ -0,0 +1,223 @@
+//! The `doctor` subcommand: step-by-step diagnostics of fetching an image.
+
+use std::{
+	error::Error,
+	fmt::Display,
+	future::Future,
+	io::{Cursor, Write},
+	sync::Arc,
+	time::Instant,
+};
+
+use reqwest::Client;
+
+use crate::{Cli, OutputStream};
+
+#[derive(clap::Args)]
+pub struct Args {
+	/// URL to the image page, image base, or item ID.
+	image:  String,
+	/// The format of the report.
+	#[clap(long, value_enum, default_value_t = ReportFormat::Human)]
+	format: ReportFormat,
+}
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+	/// Human-readable lines.
+	Human,
+	/// A JSON array of the steps.
+	Json,
+}
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+	Ok,
+	Failed,
+	Skipped,
+}
+
+/// The outcome of a diagnostic step.
+#[derive(Debug, Clone, serde::Serialize)]
+struct Step {
+	name:        &'static str,
+	status:      Status,
+	duration_ms: u128,
+	detail:      String,
+}
+
+/// The diagnostic steps taken so far.
+#[derive(Debug, Default)]
+struct Report {
+	steps: Vec<Step>,
+}
+
+impl Report {
+	/// Whether a step has failed, in which case the following steps are skipped.
+	fn failed(&self) -> Option<&Step> { self.steps.iter().find(|s| s.status == Status::Failed) }
+
+	/// Runs a step which resolves to its value and a description of it, and records its outcome.
+	async fn step<T, E: Display>(
+		&mut self,
+		name: &'static str,
+		step: impl Future<Output = Result<(T, String), E>>,
+	) -> Option<T> {
+		if self.failed().is_some() {
+			self.skip(name, "an earlier step failed");
+			return None;
+		}
+		let time_start = Instant::now();
+		let result = step.await;
+		let duration_ms = time_start.elapsed().as_millis();
+		let (status, detail, value) = match result {
+			Ok((value, detail)) => (Status::Ok, detail, Some(value)),
+			Err(e) => (Status::Failed, e.to_string(), None),
+		};
+		self.steps.push(Step {
+			name,
+			status,
+			duration_ms,
+			detail,
+		});
+		value
+	}
+
+	/// Records a step that wasn't run.
+	fn skip(&mut self, name: &'static str, reason: &str) {
+		self.steps.push(Step {
+			name,
+			status: Status::Skipped,
+			duration_ms: 0,
+			detail: reason.to_owned(),
+		});
+	}
+
+	fn write_human(&self, mut out: impl Write) -> std::io::Result<()> {
+		let name_width = self.steps.iter().map(|s| s.name.len()).max().unwrap_or(0);
+		for step in &self.steps {
+			let status = match step.status {
+				Status::Ok => "  OK  ",
+				Status::Failed => "FAILED",
+				Status::Skipped => " SKIP ",
+			};
+			writeln!(
+				out,
+				"[{status}] {:name_width$} {:>6}ms  {}",
+				step.name, step.duration_ms, step.detail
+			)?;
+		}
+		Ok(())
+	}
+}
+
+/// Runs the diagnostics and prints the report.
+pub async fn run(cli: &Cli, args: &Args, client: Arc<Client>) -> Result<(), Box<dyn Error>> {
+	let mut report = Report::default();
+
+	let input = report
+		.step("resolve input", async {
+			let Ok(input) = deathrip::Input::try_from(args.image.as_str());
+			let page_url = input.page_url(deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE);
+			let detail = match (&input, page_url) {
+				(deathrip::Input::ItemId(id), Some(page_url)) => {
+					format!("item ID {id}, page URL {page_url}")
+				}
+				(deathrip::Input::PageUrl(url), _) => format!("page URL {url}"),
+				(input, _) => format!("base URL {input}"),
+			};
+			Ok::<_, std::convert::Infallible>((input, detail))
+		})
+		.await;
+
+	let base_url = match input {
+		Some(deathrip::Input::BaseUrl(url)) => {
+			report.skip("fetch page", "the input is a base URL");
+			Some(url)
+		}
+		Some(input) => {
+			let page_url = input
+				.page_url(deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE)
+				.expect("only base URLs have no page");
+			fetch_page(&mut report, &client, &page_url).await
+		}
+		None => {
+			report.skip("fetch page", "an earlier step failed");
+			None
+		}
+	};
+	let source = base_url.map(|base_url| crate::tile_source(cli, base_url));
+	let source = source.as_ref();
+
+	report
+		.step("check base URL", async {
+			let source = source.expect("a step has failed if there is no base URL");
+			let response =
+				deathrip::send_request(client.head(source.rewrite(source.base_url.clone())))
+					.await
+					.and_then(|r| r.error_for_status())?;
+			Ok::<_, reqwest::Error>(((), format!("HTTP {}", response.status())))
+		})
+		.await;
+
+	report
+		.step("probe zoom level", async {
+			let source = source.expect("a step has failed if there is no base URL");
+			let url = source.tile_url(0, 0, deathrip::Zoom::LOWEST);
+			let response = deathrip::send_request(client.head(&url))
+				.await
+				.and_then(|r| r.error_for_status())?;
+			Ok::<_, reqwest::Error>(((), format!("HTTP {} for {url}", response.status())))
+		})
+		.await;
+
+	report
+		.step("fetch tile (0,0)", async {
+			let source = source.expect("a step has failed if there is no base URL");
+			let data =
+				deathrip::send_request(client.get(source.tile_url(0, 0, deathrip::Zoom::LOWEST)))
+					.await?
+					.error_for_status()?
+					.bytes()
+					.await?;
+			let tile = image::io::Reader::new(Cursor::new(&data))
+				.with_guessed_format()
+				.map_err(deathrip::Error::ImageFormatGuessError)?
+				.decode()?;
+			Ok::<_, deathrip::Error>((
+				(),
+				format!(
+					"{}\u{00D7}{} pixels, {} bytes",
+					tile.width(),
+					tile.height(),
+					data.len()
+				),
+			))
+		})
+		.await;
+
+	let mut output = OutputStream::lock();
+	match args.format {
+		ReportFormat::Human => report.write_human(output)?,
+		ReportFormat::Json => {
+			serde_json::to_writer_pretty(&mut output, &report.steps)?;
+			writeln!(output)?;
+		}
+	}
+
+	match report.failed() {
+		Some(step) => Err(format!("diagnostics failed at step \"{}\"", step.name).into()),
+		None => Ok(()),
+	}
+}
+
+/// Runs the page fetching step, resolving to the base URL of the image.
+async fn fetch_page(report: &mut Report, client: &Client, page_url: &str) -> Option<String> {
+	report
+		.step("fetch page", async {
+			let page = deathrip::Page::try_fetch(client, page_url).await?;
+			let detail = format!("title \"{}\", base URL {}", page.title, page.base_url);
+			Ok::<_, deathrip::PageError>((page.base_url, detail))
+		})
+		.await
+}
@@ -0,0 +1,143 @@
+//! Machine-readable progress events, see `--print-progress-to`.
+
+use std::{
+	io::Write,
+	sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+/// A progress event, written as a line of JSON.
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+	/// The input of the image, as given on the command line.
+	image:       &'a str,
+	/// `metadata` or `tiles`.
+	phase:       &'static str,
+	tiles_done:  usize,
+	tiles_total: usize,
+	bytes:       u64,
+	eta_ms:      Option<u128>,
+	/// The metadata value that was just determined, if any.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	zoom:        Option<deathrip::Zoom>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	columns:     Option<usize>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	rows:        Option<usize>,
+}
+
+/// The final event of an image, written as a line of JSON.
+#[derive(Debug, Serialize)]
+struct Done<'a> {
+	/// The input of the image, as given on the command line.
+	image: &'a str,
+	/// Always `done`.
+	phase: &'static str,
+	/// The error that failed the image, if any.
+	error: Option<&'a str>,
+}
+
+/// Writes progress events as newline-delimited JSON.
+#[derive(Clone)]
+pub struct EventWriter(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl EventWriter {
+	/// Opens the target, which is a file descriptor number (on Unix) or a file path.
+	pub fn open(target: &str) -> std::io::Result<Self> {
+		#[cfg(unix)]
+		if let Ok(fd) = target.parse::<std::os::fd::RawFd>() {
+			// The descriptor is duplicated rather than owned, so that dropping the writer doesn't close
+			// it, e.g. when it's the standard output or error.
+			// SAFETY: a file descriptor given on the command line is open for the process's lifetime.
+			let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+			let file = std::fs::File::from(fd);
+			return Ok(Self(Arc::new(Mutex::new(Box::new(file)))));
+		}
+		let file = std::fs::File::create(target)?;
+		Ok(Self(Arc::new(Mutex::new(Box::new(file)))))
+	}
+
+	fn write(&self, event: &impl Serialize) {
+		let mut writer = self.0.lock().unwrap();
+		let result = serde_json::to_writer(&mut *writer, event)
+			.map_err(std::io::Error::from)
+			.and_then(|_| writeln!(writer))
+			.and_then(|_| writer.flush());
+		if let Err(e) = result {
+			tracing::warn!("failed to write a progress event: {e}");
+		}
+	}
+
+	/// A progress callback for ripping the image of the given input.
+	pub fn callback(&self, image: &str) -> deathrip::ProgressCallback {
+		let writer = self.clone();
+		let image = image.to_owned();
+		Arc::new(move |progress: &deathrip::Progress| {
+			let (mut zoom, mut columns, mut rows) = (None, None, None);
+			match progress.detected {
+				Some(deathrip::Detection::Zoom(detected)) => zoom = Some(detected),
+				Some(deathrip::Detection::Columns(detected)) => columns = Some(detected),
+				Some(deathrip::Detection::Rows(detected)) => rows = Some(detected),
+				None => {}
+			}
+			writer.write(&Event {
+				image: &image,
+				phase: match progress.phase {
+					deathrip::Phase::Metadata => "metadata",
+					deathrip::Phase::Tiles => "tiles",
+				},
+				tiles_done: progress.tiles_done,
+				tiles_total: progress.tiles_total,
+				bytes: progress.bytes,
+				eta_ms: progress.eta.map(|eta| eta.as_millis()),
+				zoom,
+				columns,
+				rows,
+			})
+		})
+	}
+
+	/// Writes that the image of the given input is done, successfully or with the given error.
+	pub fn done(&self, image: &str, error: Option<&str>) {
+		self.write(&Done {
+			image,
+			phase: "done",
+			error,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read as _, Seek as _};
+
+	use super::*;
+
+	#[cfg(unix)]
+	#[test]
+	fn file_descriptor_stays_open() {
+		use std::os::fd::AsRawFd as _;
+		let path = std::env::temp_dir().join(format!("deathrip-events-{}", std::process::id()));
+		let mut file = std::fs::File::options()
+			.create(true)
+			.truncate(true)
+			.read(true)
+			.write(true)
+			.open(&path)
+			.unwrap();
+		let writer = EventWriter::open(&file.as_raw_fd().to_string()).unwrap();
+		writer.done("B-1", None);
+		drop(writer);
+		// The descriptor is still usable after the writer is dropped.
+		file.write_all(b"end\n").unwrap();
+		file.rewind().unwrap();
+		let mut written = String::new();
+		file.read_to_string(&mut written).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(
+			written,
+			"{\"image\":\"B-1\",\"phase\":\"done\",\"error\":null}\nend\n"
+		);
+	}
+}
@@ -0,0 +1,708 @@
+//! Output image formats.
+
+use std::{borrow::Cow, collections::HashSet};
+
+use image::{GenericImage, ImageFormat, ImageOutputFormat, Pixel};
+
+/// The specification of a supported output format.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub struct FormatSpec {
+	/// The name of the format.
+	pub name:            &'static str,
+	/// Alternative names of the format.
+	pub aliases:         &'static [&'static str],
+	/// The underlying image format.
+	pub image_format:    ImageFormat,
+	/// Whether the format is lossy.
+	pub lossy:           bool,
+	/// Whether the format supports a quality setting, see [`parse_format`].
+	pub quality:         bool,
+	/// The quality of the format when the quality is unspecified, if it supports a quality setting.
+	pub default_quality: Option<u8>,
+	/// Whether the format preserves 16 bits per channel.
+	pub deep:            bool,
+	/// Whether the format supports transparency.
+	pub alpha:           bool,
+}
+
+impl FormatSpec {
+	/// The file extension of the format.
+	pub fn extension(&self) -> &'static str { self.image_format.extensions_str()[0] }
+
+	/// The MIME type of the format, e.g. `image/png`.
+	pub fn mime_type(&self) -> &'static str { self.image_format.to_mime_type() }
+
+	/// The names of the format, starting with the main one and followed by the aliases.
+	pub fn names(&self) -> impl Iterator<Item = &'static str> {
+		std::iter::once(self.name).chain(self.aliases.iter().copied())
+	}
+
+	/// The output format with the given quality, if the format supports it, or else its
+	/// [default quality](FormatSpec::default_quality).
+	pub fn output_format(&self, quality: Option<u8>) -> ImageOutputFormat {
+		let quality = quality.or(self.default_quality).unwrap_or(100).min(100);
+		match self.image_format {
+			ImageFormat::Jpeg => ImageOutputFormat::Jpeg(quality),
+			image_format => image_format.into(),
+		}
+	}
+
+	/// Finds the specification of the output format.
+	pub fn of(format: &ImageOutputFormat) -> Option<&'static Self> {
+		supported_formats().iter().find(|spec| {
+			std::mem::discriminant(&spec.output_format(None)) == std::mem::discriminant(format)
+		})
+	}
+}
+
+/// The default quality of JPEG output, which is visually lossless for scans at a fraction of the
+/// size of the highest quality.
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// The supported output formats.
+const FORMATS: &[FormatSpec] = &[
+	FormatSpec {
+		name:            "png",
+		aliases:         &[],
+		image_format:    ImageFormat::Png,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            true,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "jpeg",
+		aliases:         &["jpg"],
+		image_format:    ImageFormat::Jpeg,
+		lossy:           true,
+		quality:         true,
+		default_quality: Some(DEFAULT_JPEG_QUALITY),
+		deep:            false,
+		alpha:           false,
+	},
+	FormatSpec {
+		name:            "bmp",
+		aliases:         &[],
+		image_format:    ImageFormat::Bmp,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            false,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "gif",
+		aliases:         &[],
+		image_format:    ImageFormat::Gif,
+		lossy:           true,
+		quality:         false,
+		default_quality: None,
+		deep:            false,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "tiff",
+		aliases:         &[],
+		image_format:    ImageFormat::Tiff,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            true,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "tga",
+		aliases:         &[],
+		image_format:    ImageFormat::Tga,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            false,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "ico",
+		aliases:         &[],
+		image_format:    ImageFormat::Ico,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            false,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "openexr",
+		aliases:         &["exr"],
+		image_format:    ImageFormat::OpenExr,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            false,
+		alpha:           true,
+	},
+	FormatSpec {
+		name:            "farbfeld",
+		aliases:         &[],
+		image_format:    ImageFormat::Farbfeld,
+		lossy:           false,
+		quality:         false,
+		default_quality: None,
+		deep:            false,
+		alpha:           true,
+	},
+];
+
+/// The supported output formats.
+pub fn supported_formats() -> &'static [FormatSpec] { FORMATS }
+
+/// Parses an output format by its name (case-insensitive), see [`supported_formats`].
+///
+/// Formats that support a [quality](FormatSpec::quality) may be suffixed with a number within
+/// [0,100] that controls it (higher is better), e.g. `jpeg80`, or else are of their
+/// [default quality](FormatSpec::default_quality), e.g. [`DEFAULT_JPEG_QUALITY`] for JPEG.
+pub fn parse_format(
+	format: &str,
+) -> Result<(&'static FormatSpec, ImageOutputFormat), &'static str> {
+	let format = format.to_ascii_lowercase();
+	for spec in supported_formats() {
+		for name in spec.names() {
+			if format == name {
+				return Ok((spec, spec.output_format(None)));
+			}
+			if let (true, Some(quality)) = (spec.quality, format.strip_prefix(name)) {
+				return match quality.parse::<u8>() {
+					Ok(quality) => Ok((spec, spec.output_format(Some(quality)))),
+					Err(_) => {
+						Err("couldn't parse the quality, it should be a number within [0,100]")
+					}
+				};
+			}
+		}
+	}
+	Err("unrecognized image output format")
+}
+
+/// Lists the supported output formats, e.g. for a help text, in the form `png | jpeg[<Q>] | ...`.
+pub fn formats_help() -> String {
+	supported_formats()
+		.iter()
+		.flat_map(|spec| {
+			let quality = if spec.quality { "[<Q>]" } else { "" };
+			spec.names().map(move |name| format!("{name}{quality}"))
+		})
+		.collect::<Vec<_>>()
+		.join(" | ")
+}
+
+/// Pads the image with transparency on its right and bottom edges to an even width and height,
+/// keeping its content at the top-left. The padding takes the background color when the image is
+/// [flattened](flatten) afterwards.
+pub fn pad_even(image: image::DynamicImage) -> image::DynamicImage {
+	let (width, height) = (image.width(), image.height());
+	let (even_width, even_height) = (width + width % 2, height + height % 2);
+	if (even_width, even_height) == (width, height) {
+		return image;
+	}
+	// The buffers are copied rather than the dynamic images, whose pixels are of 8 bits.
+	let fits = "the padded image contains the image";
+	if crate::is_deep(&image) {
+		let mut padded = image::ImageBuffer::new(even_width, even_height);
+		padded.copy_from(&image.to_rgba16(), 0, 0).expect(fits);
+		image::DynamicImage::ImageRgba16(padded)
+	} else {
+		let mut padded = image::ImageBuffer::new(even_width, even_height);
+		padded.copy_from(&image.to_rgba8(), 0, 0).expect(fits);
+		image::DynamicImage::ImageRgba8(padded)
+	}
+}
+
+/// Scales the image to the target dimensions with the filter, e.g. a higher zoom level down to a
+/// lower one. An image that's already of the target dimensions is copied as is rather than
+/// resampled, which isn't lossless for every filter.
+pub fn downscale<P: Pixel + 'static>(
+	image: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+	(width, height): (u32, u32),
+	filter: image::imageops::FilterType,
+) -> image::ImageBuffer<P, Vec<P::Subpixel>> {
+	if image.dimensions() == (width, height) {
+		return image.clone();
+	}
+	image::imageops::resize(image, width, height, filter)
+}
+
+/// Alpha-composites the image onto a solid background color, resolving to an opaque RGB image of
+/// the same depth (8 or 16 bits per channel).
+pub fn flatten(image: &image::DynamicImage, background: image::Rgb<u8>) -> image::DynamicImage {
+	if crate::is_deep(image) {
+		let image = image.to_rgba16();
+		let background = background.0.map(|channel| u32::from(channel) * 257);
+		image::DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(
+			image.width(),
+			image.height(),
+			|x, y| {
+				let [r, g, b, alpha] = image.get_pixel(x, y).0.map(u32::from);
+				let [r, g, b] = [(r, 0), (g, 1), (b, 2)].map(|(channel, i)| {
+					blend(channel, background[i], alpha, u16::MAX.into()) as u16
+				});
+				image::Rgb([r, g, b])
+			},
+		))
+	} else {
+		let image = match image.as_rgba8() {
+			Some(image) => Cow::Borrowed(image),
+			None => Cow::Owned(image.to_rgba8()),
+		};
+		let background = background.0.map(u32::from);
+		image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(
+			image.width(),
+			image.height(),
+			|x, y| {
+				let [r, g, b, alpha] = image.get_pixel(x, y).0.map(u32::from);
+				let [r, g, b] = [(r, 0), (g, 1), (b, 2)]
+					.map(|(channel, i)| blend(channel, background[i], alpha, u8::MAX.into()) as u8);
+				image::Rgb([r, g, b])
+			},
+		))
+	}
+}
+
+/// Blends a channel over the background channel by the given alpha, where `max` is the maximum
+/// value of the channels (fully opaque).
+fn blend(channel: u32, background: u32, alpha: u32, max: u32) -> u32 {
+	(channel * alpha + background * (max - alpha) + max / 2) / max
+}
+
+/// A PNG color type, see [`fit_png`].
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum PngColorType {
+	/// Grayscale.
+	Gray,
+	/// Grayscale with alpha.
+	GrayAlpha,
+	/// RGB.
+	Rgb,
+	/// RGB with alpha.
+	Rgba,
+}
+
+/// Converts the image to the given PNG color type, or, if `None`, to the narrowest color type and
+/// bit depth (8 or 16) that represent it losslessly.
+///
+/// The PNG encoder writes the color type of the image's buffer, so e.g. a grayscale scan in an RGBA
+/// buffer takes about four times the space it needs before compression. The bit depth is kept when
+/// the color type is given.
+pub fn fit_png(
+	image: image::DynamicImage,
+	color_type: Option<PngColorType>,
+) -> image::DynamicImage {
+	use image::DynamicImage as D;
+	let (color_type, deep) = match color_type {
+		Some(color_type) => (color_type, crate::is_deep(&image)),
+		None => narrowest_png(&image),
+	};
+	match (color_type, deep) {
+		(PngColorType::Gray, false) => D::ImageLuma8(image.to_luma8()),
+		(PngColorType::GrayAlpha, false) => D::ImageLumaA8(image.to_luma_alpha8()),
+		(PngColorType::Rgb, false) => D::ImageRgb8(image.to_rgb8()),
+		(PngColorType::Rgba, false) => D::ImageRgba8(image.to_rgba8()),
+		(PngColorType::Gray, true) => D::ImageLuma16(image.to_luma16()),
+		(PngColorType::GrayAlpha, true) => D::ImageLumaA16(image.to_luma_alpha16()),
+		(PngColorType::Rgb, true) => D::ImageRgb16(image.to_rgb16()),
+		(PngColorType::Rgba, true) => D::ImageRgba16(image.to_rgba16()),
+	}
+}
+
+/// Determines the narrowest PNG color type, and whether 16 bits per channel are needed, to
+/// represent the image losslessly.
+fn narrowest_png(image: &image::DynamicImage) -> (PngColorType, bool) {
+	let (gray, opaque, deep) = if crate::is_deep(image) {
+		let image = match image.as_rgba16() {
+			Some(image) => Cow::Borrowed(image),
+			None => Cow::Owned(image.to_rgba16()),
+		};
+		let (mut gray, mut opaque, mut deep) = (true, true, false);
+		for &image::Rgba([r, g, b, a]) in image.pixels() {
+			gray &= r == g && g == b;
+			opaque &= a == u16::MAX;
+			// A 16-bit channel is representable in 8 bits if it's a multiple of 257 (0x0101).
+			deep |= [r, g, b, a].iter().any(|channel| channel % 257 != 0);
+			if !gray && !opaque && deep {
+				break;
+			}
+		}
+		(gray, opaque, deep)
+	} else {
+		let image = match image.as_rgba8() {
+			Some(image) => Cow::Borrowed(image),
+			None => Cow::Owned(image.to_rgba8()),
+		};
+		let (mut gray, mut opaque) = (true, true);
+		for &image::Rgba([r, g, b, a]) in image.pixels() {
+			gray &= r == g && g == b;
+			opaque &= a == u8::MAX;
+			if !gray && !opaque {
+				break;
+			}
+		}
+		(gray, opaque, false)
+	};
+	let color_type = match (gray, opaque) {
+		(true, true) => PngColorType::Gray,
+		(true, false) => PngColorType::GrayAlpha,
+		(false, true) => PngColorType::Rgb,
+		(false, false) => PngColorType::Rgba,
+	};
+	(color_type, deep)
+}
+
+/// The JPEG quality [`infer_output_format`] picks for photo-like images.
+pub const INFERRED_JPEG_QUALITY: u8 = DEFAULT_JPEG_QUALITY;
+
+/// The maximum amount of pixels [`infer_output_format`] samples.
+const INFERENCE_SAMPLES: u64 = 1 << 20;
+
+/// Picks a suitable output format for the image: lossless for line-art and text, lossy for photos.
+///
+/// The heuristic samples the image's pixels and picks PNG if any of the following holds, or JPEG
+/// (of quality [`INFERRED_JPEG_QUALITY`]) otherwise:
+/// - The image is deeper than 8 bits per channel, or has transparency, which JPEG doesn't support.
+/// - The image has at most 256 distinct colors (with 5 bits per channel), as is typical of
+///   line-art, which is a good fit for lossless compression and suffers from JPEG artifacts.
+/// - Sharp edges (neighboring samples whose luminance differs by more than 64) are more common than
+///   smooth gradients (a difference of 1 to 16), as is typical of text.
+pub fn infer_output_format(image: &image::DynamicImage) -> ImageOutputFormat {
+	if crate::is_deep(image) {
+		return ImageOutputFormat::Png;
+	}
+	let image = match image.as_rgba8() {
+		Some(image) => Cow::Borrowed(image),
+		None => Cow::Owned(image.to_rgba8()),
+	};
+	let (width, height) = image.dimensions();
+	let pixels = width as u64 * height as u64;
+	if pixels == 0 {
+		return ImageOutputFormat::Png;
+	}
+	// Sample every `stride` rows and columns so that at most `INFERENCE_SAMPLES` are taken.
+	let stride = ((pixels as f64 / INFERENCE_SAMPLES as f64).sqrt().ceil() as u32).max(1);
+
+	let mut colors = HashSet::new();
+	let (mut sharp, mut smooth) = (0usize, 0usize);
+	for y in (0..height).step_by(stride as usize) {
+		let mut previous_luma: Option<u8> = None;
+		for x in (0..width).step_by(stride as usize) {
+			let pixel = image.get_pixel(x, y);
+			if pixel[3] != u8::MAX {
+				return ImageOutputFormat::Png;
+			}
+			colors.insert([pixel[0] >> 3, pixel[1] >> 3, pixel[2] >> 3]);
+			let luma = pixel.to_luma()[0];
+			if let Some(previous_luma) = previous_luma {
+				match luma.abs_diff(previous_luma) {
+					0 => {}
+					1..=16 => smooth += 1,
+					65.. => sharp += 1,
+					_ => {}
+				}
+			}
+			previous_luma = Some(luma);
+		}
+	}
+
+	if colors.len() <= 256 || sharp > smooth {
+		ImageOutputFormat::Png
+	} else {
+		ImageOutputFormat::Jpeg(INFERRED_JPEG_QUALITY)
+	}
+}
+
+/// Writes the images as the frames of an animated PNG (APNG) that loops forever, each shown for the
+/// given duration.
+///
+/// The frames are placed at the top-left corner of the largest frame's dimensions, so frames of
+/// differing dimensions are padded with transparency.
+///
+/// Fails with an [`InvalidInput`](std::io::ErrorKind::InvalidInput) I/O error, writing nothing, if
+/// there are no frames, since an APNG has at least one.
+pub fn write_apng(
+	writer: impl std::io::Write,
+	frames: &[image::DynamicImage],
+	frame_duration: std::time::Duration,
+) -> Result<(), png::EncodingError> {
+	if frames.is_empty() {
+		return Err(png::EncodingError::IoError(std::io::Error::new(
+			std::io::ErrorKind::InvalidInput,
+			"an animation needs at least one frame",
+		)));
+	}
+	let width = frames
+		.iter()
+		.map(image::DynamicImage::width)
+		.max()
+		.unwrap_or(1);
+	let height = frames
+		.iter()
+		.map(image::DynamicImage::height)
+		.max()
+		.unwrap_or(1);
+	let mut encoder = png::Encoder::new(writer, width, height);
+	encoder.set_color(png::ColorType::Rgba);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.set_animated(frames.len() as u32, 0)?;
+	// The delay is in milliseconds, clamped to what fits in the frame control chunk.
+	let delay = frame_duration.as_millis().min(u16::MAX.into()) as u16;
+	encoder.set_frame_delay(delay, 1000)?;
+	let mut png_writer = encoder.write_header()?;
+	for frame in frames {
+		let mut canvas = image::RgbaImage::new(width, height);
+		image::imageops::overlay(&mut canvas, &frame.to_rgba8(), 0, 0);
+		png_writer.write_image_data(&canvas)?;
+	}
+	png_writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use image::imageops::FilterType;
+
+	use super::*;
+
+	const FILTERS: [FilterType; 5] = [
+		FilterType::Nearest,
+		FilterType::Triangle,
+		FilterType::CatmullRom,
+		FilterType::Gaussian,
+		FilterType::Lanczos3,
+	];
+
+	#[test]
+	fn downscale_dimensions() {
+		let image = image::GrayImage::from_fn(64, 48, |x, y| image::Luma([(x * 3 + y) as u8]));
+		for filter in FILTERS {
+			assert_eq!(
+				downscale(&image, (20, 15), filter).dimensions(),
+				(20, 15),
+				"{filter:?}"
+			);
+			assert_eq!(
+				downscale(&image, (1, 1), filter).dimensions(),
+				(1, 1),
+				"{filter:?}"
+			);
+			// The target size keeps the image as is.
+			assert_eq!(downscale(&image, (64, 48), filter), image, "{filter:?}");
+		}
+	}
+
+	#[test]
+	fn apng_of_no_frames_fails() {
+		let mut data = Vec::new();
+		let result = write_apng(&mut data, &[], std::time::Duration::from_millis(500));
+		assert!(
+			matches!(&result, Err(png::EncodingError::IoError(e)) if e.kind() == std::io::ErrorKind::InvalidInput),
+			"{result:?}"
+		);
+		assert!(data.is_empty());
+	}
+
+	#[test]
+	fn apng_frames() {
+		let red = image::Rgba([255, 0, 0, 255]);
+		let blue = image::Rgba([0, 0, 255, 255]);
+		let frames = [
+			image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 2, red)),
+			image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(2, 3, blue)),
+		];
+		let mut data = Vec::new();
+		write_apng(&mut data, &frames, std::time::Duration::from_millis(500)).unwrap();
+
+		let mut reader = png::Decoder::new(data.as_slice()).read_info().unwrap();
+		let info = reader.info();
+		assert_eq!((info.width, info.height), (4, 3));
+		let control = info.animation_control().unwrap();
+		assert_eq!((control.num_frames, control.num_plays), (2, 0));
+		let mut buffer = vec![0; reader.output_buffer_size()];
+		let mut decoded = Vec::new();
+		for _ in 0..2 {
+			reader.next_frame(&mut buffer).unwrap();
+			let frame = reader.info().frame_control().unwrap();
+			assert_eq!((frame.delay_num, frame.delay_den), (500, 1000));
+			decoded.push(image::RgbaImage::from_raw(4, 3, buffer.clone()).unwrap());
+		}
+		// The smaller frames are padded with transparency.
+		assert_eq!(*decoded[0].get_pixel(0, 0), red);
+		assert_eq!(decoded[0].get_pixel(0, 2).0[3], 0);
+		assert_eq!(*decoded[1].get_pixel(1, 2), blue);
+		assert_eq!(decoded[1].get_pixel(3, 0).0[3], 0);
+	}
+
+	#[test]
+	fn flatten_blends_over_the_background() {
+		let white = image::Rgb([255; 3]);
+		// Opaque, half transparent black, and fully transparent.
+		let image =
+			image::RgbaImage::from_raw(3, 1, vec![200, 100, 50, 255, 0, 0, 0, 128, 10, 20, 30, 0])
+				.unwrap();
+		let flat = flatten(&image::DynamicImage::ImageRgba8(image), white);
+		let flat = flat.as_rgb8().unwrap();
+		let pixels = flat.pixels().map(|pixel| pixel.0).collect::<Vec<_>>();
+		assert_eq!(pixels, [[200, 100, 50], [127, 127, 127], [255, 255, 255]]);
+
+		// A deep image keeps its depth, and the precision of its samples.
+		let image = image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(
+			3,
+			1,
+			vec![0x1234, 0x5678, 0x9abc, 0xffff, 0, 0, 0, 0x8000, 1, 2, 3, 0],
+		)
+		.unwrap();
+		let flat = flatten(&image::DynamicImage::ImageRgba16(image), white);
+		let flat = flat.as_rgb16().unwrap();
+		let pixels = flat.pixels().map(|pixel| pixel.0).collect::<Vec<_>>();
+		assert_eq!(pixels, [[0x1234, 0x5678, 0x9abc], [0x7fff; 3], [0xffff; 3]]);
+	}
+
+	#[test]
+	fn blend_rounds() {
+		assert_eq!(blend(0, 255, 128, 255), 127);
+		assert_eq!(blend(255, 0, 128, 255), 128);
+		assert_eq!(blend(0, 65535, 0x8000, 65535), 0x7fff);
+		assert_eq!(blend(65535, 0, 0x8000, 65535), 0x8000);
+		assert_eq!(blend(77, 255, 255, 255), 77);
+		assert_eq!(blend(77, 255, 0, 255), 255);
+	}
+
+	#[test]
+	fn pad_even_keeps_the_image_top_left() {
+		let red = image::Rgba([255, 0, 0, 255]);
+		for (width, height) in [(3, 2), (2, 3), (3, 3), (1, 1)] {
+			let image =
+				image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, red));
+			let padded = pad_even(image).into_rgba8();
+			let (even_width, even_height) = (width + width % 2, height + height % 2);
+			assert_eq!(padded.dimensions(), (even_width, even_height));
+			for (x, y, pixel) in padded.enumerate_pixels() {
+				let expected = match x < width && y < height {
+					true => red,
+					false => image::Rgba([0; 4]),
+				};
+				assert_eq!(*pixel, expected, "({x},{y}) of {width}\u{00D7}{height}");
+			}
+		}
+
+		// An image of even dimensions is kept as is.
+		let image = image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 2));
+		assert_eq!(pad_even(image.clone()), image);
+
+		// A deep image is padded in 16 bits.
+		let deep = image::Rgba([0x1234, 0x5678, 0x9abc, 0xffff]);
+		let image = image::DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(1, 1, deep));
+		let padded = pad_even(image);
+		let padded = padded.as_rgba16().unwrap();
+		assert_eq!(padded.dimensions(), (2, 2));
+		assert_eq!(*padded.get_pixel(0, 0), deep);
+		assert_eq!(padded.get_pixel(1, 1).0[3], 0);
+	}
+
+	#[test]
+	fn infers_photos_and_text() {
+		let infer = |image: image::RgbaImage| infer_output_format(&image.into());
+		// A smooth gradient of many colors, like a photo.
+		let photo = image::RgbaImage::from_fn(256, 256, |x, y| {
+			image::Rgba([x as u8, y as u8, ((x + y) / 2) as u8, 255])
+		});
+		assert_eq!(
+			infer(photo.clone()),
+			ImageOutputFormat::Jpeg(INFERRED_JPEG_QUALITY)
+		);
+		// Black text on white, of few colors.
+		let text = image::RgbaImage::from_fn(256, 256, |x, y| match (x / 3 + y / 5) % 4 {
+			0 => image::Rgba([0, 0, 0, 255]),
+			_ => image::Rgba([255; 4]),
+		});
+		assert_eq!(infer(text), ImageOutputFormat::Png);
+		// Sharp edges between many colors, like colored text.
+		let edges = image::RgbaImage::from_fn(256, 256, |x, y| {
+			let (r, g, b) = ((x % 64) as u8, (y % 64) as u8, ((x + y) % 64) as u8);
+			match x % 2 {
+				0 => image::Rgba([r, g, b, 255]),
+				_ => image::Rgba([192 + r, 192 + g, 192 + b, 255]),
+			}
+		});
+		assert_eq!(infer(edges), ImageOutputFormat::Png);
+		// A photo with transparency, which JPEG doesn't support.
+		let mut transparent = photo;
+		transparent.get_pixel_mut(100, 100).0[3] = 0;
+		assert_eq!(infer(transparent), ImageOutputFormat::Png);
+	}
+
+	#[test]
+	fn fit_png_is_narrowest_and_lossless() {
+		use image::{ColorType, DynamicImage as D};
+		let rgba8 =
+			|pixel: [u8; 4]| D::ImageRgba8(image::RgbaImage::from_pixel(3, 2, image::Rgba(pixel)));
+		let rgba16 = |pixel: [u16; 4]| {
+			D::ImageRgba16(image::ImageBuffer::from_pixel(3, 2, image::Rgba(pixel)))
+		};
+		let cases = [
+			(rgba8([7, 7, 7, 255]), ColorType::L8),
+			(rgba8([7, 7, 7, 100]), ColorType::La8),
+			(rgba8([7, 8, 9, 255]), ColorType::Rgb8),
+			(rgba8([7, 8, 9, 100]), ColorType::Rgba8),
+			// 16-bit samples that are representable in 8 bits.
+			(rgba16([7 * 257, 8 * 257, 9 * 257, 65535]), ColorType::Rgb8),
+			(rgba16([7000, 7000, 7000, 65535]), ColorType::L16),
+			(rgba16([7000, 7000, 7000, 100]), ColorType::La16),
+			(rgba16([7000, 8000, 9000, 65535]), ColorType::Rgb16),
+			(rgba16([7000, 8000, 9000, 100]), ColorType::Rgba16),
+		];
+		for (image, color) in cases {
+			let fit = fit_png(image.clone(), None);
+			assert_eq!(fit.color(), color, "{:?}", image.color());
+			let mut data = Vec::new();
+			fit.write_to(&mut std::io::Cursor::new(&mut data), ImageOutputFormat::Png)
+				.unwrap();
+			let decoded = image::load_from_memory(&data).unwrap();
+			assert_eq!(decoded.color(), color);
+			assert_eq!(decoded.to_rgba16(), image.to_rgba16(), "{color:?}");
+		}
+
+		// A given color type keeps the depth.
+		let fit = fit_png(
+			rgba16([7 * 257, 8 * 257, 9 * 257, 65535]),
+			Some(PngColorType::Rgba),
+		);
+		assert_eq!(fit.color(), ColorType::Rgba16);
+		let fit = fit_png(rgba8([7, 8, 9, 255]), Some(PngColorType::Rgba));
+		assert_eq!(fit.color(), ColorType::Rgba8);
+	}
+
+	#[test]
+	fn formats_help_matches_parsing() {
+		let help = formats_help();
+		let mut listed = Vec::new();
+		for option in help.split(" | ") {
+			let (name, quality) = match option.strip_suffix("[<Q>]") {
+				Some(name) => (name, true),
+				None => (option, false),
+			};
+			let (spec, format) = parse_format(name).unwrap();
+			assert!(spec.names().any(|spec_name| spec_name == name), "{name}");
+			assert_eq!(format, spec.output_format(None));
+			assert_eq!(spec.quality, quality, "{name}");
+			if quality {
+				let (_, format) = parse_format(&format!("{name}80")).unwrap();
+				assert_eq!(format, spec.output_format(Some(80)));
+			} else {
+				assert!(parse_format(&format!("{name}80")).is_err(), "{name}");
+			}
+			listed.push(name);
+		}
+		let names = supported_formats()
+			.iter()
+			.flat_map(FormatSpec::names)
+			.collect::<Vec<_>>();
+		assert_eq!(listed, names);
+		assert!(parse_format("webp").is_err());
+	}
+}
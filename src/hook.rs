@@ -0,0 +1,169 @@
+//! The `--on-tile-fetched` hook: running a command on each tile as soon as it's fetched.
+
+use std::{
+	path::Path,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex,
+	},
+};
+
+use tokio::{sync::Semaphore, task::JoinHandle};
+
+/// Runs a command on each tile of an image, a bounded amount at a time.
+///
+/// Each tile is written to a temporary file for the command when its turn comes, which is removed
+/// once the command exits. The command gets the tile in the environment variables `DEATHRIP_IMAGE` (the image as
+/// given on the command line), `DEATHRIP_TILE_X`, `DEATHRIP_TILE_Y`, and `DEATHRIP_TILE_PATH`.
+pub struct TileHook {
+	command:   String,
+	image:     String,
+	/// Distinguishes the temporary files of this hook's image from those of other images.
+	id:        usize,
+	semaphore: Semaphore,
+	tasks:     Mutex<Vec<JoinHandle<bool>>>,
+}
+
+impl TileHook {
+	/// A hook running the shell command on the tiles of the image, at most the given amount at a
+	/// time.
+	pub fn new(command: &str, image: &str, max_running: usize) -> Arc<Self> {
+		static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+		Arc::new(Self {
+			command:   command.to_owned(),
+			image:     image.to_owned(),
+			id:        NEXT_ID.fetch_add(1, Ordering::SeqCst),
+			semaphore: Semaphore::new(max_running.max(1)),
+			tasks:     Mutex::new(Vec::new()),
+		})
+	}
+
+	/// The tile callback that runs the hook, see [`deathrip::RipOptions::on_tile`].
+	pub fn callback(self: &Arc<Self>) -> deathrip::TileCallback {
+		let hook = Arc::clone(self);
+		Arc::new(move |tile| hook.spawn(tile.position, tile.data))
+	}
+
+	/// Runs the hook on the tile once there's room for it, writing it to its temporary file only
+	/// then, so that the tiles waiting for their turn don't pile up on the disk.
+	fn spawn(self: &Arc<Self>, (x, y): (usize, usize), data: &bytes::Bytes) {
+		let extension =
+			image::guess_format(data).map_or("bin", |format| format.extensions_str()[0]);
+		let path = std::env::temp_dir().join(format!(
+			"deathrip-{}-{}-{x}_{y}.{extension}",
+			std::process::id(),
+			self.id
+		));
+		let (hook, data) = (Arc::clone(self), data.clone());
+		let task = tokio::spawn(async move {
+			let permit = hook.semaphore.acquire().await;
+			if let Err(e) = tokio::fs::write(&path, data).await {
+				tracing::warn!("failed to write tile ({x},{y}) for the hook: {e}");
+				return false;
+			}
+			let status = hook.command((x, y), &path).status().await;
+			if let Err(e) = tokio::fs::remove_file(&path).await {
+				tracing::debug!("failed to remove {}: {e}", path.display());
+			}
+			drop(permit);
+			match status {
+				Ok(status) if status.success() => true,
+				Ok(status) => {
+					tracing::warn!("the hook of tile ({x},{y}) failed with {status}");
+					false
+				}
+				Err(e) => {
+					tracing::warn!("failed to run the hook of tile ({x},{y}): {e}");
+					false
+				}
+			}
+		});
+		self.tasks.lock().unwrap().push(task);
+	}
+
+	/// The shell command for the tile at the position, whose data is at the path.
+	fn command(&self, (x, y): (usize, usize), path: &Path) -> tokio::process::Command {
+		#[cfg(windows)]
+		let mut command = {
+			let mut command = tokio::process::Command::new("cmd");
+			command.arg("/C").arg(&self.command);
+			command
+		};
+		#[cfg(not(windows))]
+		let mut command = {
+			let mut command = tokio::process::Command::new("sh");
+			command.arg("-c").arg(&self.command);
+			command
+		};
+		command
+			.env("DEATHRIP_IMAGE", &self.image)
+			.env("DEATHRIP_TILE_X", x.to_string())
+			.env("DEATHRIP_TILE_Y", y.to_string())
+			.env("DEATHRIP_TILE_PATH", path)
+			.stdin(std::process::Stdio::null())
+			// The standard output may be the image.
+			.stdout(std::io::stderr());
+		command
+	}
+
+	/// Waits for the hooks of all the tiles so far to finish, failing if any of them failed.
+	pub async fn finish(&self) -> Result<(), String> {
+		let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+		let mut failures = 0;
+		for task in tasks {
+			if !task.await.unwrap_or(false) {
+				failures += 1;
+			}
+		}
+		match failures {
+			0 => Ok(()),
+			failures => Err(format!("the tile hook failed on {failures} tiles")),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn runs_on_each_tile_a_bounded_amount_at_a_time() {
+		let out = std::env::temp_dir().join(format!("deathrip-hook-{}", std::process::id()));
+		let _ = std::fs::remove_file(&out);
+		// Records each tile, and how many of the hook's temporary files exist while it runs.
+		let command = format!(
+			"test -s \"$DEATHRIP_TILE_PATH\" && ls \"$(dirname \"$DEATHRIP_TILE_PATH\")\" | grep -c \
+			 \"^deathrip-$PPID-\" | sed \"s/^/$DEATHRIP_TILE_X,$DEATHRIP_TILE_Y /\" >> {}",
+			out.display()
+		);
+		let hook = TileHook::new(&command, "B-1", 2);
+		let callback = hook.callback();
+		let data = bytes::Bytes::from_static(b"tile");
+		let tiles = (0..4).flat_map(|y| (0..3).map(move |x| (x, y)));
+		for position in tiles.clone() {
+			callback(&deathrip::FetchedTile {
+				position,
+				zoom: deathrip::Zoom::new(0).unwrap(),
+				url: "https://example.com/tile",
+				status: None,
+				duration: std::time::Duration::ZERO,
+				data: &data,
+			});
+		}
+		hook.finish().await.unwrap();
+		let records = std::fs::read_to_string(&out).unwrap();
+		std::fs::remove_file(&out).unwrap();
+		let mut seen = Vec::new();
+		for record in records.lines() {
+			let (position, files) = record.split_once(' ').unwrap();
+			let files = files.parse::<usize>().unwrap();
+			assert!((1..=2).contains(&files), "{files} temporary files at once");
+			seen.push(position.to_owned());
+		}
+		seen.sort();
+		let mut expected = tiles.map(|(x, y)| format!("{x},{y}")).collect::<Vec<_>>();
+		expected.sort();
+		assert_eq!(seen, expected);
+	}
+}
@@ -0,0 +1,109 @@
+//! The `--iiif-manifest` sidecar: a IIIF Presentation API 3.0 manifest of the image, to publish it
+//! to IIIF viewers.
+
+use std::path::{Path, PathBuf};
+
+/// The JSON-LD context of the Presentation API 3.0.
+const CONTEXT: &str = "http://iiif.io/api/presentation/3/context.json";
+
+/// The URL of the file of the given name under the base URL (a directory URL), failing if the base
+/// URL can't have files under it, e.g. `mailto:` URLs.
+fn url_of(base_url: &reqwest::Url, name: &str) -> Result<String, &'static str> {
+	let mut url = base_url.clone();
+	url.path_segments_mut()
+		.map_err(|()| {
+			"the IIIF base URL should be an absolute URL, e.g. https://example.com/scrolls/"
+		})?
+		.pop_if_empty()
+		.push(name);
+	Ok(url.into())
+}
+
+/// Writes a manifest of a single canvas painted with the image file to a sidecar
+/// `<file>.iiif.json` next to it, for both to be served from the base URL, resolving to the
+/// sidecar's path.
+pub fn write_beside(
+	path: &Path,
+	(width, height): (u32, u32),
+	base_url: &reqwest::Url,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+	let format = image::ImageFormat::from_path(path)
+		.map_err(|_| "the IIIF manifest supports only image files")?
+		.to_mime_type();
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	let label = path.file_stem().unwrap_or_default().to_string_lossy();
+	let manifest_name = format!("{file_name}.iiif.json");
+	let id = url_of(base_url, &manifest_name)?;
+	let canvas = format!("{id}/canvas/1");
+	let manifest = serde_json::json!({
+		"@context": CONTEXT,
+		"id": id,
+		"type": "Manifest",
+		"label": { "none": [label] },
+		"items": [{
+			"id": canvas,
+			"type": "Canvas",
+			"width": width,
+			"height": height,
+			"items": [{
+				"id": format!("{id}/page/1"),
+				"type": "AnnotationPage",
+				"items": [{
+					"id": format!("{id}/annotation/1"),
+					"type": "Annotation",
+					"motivation": "painting",
+					"body": {
+						"id": url_of(base_url, &file_name)?,
+						"type": "Image",
+						"format": format,
+						"width": width,
+						"height": height,
+					},
+					"target": canvas,
+				}],
+			}],
+		}],
+	});
+	let manifest_path = path.with_file_name(manifest_name);
+	let file = std::fs::File::create(&manifest_path)?;
+	serde_json::to_writer_pretty(std::io::BufWriter::new(file), &manifest)?;
+	Ok(manifest_path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::TempDir;
+
+	#[test]
+	fn manifest_of_image() {
+		let dir = TempDir::new("iiif");
+		let base_url = reqwest::Url::parse("https://example.com/scrolls/").unwrap();
+		let path = write_beside(&dir.join("scroll.png"), (640, 480), &base_url).unwrap();
+		assert_eq!(path, dir.join("scroll.png.iiif.json"));
+		let manifest: serde_json::Value =
+			serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+		assert_eq!(manifest["@context"], CONTEXT);
+		assert_eq!(
+			manifest["id"],
+			"https://example.com/scrolls/scroll.png.iiif.json"
+		);
+		assert_eq!(manifest["type"], "Manifest");
+		let canvas = &manifest["items"][0];
+		assert_eq!([&canvas["width"], &canvas["height"]], [640, 480]);
+		let body = &canvas["items"][0]["items"][0]["body"];
+		assert_eq!(body["id"], "https://example.com/scrolls/scroll.png");
+		assert_eq!(body["type"], "Image");
+		assert_eq!(body["format"], "image/png");
+		assert_eq!([&body["width"], &body["height"]], [640, 480]);
+	}
+
+	#[test]
+	fn base_url_that_cant_be_a_base_fails() {
+		assert!(crate::parse_base_url("mailto:x").is_err());
+		let dir = TempDir::new("iiif-mailto");
+		let base_url = reqwest::Url::parse("mailto:x").unwrap();
+		assert!(write_beside(&dir.join("scroll.png"), (1, 1), &base_url).is_err());
+		assert!(!dir.join("scroll.png.iiif.json").exists());
+	}
+}
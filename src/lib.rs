@@ -1,4 +1,25 @@
+//! Rips full-resolution images from the Dead Sea Scrolls digital library.
+//!
+//! # Cancellation
+//!
+//! The futures of this crate are cancel-safe: dropping one (e.g. when it loses a
+//! [`select!`](tokio::select) or exceeds a [`timeout`](tokio::time::timeout)) aborts all of its
+//! in-flight work, including the tasks it spawned, and leaves no work running in the background.
+//! Note that aborted tasks stop at their next `.await`, so requests that were already sent may
+//! still complete on the server's end, and tiles that were fetched may already be in the
+//! [tile store](RipOptions::tile_store).
+
+mod format;
+mod linked_data;
+#[cfg(test)]
+mod mock;
+mod orientation;
+mod progress;
+mod retry;
+mod store;
+mod throttle;
 mod util;
+mod zooms;
 
 use std::{
 	convert::Infallible,
@@ -6,15 +27,34 @@ use std::{
 	io::Cursor,
 	sync::{
 		atomic::{self, AtomicUsize},
-		Arc,
+		Arc, LazyLock,
 	},
+	time::{Duration, Instant},
 };
 
+use futures::{StreamExt as _, TryFutureExt as _, TryStreamExt as _};
 use image::{GenericImage, GenericImageView};
 use itertools::Itertools as _;
+use progress::TileProgress;
 use reqwest::Client;
-use tokio::sync::{Mutex, RwLock};
-use util::StringMutTail;
+use retry::RetryBudget;
+use tokio::sync::{Mutex, Semaphore};
+use util::{InFlight, StringMutTail};
+
+pub use util::{send_request, ByteSize, REQUEST_TARGET};
+
+pub use format::{
+	downscale, fit_png, flatten, formats_help, infer_output_format, pad_even, parse_format,
+	supported_formats, write_apng, FormatSpec, PngColorType, DEFAULT_JPEG_QUALITY,
+	INFERRED_JPEG_QUALITY,
+};
+pub use progress::{progress_stream, Detection, Phase, Progress, ProgressCallback};
+pub use retry::{classify, RetryClassifier, RetryDecision, RetryPolicy};
+pub use store::{DiskStore, MemoryStore, TileDir, TileStore};
+pub use throttle::BandwidthLimit;
+pub use zooms::{
+	enumerate_zooms, pick_knee_zoom, ParseZoomError, Zoom, ZoomLevel, DEFAULT_KNEE_THRESHOLD,
+};
 
 /// Input to the main operation, i.e. reference to the desired image.
 #[derive(Debug, Hash, Clone, PartialEq, PartialOrd, Eq, Ord)]
@@ -56,6 +96,7 @@ impl Display for Input {
 /// Attempts to infer the type of input.
 ///
 /// Currently always succeeds with [`Input::ItemId`](Input::ItemId) as fallback, but may change later.
+#[allow(clippy::infallible_try_from)]
 impl TryFrom<&str> for Input {
 	type Error = Infallible;
 
@@ -71,20 +112,214 @@ impl TryFrom<&str> for Input {
 	}
 }
 
-/// Determines the limit of an axis for the image.
-///
-/// - The `base` parameter is the base URL of the image along with `=` and XYZ parameters (see section below), but with the
-/// target axis parameter last and without a value (e.g. end with `=x0-y0-z` to target the Z axis).
-/// - The `num_workers` is the amount of simultaneous requests that will be made.
-///
-/// ## Base URL
+impl Input {
+	/// The template of the URL of an item's page, where `{id}` stands for the item ID.
+	pub const DEFAULT_PAGE_URL_TEMPLATE: &'static str =
+		"https://www.deadseascrolls.org.il/explore-the-archive/image/{id}";
+
+	/// The URL of the page to fetch for the image, if any (i.e. unless it's a base URL), where
+	/// `{id}` in the template stands for the item ID.
+	pub fn page_url(&self, page_url_template: &str) -> Option<String> {
+		match self {
+			Input::BaseUrl(_) => None,
+			Input::PageUrl(url) => Some(url.clone()),
+			Input::ItemId(id) => Some(page_url_template.replace("{id}", id)),
+		}
+	}
+
+	/// The canonical URL of the input: the URL of its [page](Input::page_url), or the base URL for
+	/// a base URL.
+	///
+	/// With the [default template](Input::DEFAULT_PAGE_URL_TEMPLATE), the URL of an item ID or of an
+	/// archive page parses back to the page URL, and a base URL on `ggpht.com` back to itself. Other
+	/// URLs (e.g. of another template, or of a base URL elsewhere) parse back as item IDs.
+	pub fn to_url(&self, page_url_template: &str) -> String {
+		self.page_url(page_url_template)
+			.unwrap_or_else(|| self.as_ref().to_owned())
+	}
+}
+
+/// Infers the type of input, like [`Input::try_from`].
+impl std::str::FromStr for Input {
+	type Err = Infallible;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> { Self::try_from(s) }
+}
+
+/// An [`Input`] resolved to its image, see [`resolve_input`].
+#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+pub struct ResolvedImage {
+	/// The base URL of the image, see [`Input::BaseUrl`].
+	pub base_url: String,
+	/// The title of the image's page, if the page was fetched.
+	pub title:    Option<String>,
+}
+
+/// Resolves the input to its image, fetching its page unless the input is a base URL.
 ///
-/// The base URL for this function is not the same as the base for [`rip`](rip).
-/// This one requires partial parameterization.
+/// The `page_url_template` is the URL of an item's page, where `{id}` stands for the item ID, see
+/// [`Input::DEFAULT_PAGE_URL_TEMPLATE`].
+pub async fn resolve_input(
+	client: &Client,
+	input: Input,
+	page_url_template: &str,
+) -> Result<ResolvedImage, PageError> {
+	let Some(page_url) = input.page_url(page_url_template) else {
+		return Ok(ResolvedImage {
+			base_url: input.as_ref().to_owned(),
+			title:    None,
+		});
+	};
+	tracing::debug!("fetching the page {page_url}");
+	let page = Page::try_fetch(client, &page_url).await?;
+	Ok(ResolvedImage {
+		base_url: page.base_url,
+		title:    Some(page.title),
+	})
+}
+
+/// The source of an image's tiles: its base URL and the format of the tile parameters.
 ///
-/// The image base URL is appended with `=` and X, Y, and Z values in the format:
+/// The tile URLs are the base URL appended with the [prefix](TileSource::prefix) and X, Y, and Z
+/// values delimited by the [separator](TileSource::separator), e.g. with the defaults:
 /// `=x<X>-y<Y>-z<Z>`. The order of the axes is insignificant.
-/// X and Y refer to position and Z refers to the resolution.
+/// X and Y refer to position and Z refers to the resolution. X is the column and Y is the row,
+/// unless the source is [transposed](TileSource::transposed).
+#[derive(Clone)]
+pub struct TileSource {
+	/// The base URL of the image, see [`Input::BaseUrl`].
+	pub base_url:         String,
+	/// The string that separates the base URL from the parameters.
+	pub prefix:           String,
+	/// The string that separates the parameters from one another.
+	pub separator:        String,
+	/// A rewrite applied to every tile and probe URL just before its request is sent, e.g. to
+	/// substitute the host or sign the URL.
+	pub url_rewrite:      Option<UrlRewrite>,
+	/// Whether the X parameter is the row and the Y parameter is the column, rather than the other
+	/// way around.
+	///
+	/// Positions in the API are always (column, row), this only affects the URLs.
+	pub transposed:       bool,
+	/// Decides which failed tile and probe requests are retried, taken for boundaries, or fatal, for
+	/// servers that signal these differently (e.g. rate-limiting with HTTP 403), or `None` for the
+	/// [default](classify).
+	pub retry_classifier: Option<RetryClassifier>,
+}
+
+/// A URL rewrite, see [`TileSource::url_rewrite`].
+pub type UrlRewrite = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+impl fmt::Debug for TileSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("TileSource")
+			.field("base_url", &self.base_url)
+			.field("prefix", &self.prefix)
+			.field("separator", &self.separator)
+			.field("url_rewrite", &self.url_rewrite.as_ref().map(|_| ".."))
+			.field("transposed", &self.transposed)
+			.field(
+				"retry_classifier",
+				&self.retry_classifier.as_ref().map(|_| ".."),
+			)
+			.finish()
+	}
+}
+
+impl TileSource {
+	/// The default [prefix](TileSource::prefix).
+	pub const DEFAULT_PREFIX: &'static str = "=";
+	/// The default [separator](TileSource::separator).
+	pub const DEFAULT_SEPARATOR: &'static str = "-";
+
+	/// Formats the URL of the tile at the given column, row, and zoom level, with the
+	/// [rewrite](TileSource::url_rewrite) applied.
+	pub fn tile_url(&self, column: usize, row: usize, zoom: Zoom) -> String {
+		self.rewrite(self.url_with(
+			&[
+				(self.column_axis(), column),
+				(self.row_axis(), row),
+				('z', zoom.level()),
+			],
+			None,
+		))
+	}
+
+	/// The URL parameter of the column, see [`transposed`](TileSource::transposed).
+	pub fn column_axis(&self) -> char {
+		if self.transposed {
+			'y'
+		} else {
+			'x'
+		}
+	}
+
+	/// The URL parameter of the row, see [`transposed`](TileSource::transposed).
+	pub fn row_axis(&self) -> char {
+		if self.transposed {
+			'x'
+		} else {
+			'y'
+		}
+	}
+
+	/// Applies the [rewrite](TileSource::url_rewrite), if any, to the URL.
+	pub fn rewrite(&self, url: String) -> String {
+		match &self.url_rewrite {
+			Some(rewrite) => rewrite(&url),
+			None => url,
+		}
+	}
+
+	/// Formats a URL with the given parameters, optionally followed by the given axis without a
+	/// value (see [`determine_limit`]).
+	fn url_with(&self, parameters: &[(char, usize)], target: Option<char>) -> String {
+		let mut url = format!("{}{}", self.base_url, self.prefix);
+		for (i, (axis, value)) in parameters.iter().enumerate() {
+			if i > 0 {
+				url.push_str(&self.separator);
+			}
+			url.push(*axis);
+			url.push_str(itoa::Buffer::new().format(*value));
+		}
+		if let Some(target) = target {
+			if !parameters.is_empty() {
+				url.push_str(&self.separator);
+			}
+			url.push(target);
+		}
+		url
+	}
+}
+
+impl From<String> for TileSource {
+	fn from(base_url: String) -> Self {
+		Self {
+			base_url,
+			prefix: Self::DEFAULT_PREFIX.to_owned(),
+			separator: Self::DEFAULT_SEPARATOR.to_owned(),
+			url_rewrite: None,
+			transposed: false,
+			retry_classifier: None,
+		}
+	}
+}
+
+impl From<&str> for TileSource {
+	fn from(base_url: &str) -> Self { Self::from(base_url.to_owned()) }
+}
+
+/// Determines the limit of an axis for the image.
+///
+/// - The `base` parameter is the base URL of the image along with the XYZ parameters (see
+///   [`TileSource`]), but with the target axis parameter last and without a value (e.g. end with
+///   `=x0-y0-z` to target the Z axis).
+/// - The `num_workers` is the amount of simultaneous requests that will be made.
+/// - The `rewrite` is applied to the URLs before they're requested, see [`TileSource::url_rewrite`].
+/// - The `classifier` decides which failed requests are retried (per the `retry` policy), taken for
+///   the limit, or fatal, see [`TileSource::retry_classifier`].
+/// - The `ceiling` is taken for past the limit without probing it, which bounds the probing of a server
+///   that serves any value.
 ///
 /// This function will send HEAD requests, incrementing an axis determined by the base URL,
 /// and will return the highest value that succeeds.
@@ -92,103 +327,279 @@ async fn determine_limit(
 	client: impl AsRef<Client> + 'static + Send + Clone,
 	base: &str,
 	num_workers: usize,
+	rewrite: Option<UrlRewrite>,
+	classifier: Option<RetryClassifier>,
+	retry: RetryPolicy,
+	ceiling: usize,
 ) -> Result<usize, reqwest::Error> {
-	// A variable dedicated for the result.
-	// It's a `Result` that will be the minimal value that succeeds or an error if we encounter an
-	// error (that isn't a client-error because we took the axis too far).
-	let min_failure = Arc::new(RwLock::new(Ok::<usize, reqwest::Error>(usize::MAX)));
-	// An atomic counter of the axis value. Threads read and increment it as they try higher axis values.
-	let i = Arc::new(AtomicUsize::new(1));
-
-	let workers = (0..num_workers).map(|_| {
-		let mut base = StringMutTail::from(base);
-		let i = Arc::clone(&i);
-		let min_failure = Arc::clone(&min_failure);
-		let client = client.clone();
-		tokio::spawn(async move {
-			loop {
-				let client = client.as_ref();
-				let level = i.fetch_add(1, atomic::Ordering::SeqCst);
-				let response = client
-					.head(base.with_tail_int(level))
-					.send()
-					.await
-					.and_then(|r| r.error_for_status());
-				match response {
-					Ok(_) => {}
-					Err(e) if e.status().map_or(false, |c| c.is_client_error()) => {
-						let mut current_result = min_failure.write().await;
-						match *current_result {
-							Ok(previous_level) if level <= previous_level => {
-								*current_result = Ok(level);
-							}
-							Ok(_) => {}
-							Err(_) => {}
-						}
-						break;
+	let probe = Arc::new(LimitProbe::new(base, rewrite, classifier, ceiling));
+	// The workers are spawned into a set so that they're aborted if this future is dropped, or
+	// once one of them fails.
+	let mut workers = tokio::task::JoinSet::new();
+	for _ in 0..num_workers {
+		workers.spawn(Arc::clone(&probe).work(client.clone(), retry));
+	}
+	while let Some(worker) = workers.join_next().await {
+		if let Err(e) = worker.unwrap_or_else(resume_worker_panic) {
+			workers.abort_all();
+			return Err(e);
+		}
+	}
+	Ok(probe.limit())
+}
+
+/// Resumes the panic of a probing worker in its caller, as if it was the caller's own.
+///
+/// The workers are only cancelled when their set is dropped, along with its caller, so a worker
+/// that failed to join panicked.
+fn resume_worker_panic<T>(e: tokio::task::JoinError) -> T {
+	std::panic::resume_unwind(e.into_panic())
+}
+
+/// The probing of the limit of an axis by concurrent workers, see [`determine_limit`].
+struct LimitProbe {
+	/// The base URL, see [`determine_limit`].
+	base:        String,
+	rewrite:     Option<UrlRewrite>,
+	classifier:  Option<RetryClassifier>,
+	/// An atomic counter of the axis value. Workers read and increment it as they try higher axis
+	/// values.
+	next:        AtomicUsize,
+	/// The minimal axis value that fails with a client-error because we took the axis too far, or
+	/// the ceiling of the axis until one does.
+	min_failure: AtomicUsize,
+}
+
+impl LimitProbe {
+	fn new(
+		base: &str,
+		rewrite: Option<UrlRewrite>,
+		classifier: Option<RetryClassifier>,
+		ceiling: usize,
+	) -> Self {
+		Self {
+			base: base.to_owned(),
+			rewrite,
+			classifier,
+			next: AtomicUsize::new(1),
+			min_failure: AtomicUsize::new(ceiling),
+		}
+	}
+
+	/// Works on the probe as one of its workers, trying axis values until one that's past the limit.
+	///
+	/// Once a worker is done, higher values are known to be past the limit, so a worker that joins
+	/// afterwards is done right away.
+	async fn work(
+		self: Arc<Self>,
+		client: impl AsRef<Client>,
+		retry: RetryPolicy,
+	) -> Result<(), reqwest::Error> {
+		let client = client.as_ref();
+		let mut base = StringMutTail::from(self.base.as_str());
+		loop {
+			let level = self.next.fetch_add(1, atomic::Ordering::SeqCst);
+			if level >= self.min_failure.load(atomic::Ordering::SeqCst) {
+				return Ok(());
+			}
+			let url = base.with_tail_int(level);
+			let url = match &self.rewrite {
+				Some(rewrite) => rewrite(url),
+				None => url.to_owned(),
+			};
+			let (mut retries, mut backed_off) = (0, Duration::ZERO);
+			let response = loop {
+				let mut retry_after = None;
+				let response = send_request(client.head(&url)).await.and_then(|r| {
+					retry_after = retry::retry_after(&r);
+					r.error_for_status()
+				});
+				let e = match response {
+					Ok(response) => break Ok(response),
+					Err(e) => e,
+				};
+				match retry::decide(self.classifier.as_ref(), &e, retry_after) {
+					// A failure that's retried is no indication of the limit, even once the
+					// retries run out.
+					RetryDecision::Retry => {
+						let Some(backoff) = retry.next_delay(retries, backed_off, retry_after)
+						else {
+							break Err(e);
+						};
+						tracing::debug!(
+							"probe of {level} failed, retrying in {}ms: {e}",
+							backoff.as_millis()
+						);
+						tokio::time::sleep(backoff).await;
+						retries += 1;
+						backed_off += backoff;
 					}
-					Err(e) => {
-						*min_failure.write().await = Err(e);
-						break;
+					RetryDecision::Boundary => {
+						self.min_failure.fetch_min(level, atomic::Ordering::SeqCst);
+						return Ok(());
 					}
+					RetryDecision::Fatal => break Err(e),
 				}
-			}
-		})
-	});
+			};
+			response?;
+		}
+	}
 
-	futures::future::try_join_all(workers).await.unwrap();
-	Arc::try_unwrap(min_failure)
-		.unwrap()
-		.into_inner()
-		.map(|l| l - 1)
+	/// The limit of the axis, once all the workers are done.
+	fn limit(&self) -> usize { self.min_failure.load(atomic::Ordering::SeqCst) - 1 }
 }
 
-/// Determines the max zoom level for the image at the base URL.
+/// Determines the max zoom level for the image.
 pub async fn determine_max_zoom(
 	client: impl AsRef<Client> + 'static + Send + Clone,
-	base: &str,
+	source: &TileSource,
 	num_workers: usize,
-) -> Result<usize, reqwest::Error> {
-	determine_limit(client, &format!("{}=x0-y0-z", base), num_workers).await
+	retry: RetryPolicy,
+) -> Result<Zoom, reqwest::Error> {
+	let base = source.url_with(&[('x', 0), ('y', 0)], Some('z'));
+	determine_limit(
+		client,
+		&base,
+		num_workers,
+		source.url_rewrite.clone(),
+		source.retry_classifier.clone(),
+		retry,
+		// A server that serves any zoom level (e.g. by upscaling) is taken at the highest.
+		Zoom::HIGHEST.level() + 1,
+	)
+	.await
+	.map(|level| Zoom::new(level).expect("the limit is below the ceiling"))
 }
 
 /// Determines the count of columns i.e. the amount of cells going across the image.
 pub async fn determine_columns(
 	client: impl AsRef<Client> + 'static + Send + Clone,
-	base: &str,
-	zoom: usize,
+	source: &TileSource,
+	zoom: Zoom,
 	num_workers: usize,
+	retry: RetryPolicy,
 ) -> Result<usize, reqwest::Error> {
-	let base = format!("{}=z{}-y0-x", base, zoom);
-	determine_limit(client, &base, num_workers)
-		.await
-		.map(|c| c + 1)
+	let base = source.url_with(
+		&[('z', zoom.level()), (source.row_axis(), 0)],
+		Some(source.column_axis()),
+	);
+	determine_limit(
+		client,
+		&base,
+		num_workers,
+		source.url_rewrite.clone(),
+		source.retry_classifier.clone(),
+		retry,
+		usize::MAX,
+	)
+	.await
+	.map(|c| c + 1)
 }
 
 /// Determines the count of rows i.e. the amount of cells going along the image.
 pub async fn determine_rows(
 	client: impl AsRef<Client> + 'static + Send + Clone,
-	base: &str,
-	zoom: usize,
+	source: &TileSource,
+	zoom: Zoom,
 	num_workers: usize,
+	retry: RetryPolicy,
 ) -> Result<usize, reqwest::Error> {
-	let base = format!("{}=z{}-x0-y", base, zoom);
-	determine_limit(client, &base, num_workers)
-		.await
-		.map(|c| c + 1)
+	let base = source.url_with(
+		&[('z', zoom.level()), (source.column_axis(), 0)],
+		Some(source.row_axis()),
+	);
+	determine_limit(
+		client,
+		&base,
+		num_workers,
+		source.url_rewrite.clone(),
+		source.retry_classifier.clone(),
+		retry,
+		usize::MAX,
+	)
+	.await
+	.map(|c| c + 1)
 }
 
 /// Determines the [rows](determine_rows) and [columns](determine_columns) of the image (in-parallel).
 pub async fn determine_dimensions(
 	client: impl AsRef<Client> + 'static + Send + Clone,
-	base: &str,
-	zoom: usize,
+	source: &TileSource,
+	zoom: Zoom,
 	num_workers_half: usize,
+	retry: RetryPolicy,
 ) -> Result<(usize, usize), reqwest::Error> {
-	tokio::try_join!(
-		determine_columns(Clone::clone(&client), base, zoom, num_workers_half),
-		determine_rows(client, base, zoom, num_workers_half)
-	)
+	probe_dimensions(client, source, zoom, num_workers_half, retry, |_| {}).await
+}
+
+/// Determines the dimensions like [`determine_dimensions`], reporting each axis as soon as it's
+/// determined.
+///
+/// Each axis starts with half of the workers, and the workers of the axis that's done first move
+/// over to the other, since the axes of a non-square image take different amounts of probes.
+async fn probe_dimensions(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	zoom: Zoom,
+	num_workers_half: usize,
+	retry: RetryPolicy,
+	mut report: impl FnMut(Detection),
+) -> Result<(usize, usize), reqwest::Error> {
+	let probes = [
+		source.url_with(
+			&[('z', zoom.level()), (source.row_axis(), 0)],
+			Some(source.column_axis()),
+		),
+		source.url_with(
+			&[('z', zoom.level()), (source.column_axis(), 0)],
+			Some(source.row_axis()),
+		),
+	]
+	.map(|base| {
+		Arc::new(LimitProbe::new(
+			&base,
+			source.url_rewrite.clone(),
+			source.retry_classifier.clone(),
+			usize::MAX,
+		))
+	});
+	// The workers resolve to the index of the axis they worked on.
+	let mut workers = tokio::task::JoinSet::new();
+	let spawn = |workers: &mut tokio::task::JoinSet<_>, axis: usize| {
+		let work = Arc::clone(&probes[axis]).work(client.clone(), retry);
+		workers.spawn(work.map_ok(move |()| axis));
+	};
+	for axis in [0, 1] {
+		(0..num_workers_half).for_each(|_| spawn(&mut workers, axis));
+	}
+	let mut active = [num_workers_half; 2];
+	// Once a worker of an axis is done, the axis has no more values worth trying.
+	let mut exhausted = [false; 2];
+	while let Some(worker) = workers.join_next().await {
+		let axis = match worker.unwrap_or_else(resume_worker_panic) {
+			Ok(axis) => axis,
+			Err(e) => {
+				workers.abort_all();
+				return Err(e);
+			}
+		};
+		active[axis] -= 1;
+		exhausted[axis] = true;
+		if active[axis] == 0 {
+			let limit = probes[axis].limit() + 1;
+			report(match axis {
+				0 => Detection::Columns(limit),
+				_ => Detection::Rows(limit),
+			});
+		}
+		// The worker moves over to the other axis, if it still has values worth trying.
+		let other = 1 - axis;
+		if !exhausted[other] {
+			spawn(&mut workers, other);
+			active[other] += 1;
+		}
+	}
+	Ok((probes[0].limit() + 1, probes[1].limit() + 1))
 }
 
 /// An error when fetching or processing an image.
@@ -203,87 +614,1397 @@ pub enum Error {
 	/// Failure trying to determine the image's format.
 	#[error("image format inference error: {0}")]
 	ImageFormatGuessError(std::io::Error),
+	/// Failure trying to encode the image.
+	#[error("image encoding error: {0}")]
+	EncodingError(#[from] png::EncodingError),
+	/// Failure trying to write the image.
+	#[error("image write error: {0}")]
+	WriteError(std::io::Error),
+	/// The image is too large to be held in memory.
+	#[error(
+		"the image is too large ({width}\u{00D7}{height}), try a lower zoom level or the tile window \
+		 mode"
+	)]
+	ImageTooLarge { width: u64, height: u64 },
+	/// A tile claims more pixels than allowed, see [`RipOptions::max_tile_pixels`].
+	#[error(
+		"tile ({x},{y}) is too large ({width}\u{00D7}{height}), exceeding the limit of {max_pixels} \
+		 pixels"
+	)]
+	TileTooLarge {
+		x:          usize,
+		y:          usize,
+		width:      u32,
+		height:     u32,
+		max_pixels: u64,
+	},
+	/// A tile's size differs from the size of the first tile, beyond edge tiles being smaller, see
+	/// [`RipOptions::strict_tile_sizes`].
+	#[error(
+		"tile ({x},{y}) is {width}\u{00D7}{height} but the tiles are {tile_width}\u{00D7}{tile_height}"
+	)]
+	TileSizeMismatch {
+		x:           usize,
+		y:           usize,
+		width:       u32,
+		height:      u32,
+		tile_width:  u32,
+		tile_height: u32,
+	},
+	/// The buffer given to [`rip_into`] doesn't match the dimensions of the image.
+	#[error(
+		"the buffer is {actual_width}\u{00D7}{actual_height} but the image is \
+		 {width}\u{00D7}{height}"
+	)]
+	BufferSizeMismatch {
+		width:         u32,
+		height:        u32,
+		actual_width:  u32,
+		actual_height: u32,
+	},
+	/// The metadata phase (e.g. dimension detection) did not finish within its time limit.
+	#[error("timed out determining the image metadata")]
+	MetadataTimeout,
+	/// The tiles were retried more than the given amount of times in total, see
+	/// [`RipOptions::max_total_retries`].
+	#[error(
+		"giving up after exhausting the budget of {0} retries, the server seems to be failing"
+	)]
+	RetryBudgetExhausted(usize),
+	/// A tile is missing, e.g. HTTP 404 (or whatever the
+	/// [classifier](TileSource::retry_classifier) takes for a boundary).
+	#[error("missing tile: {0}")]
+	TileMissing(reqwest::Error),
+	/// The written image doesn't decode to its dimensions, e.g. due to a truncated write.
+	#[error("the written image failed verification: {0}")]
+	OutputVerificationFailed(String),
+	/// A tile's response had only part of it (HTTP 206), which is retried asking for the whole of
+	/// it.
+	#[error("got only part of the tile at {0}")]
+	PartialContent(String),
+	/// The image has no tiles, e.g. due to a broken server or the wrong zoom level or grid.
+	#[error("the image has no tiles ({columns} columns \u{00D7} {rows} rows)")]
+	EmptyDimensions { columns: usize, rows: usize },
 }
 
-/// Rips an image from the given base URL.
+impl Error {
+	/// Whether the error is due to the requested resource not being found (HTTP 404, or a
+	/// [missing tile](Error::TileMissing)).
+	pub fn is_not_found(&self) -> bool {
+		matches!(self, Self::TileMissing(_))
+			|| matches!(self, Self::HttpError(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND))
+	}
+}
+
+/// Serializes the error as an object of its kind (e.g. `tile_missing`), its message, and its context
+/// (e.g. the URL and HTTP status of the failed request, or the position of the failed tile), for
+/// machine-readable failures.
+impl serde::Serialize for Error {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		use serde_json::json;
+		let (kind, context) = match self {
+			Self::HttpError(e) => ("http", http_context(e)),
+			Self::ImageError(_) => ("image", json!({})),
+			Self::ImageFormatGuessError(_) => ("image_format_guess", json!({})),
+			Self::EncodingError(_) => ("encoding", json!({})),
+			Self::WriteError(_) => ("write", json!({})),
+			Self::ImageTooLarge { width, height } => (
+				"image_too_large",
+				json!({ "width": width, "height": height }),
+			),
+			Self::TileTooLarge {
+				x,
+				y,
+				width,
+				height,
+				max_pixels,
+			} => (
+				"tile_too_large",
+				json!({
+					"x": x,
+					"y": y,
+					"width": width,
+					"height": height,
+					"max_pixels": max_pixels,
+				}),
+			),
+			Self::TileSizeMismatch {
+				x,
+				y,
+				width,
+				height,
+				tile_width,
+				tile_height,
+			} => (
+				"tile_size_mismatch",
+				json!({
+					"x": x,
+					"y": y,
+					"width": width,
+					"height": height,
+					"tile_width": tile_width,
+					"tile_height": tile_height,
+				}),
+			),
+			Self::BufferSizeMismatch {
+				width,
+				height,
+				actual_width,
+				actual_height,
+			} => (
+				"buffer_size_mismatch",
+				json!({
+					"width": width,
+					"height": height,
+					"actual_width": actual_width,
+					"actual_height": actual_height,
+				}),
+			),
+			Self::MetadataTimeout => ("metadata_timeout", json!({})),
+			Self::RetryBudgetExhausted(retries) => {
+				("retry_budget_exhausted", json!({ "retries": retries }))
+			}
+			Self::TileMissing(e) => ("tile_missing", http_context(e)),
+			Self::OutputVerificationFailed(_) => ("output_verification_failed", json!({})),
+			Self::PartialContent(url) => ("partial_content", json!({ "url": url })),
+			Self::EmptyDimensions { columns, rows } => (
+				"empty_dimensions",
+				json!({ "columns": columns, "rows": rows }),
+			),
+		};
+		serialize_error(serializer, kind, self, context)
+	}
+}
+
+/// The context of a failed request: its URL and the HTTP status of its response, if any.
+fn http_context(e: &reqwest::Error) -> serde_json::Value {
+	serde_json::json!({
+		"url": e.url().map(|url| url.as_str()),
+		"status": e.status().map(|status| status.as_u16()),
+	})
+}
+
+/// Serializes an error as an object of its kind, its message, and the fields of its context.
+fn serialize_error<S: serde::Serializer>(
+	serializer: S,
+	kind: &str,
+	error: &dyn Display,
+	context: serde_json::Value,
+) -> Result<S::Ok, S::Error> {
+	use serde::ser::SerializeMap as _;
+	let mut map = serializer.serialize_map(None)?;
+	map.serialize_entry("kind", kind)?;
+	map.serialize_entry("message", &error.to_string())?;
+	for (key, value) in context.as_object().into_iter().flatten() {
+		map.serialize_entry(key, value)?;
+	}
+	map.end()
+}
+
+/// A tile that was fetched, see [`RipOptions::on_tile`].
+#[derive(Debug, Clone, Copy)]
+pub struct FetchedTile<'a> {
+	/// The column and row of the tile.
+	pub position: (usize, usize),
+	/// The zoom level of the tile.
+	pub zoom:     Zoom,
+	/// The URL of the tile.
+	pub url:      &'a str,
+	/// The HTTP status of the response the tile came in, or `None` if it came from the
+	/// [tile store](RipOptions::tile_store).
+	pub status:   Option<reqwest::StatusCode>,
+	/// The time it took to get the tile, including retries.
+	pub duration: Duration,
+	/// The encoded data of the tile.
+	pub data:     &'a bytes::Bytes,
+}
+
+/// A callback with each tile that was fetched, see [`RipOptions::on_tile`].
+pub type TileCallback = Arc<dyn Fn(&FetchedTile) + Send + Sync>;
+
+/// Options for [`rip`].
+#[derive(Clone)]
+pub struct RipOptions {
+	/// The zoom / resolution level.
+	pub zoom:                Zoom,
+	/// Half of the amount of parallel connections that will be used to fetch metadata (half
+	/// because at most two operations will get this limit in parallel).
+	pub num_workers_half:    usize,
+	/// The time limit for the metadata phase, i.e. determining the dimensions of the image.
+	///
+	/// This is separate from the time it takes to download the image itself. `None` for no limit.
+	pub metadata_timeout:    Option<Duration>,
+	/// The maximum amount of tiles that will be fetched in parallel.
+	pub tile_workers:        usize,
+	/// A store that is consulted before fetching tiles and that fetched tiles are put in.
+	pub tile_store:          Option<Arc<dyn TileStore>>,
+	/// A limit of the bandwidth of the tile downloads, which may be shared with other rips to limit
+	/// them together, or `None` for no limit.
+	pub bandwidth_limit:     Option<Arc<BandwidthLimit>>,
+	/// If set, tiles that aren't found (HTTP 404) are re-attempted once more after the rest of the
+	/// tiles are fetched and the given delay passes, rather than failing the rip right away.
+	///
+	/// This salvages tiles that weren't available early in the rip, e.g. if the server was still
+	/// warming its cache.
+	pub retry_missing_after: Option<Duration>,
+	/// The maximum amount of pixels a tile may have, or `None` for no limit.
+	///
+	/// Tiles are small, so a tile that claims more pixels is likely corrupt or malicious (a
+	/// decompression bomb), and is rejected before it's decoded.
+	pub max_tile_pixels:     Option<u64>,
+	/// A callback that is called as the rip progresses.
+	pub progress:            Option<ProgressCallback>,
+	/// A callback that is called with each tile (its position, encoded data, and how it was
+	/// fetched) as soon as it's fetched (or found in the [store](RipOptions::tile_store)) and
+	/// decoded, e.g. to process the tiles while the rip goes on.
+	pub on_tile:             Option<TileCallback>,
+	/// The columns and rows of the tiles, if already known (e.g. from a previous rip), in which case
+	/// they're not determined again.
+	///
+	/// They must match the [zoom level](RipOptions::zoom).
+	pub grid:                Option<(usize, usize)>,
+	/// The maximum amount of tiles [`rip_png_stream`] holds that are fetched but not yet written,
+	/// beyond the ones that are being fetched.
+	///
+	/// Once reached, fetching pauses until the writer catches up, which bounds the memory usage
+	/// when the writer is the bottleneck (e.g. a slow disk).
+	pub max_buffered_tiles:  usize,
+	/// How failed requests are retried.
+	pub retry:               RetryPolicy,
+	/// The maximum amount of times to fetch a tile again when it fails to decode, since bytes that
+	/// were corrupted on the way are often fine when fetched again. Content that isn't recognizably
+	/// an image (e.g. an HTML error page) isn't fetched again.
+	pub decode_retries:      u32,
+	/// The maximum amount of retries of all the tiles of a rip combined, or `None` for no limit.
+	///
+	/// Once exceeded, the rip fails with [`Error::RetryBudgetExhausted`], so that a failing server
+	/// fails the rip quickly, rather than after every tile exhausts its own retries.
+	pub max_total_retries:   Option<usize>,
+	/// Whether a tile whose size differs from the first tile's (beyond the tiles at the right and
+	/// bottom edges being smaller) fails the rip, rather than just being logged as a warning.
+	pub strict_tile_sizes:   bool,
+	/// The size of the tiles to place them by, or `None` to take the size of the first tile.
+	///
+	/// For sources whose first tile isn't of the size of the rest. The tiles are still
+	/// [checked](RipOptions::strict_tile_sizes) against it.
+	pub tile_size:           Option<(u32, u32)>,
+	/// Whether to transform the image according to the EXIF orientation of its first tile (all the
+	/// tiles are assumed to share it), so that rotated scans come out upright.
+	pub auto_orient:         bool,
+}
+
+impl fmt::Debug for RipOptions {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("RipOptions")
+			.field("zoom", &self.zoom)
+			.field("num_workers_half", &self.num_workers_half)
+			.field("metadata_timeout", &self.metadata_timeout)
+			.field("tile_workers", &self.tile_workers)
+			.field("tile_store", &self.tile_store)
+			.field("bandwidth_limit", &self.bandwidth_limit)
+			.field("retry_missing_after", &self.retry_missing_after)
+			.field("max_tile_pixels", &self.max_tile_pixels)
+			.field("progress", &self.progress.as_ref().map(|_| ".."))
+			.field("on_tile", &self.on_tile.as_ref().map(|_| ".."))
+			.field("grid", &self.grid)
+			.field("max_buffered_tiles", &self.max_buffered_tiles)
+			.field("retry", &self.retry)
+			.field("decode_retries", &self.decode_retries)
+			.field("max_total_retries", &self.max_total_retries)
+			.field("strict_tile_sizes", &self.strict_tile_sizes)
+			.field("tile_size", &self.tile_size)
+			.field("auto_orient", &self.auto_orient)
+			.finish()
+	}
+}
+
+impl RipOptions {
+	/// The default of [`max_tile_pixels`](RipOptions::max_tile_pixels), 2048\u{00D7}2048.
+	pub const DEFAULT_MAX_TILE_PIXELS: u64 = 2048 * 2048;
+	/// The default of [`max_buffered_tiles`](RipOptions::max_buffered_tiles).
+	pub const DEFAULT_MAX_BUFFERED_TILES: usize = 64;
+
+	/// Creates options for the given zoom level, with defaults for the rest.
+	pub fn new(zoom: Zoom) -> Self {
+		Self {
+			zoom,
+			num_workers_half: 8,
+			metadata_timeout: None,
+			tile_workers: 16,
+			tile_store: None,
+			bandwidth_limit: None,
+			retry_missing_after: None,
+			max_tile_pixels: Some(Self::DEFAULT_MAX_TILE_PIXELS),
+			progress: None,
+			on_tile: None,
+			grid: None,
+			max_buffered_tiles: Self::DEFAULT_MAX_BUFFERED_TILES,
+			retry: RetryPolicy::default(),
+			decode_retries: 0,
+			max_total_retries: None,
+			strict_tile_sizes: false,
+			tile_size: None,
+			auto_orient: true,
+		}
+	}
+}
+
+/// Runs the metadata future with the given time limit, if any.
+async fn with_metadata_timeout<T>(
+	timeout: Option<Duration>,
+	future: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+	match timeout {
+		Some(timeout) => tokio::time::timeout(timeout, future)
+			.await
+			.map_err(|_| Error::MetadataTimeout)?,
+		None => future.await,
+	}
+}
+
+/// Whether the response has only part of the resource: HTTP 206 (Partial Content) without a
+/// `Content-Range` of the whole of it, e.g. due to a stray `Range` header from a proxy.
+fn is_partial(response: &reqwest::Response) -> bool {
+	if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+		return false;
+	}
+	let is_whole = || {
+		let range = response
+			.headers()
+			.get(reqwest::header::CONTENT_RANGE)?
+			.to_str()
+			.ok()?
+			.strip_prefix("bytes ")?;
+		let (range, total) = range.split_once('/')?;
+		let (start, end) = range.split_once('-')?;
+		let (start, end, total) = (
+			start.parse::<u64>().ok()?,
+			end.parse::<u64>().ok()?,
+			total.parse::<u64>().ok()?,
+		);
+		Some(start == 0 && end + 1 == total)
+	};
+	!is_whole().unwrap_or(false)
+}
+
+/// Fetches and decodes the tile at the given position, going through the
+/// [tile store](RipOptions::tile_store) if any.
 ///
-/// `num_workers_half` corresponds to half of the amount of parallel connections that will be used to
-/// fetch metadata (half because at most two operations will get this limit in parallel).
-pub async fn rip(
+/// Transient failures are [retried](RipOptions::retry), taking from the budget, and so are tiles
+/// that fail to decode, [if enabled](RipOptions::decode_retries).
+///
+/// Resolves to the tile and its encoded data.
+async fn fetch_tile(
+	client: &Client,
+	source: &TileSource,
+	options: &RipOptions,
+	budget: &RetryBudget,
+	(x, y): (usize, usize),
+) -> Result<(image::DynamicImage, bytes::Bytes), Error> {
+	let time_start = Instant::now();
+	let url = source.tile_url(x, y, options.zoom);
+	let stored = match &options.tile_store {
+		Some(store) => store.get(&url).await,
+		None => None,
+	};
+	let is_stored = stored.is_some();
+	let (mut data, mut status) = if let Some(data) = stored {
+		tracing::trace!("found cell ({x},{y}) in the tile store");
+		(data, None)
+	} else {
+		tracing::trace!("fetching cell ({x},{y})");
+		download_tile(client, source, options, budget, &url, (x, y)).await?
+	};
+	let mut decode_retries = 0;
+	let tile = loop {
+		let e = match decode_tile(options, (x, y), &data) {
+			Ok(tile) => break tile,
+			Err(e) => e,
+		};
+		// Content that isn't recognizably an image (e.g. an HTML error page) won't be one when
+		// fetched again, unlike an image whose bytes were corrupted on the way.
+		let is_corrupt = matches!(e, Error::ImageError(_)) && image::guess_format(&data).is_ok();
+		if !is_corrupt || decode_retries >= options.decode_retries {
+			return Err(e);
+		}
+		budget.take().map_err(Error::RetryBudgetExhausted)?;
+		let backoff = options.retry.backoff(decode_retries);
+		let cause = format!("failing to decode it ({e})");
+		retry::log_tile_retry(
+			(x, y),
+			decode_retries,
+			options.decode_retries,
+			&cause,
+			backoff,
+		);
+		tokio::time::sleep(backoff).await;
+		budget.back_off(backoff);
+		decode_retries += 1;
+		(data, status) = download_tile(client, source, options, budget, &url, (x, y)).await?;
+	};
+	if let Some(store) = &options.tile_store {
+		if !is_stored || decode_retries > 0 {
+			store.put(&url, data.clone()).await;
+		}
+	}
+	if let Some(on_tile) = &options.on_tile {
+		on_tile(&FetchedTile {
+			position: (x, y),
+			zoom: options.zoom,
+			url: &url,
+			status,
+			duration: time_start.elapsed(),
+			data: &data,
+		});
+	}
+	Ok((tile, data))
+}
+
+/// Downloads the encoded data of the tile at the URL (at the given position), retrying transient
+/// failures.
+///
+/// Resolves to the data and the HTTP status of its response.
+async fn download_tile(
+	client: &Client,
+	source: &TileSource,
+	options: &RipOptions,
+	budget: &RetryBudget,
+	url: &str,
+	(x, y): (usize, usize),
+) -> Result<(bytes::Bytes, Option<reqwest::StatusCode>), Error> {
+	let (mut retries, mut backed_off) = (0, Duration::ZERO);
+	// Whether to ask for the whole tile explicitly, after getting only part of it.
+	let mut full_range = false;
+	let mut status = None;
+	let data = loop {
+		let mut retry_after = None;
+		let data = async {
+			let mut request = client.get(url);
+			if full_range {
+				request = request.header(reqwest::header::RANGE, "bytes=0-");
+			}
+			let response = send_request(request).await?;
+			status = Some(response.status());
+			retry_after = retry::retry_after(&response);
+			let mut response = response.error_for_status()?;
+			if is_partial(&response) {
+				return Ok(None);
+			}
+			let Some(limit) = &options.bandwidth_limit else {
+				return response.bytes().await.map(Some);
+			};
+			let mut data = bytes::BytesMut::new();
+			while let Some(chunk) = response.chunk().await? {
+				limit.consume(chunk.len()).await;
+				data.extend_from_slice(&chunk);
+			}
+			Ok(Some(data.freeze()))
+		}
+		.await;
+		let (decision, e) = match data {
+			Ok(Some(data)) => break data,
+			Ok(None) => {
+				full_range = true;
+				(RetryDecision::Retry, Error::PartialContent(url.to_owned()))
+			}
+			Err(e) => (
+				retry::decide(source.retry_classifier.as_ref(), &e, retry_after),
+				Error::HttpError(e),
+			),
+		};
+		match decision {
+			RetryDecision::Retry => {
+				let Some(backoff) = options.retry.next_delay(retries, backed_off, retry_after)
+				else {
+					return Err(e);
+				};
+				budget.take().map_err(Error::RetryBudgetExhausted)?;
+				let cause = match &e {
+					Error::HttpError(e) => {
+						e.status().map_or_else(|| e.to_string(), |s| s.to_string())
+					}
+					e => e.to_string(),
+				};
+				retry::log_tile_retry((x, y), retries, options.retry.max_retries, &cause, backoff);
+				tokio::time::sleep(backoff).await;
+				budget.back_off(backoff);
+				retries += 1;
+				backed_off += backoff;
+			}
+			RetryDecision::Boundary => {
+				return Err(match e {
+					Error::HttpError(e) => Error::TileMissing(e),
+					e => e,
+				})
+			}
+			RetryDecision::Fatal => return Err(e),
+		}
+	};
+	Ok((data, status))
+}
+
+/// Decodes the encoded data of the tile at the given position.
+fn decode_tile(
+	options: &RipOptions,
+	(x, y): (usize, usize),
+	data: &bytes::Bytes,
+) -> Result<image::DynamicImage, Error> {
+	let reader = || {
+		image::io::Reader::new(Cursor::new(data))
+			.with_guessed_format()
+			.map_err(Error::ImageFormatGuessError)
+	};
+	let mut tile = reader()?;
+	if let Some(max_pixels) = options.max_tile_pixels {
+		let (width, height) = reader()?.into_dimensions()?;
+		if u64::from(width) * u64::from(height) > max_pixels {
+			return Err(Error::TileTooLarge {
+				x,
+				y,
+				width,
+				height,
+				max_pixels,
+			});
+		}
+		let mut limits = image::io::Limits::default();
+		// The deepest pixels are RGBA of 32-bit floats.
+		limits.max_alloc = Some(max_pixels.saturating_mul(16));
+		tile.limits(limits);
+	}
+	Ok(tile.decode()?)
+}
+
+/// Determines the columns and rows of the image, and fetches the first tile (in-parallel).
+///
+/// Resolves to the columns, rows, the first tile and its encoded data, and the EXIF orientation of
+/// the first tile, if [auto-orienting](RipOptions::auto_orient).
+async fn determine_layout(
 	client: impl AsRef<Client> + 'static + Send + Clone,
-	base: &str,
-	zoom: usize,
-	num_workers_half: usize,
-) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, Error> {
+	source: &TileSource,
+	options: &RipOptions,
+	budget: &RetryBudget,
+) -> Result<(usize, usize, image::DynamicImage, bytes::Bytes, Option<u32>), Error> {
+	if let Some(progress) = &options.progress {
+		progress(&Progress {
+			phase:       Phase::Metadata,
+			tiles_done:  0,
+			tiles_total: 0,
+			bytes:       0,
+			eta:         None,
+			detected:    None,
+			backed_off:  budget.backed_off(),
+		});
+	}
+	let report = |detection| {
+		if let Some(progress) = &options.progress {
+			progress(&Progress::detected(detection));
+		}
+	};
+	let zoom = options.zoom;
+	let grid = options.grid;
+	// A given grid is checked before the first tile is fetched alongside the dimensions, so that
+	// the tile isn't fetched for nothing (probed dimensions are at least a column and a row).
+	if let Some((columns, rows)) = grid {
+		non_empty(columns, rows)?;
+	}
 	let dims_task = {
 		let client = Clone::clone(&client);
-		async {
-			determine_dimensions(client, base, zoom, num_workers_half)
-				.await
-				.map_err(Error::HttpError)
-		}
+		with_metadata_timeout(options.metadata_timeout, async move {
+			if let Some((columns, rows)) = grid {
+				tracing::trace!("using the given grid instead of determining it");
+				report(Detection::Columns(columns));
+				report(Detection::Rows(rows));
+				return non_empty(columns, rows);
+			}
+			// The axes are reported as each is determined, rather than when both are.
+			let (columns, rows) = probe_dimensions(
+				client,
+				source,
+				zoom,
+				options.num_workers_half,
+				options.retry,
+				report,
+			)
+			.await
+			.map_err(Error::HttpError)?;
+			non_empty(columns, rows)
+		})
 	};
-	let fetch_cell_client = Clone::clone(&client);
-	let fetch_cell = |(x, y): (usize, usize)| {
-		tracing::trace!("fetching cell ({x},{y})");
-		let client = Clone::clone(&fetch_cell_client);
+	let head_task = fetch_tile(client.as_ref(), source, options, budget, (0, 0));
+	let ((columns, rows), (head, data)) = tokio::try_join!(dims_task, head_task)?;
+	tracing::trace!("determined {columns} columns \u{00D7} {rows} rows");
+	let orientation = options
+		.auto_orient
+		.then(|| orientation::read(&data))
+		.flatten()
+		.filter(|&orientation| orientation != 1);
+	if let Some(orientation) = orientation {
+		tracing::debug!("the tiles have the EXIF orientation {orientation}");
+	}
+	Ok((columns, rows, head, data, orientation))
+}
+
+/// Fails with [`Error::EmptyDimensions`] unless there's at least a column and a row, so that an
+/// image without tiles isn't allocated.
+fn non_empty(columns: usize, rows: usize) -> Result<(usize, usize), Error> {
+	if columns == 0 || rows == 0 {
+		return Err(Error::EmptyDimensions { columns, rows });
+	}
+	Ok((columns, rows))
+}
+
+/// Checks that the tile at the given position of the layout of the given columns and rows is of
+/// the given tile size, except for tiles at the right and bottom edges, which may be smaller.
+///
+/// A mismatch is an error if the [sizes are strict](RipOptions::strict_tile_sizes), or a warning
+/// otherwise.
+fn check_tile_size(
+	options: &RipOptions,
+	(columns, rows): (usize, usize),
+	(tile_width, tile_height): (u32, u32),
+	(x, y): (usize, usize),
+	tile: &image::DynamicImage,
+) -> Result<(), Error> {
+	let (width, height) = tile.dimensions();
+	let fits =
+		|size: u32, expected: u32, is_edge: bool| size == expected || (is_edge && size < expected);
+	if fits(width, tile_width, x + 1 == columns) && fits(height, tile_height, y + 1 == rows) {
+		return Ok(());
+	}
+	let error = Error::TileSizeMismatch {
+		x,
+		y,
+		width,
+		height,
+		tile_width,
+		tile_height,
+	};
+	if options.strict_tile_sizes {
+		return Err(error);
+	}
+	tracing::warn!("{error}, it may be misaligned");
+	Ok(())
+}
+
+/// The size of the tiles: the [given](RipOptions::tile_size) size, or the size of the first tile.
+fn tile_size(options: &RipOptions, head: &image::DynamicImage) -> (u32, u32) {
+	let Some((tile_width, tile_height)) = options.tile_size else {
+		return head.dimensions();
+	};
+	let (width, height) = head.dimensions();
+	if (width, height) != (tile_width, tile_height) {
+		tracing::warn!(
+			"the first tile is {width}\u{00D7}{height}, placing the tiles as \
+			 {tile_width}\u{00D7}{tile_height} instead"
+		);
+	}
+	(tile_width, tile_height)
+}
+
+/// Computes the dimensions of an image of the given layout, validating that they're representable.
+fn image_dimensions(
+	columns: usize,
+	rows: usize,
+	tile_width: u32,
+	tile_height: u32,
+) -> Result<(u32, u32), Error> {
+	let width = (columns as u64).saturating_mul(tile_width.into());
+	let height = (rows as u64).saturating_mul(tile_height.into());
+	tracing::trace!("cell size is {tile_width}\u{00D7}{tile_height}, total image size will be {width}\u{00D7}{height}");
+	match (u32::try_from(width), u32::try_from(height)) {
+		(Ok(width), Ok(height)) => Ok((width, height)),
+		_ => Err(Error::ImageTooLarge { width, height }),
+	}
+}
+
+/// Whether the image has more than 8 bits per channel.
+fn is_deep(image: &image::DynamicImage) -> bool {
+	let color = image.color();
+	color.bytes_per_pixel() > color.channel_count()
+}
+
+/// An image being stitched from its tiles.
+struct Canvas {
+	/// The image, which is RGBA of 8 bits per channel, or of 16 bits if any of the tiles is deeper
+	/// than 8 bits.
+	image:       image::DynamicImage,
+	columns:     usize,
+	rows:        usize,
+	tile_width:  u32,
+	tile_height: u32,
+	/// Whether each tile has been placed in the image, in column-major order.
+	placed:      Vec<bool>,
+	/// The EXIF orientation of the tiles, which is applied to the final image.
+	orientation: Option<u32>,
+}
+
+impl Canvas {
+	/// Creates a blank canvas for the layout of tiles of the given size, with the first tile
+	/// placed.
+	fn new(
+		columns: usize,
+		rows: usize,
+		(tile_width, tile_height): (u32, u32),
+		head: &image::DynamicImage,
+	) -> Result<Self, Error> {
+		let (image_width, image_height) = image_dimensions(columns, rows, tile_width, tile_height)?;
+		let image = if is_deep(head) {
+			Self::check_allocation(image_width, image_height, 8)?;
+			image::DynamicImage::new_rgba16(image_width, image_height)
+		} else {
+			Self::check_allocation(image_width, image_height, 4)?;
+			image::DynamicImage::new_rgba8(image_width, image_height)
+		};
+		let mut canvas = Self {
+			image,
+			columns,
+			rows,
+			tile_width,
+			tile_height,
+			placed: vec![false; columns * rows],
+			orientation: None,
+		};
+		canvas.place((0, 0), head)?;
+		Ok(canvas)
+	}
+
+	/// Checks that an image of the given dimensions and pixel size can be allocated.
+	fn check_allocation(width: u32, height: u32, bytes_per_pixel: usize) -> Result<(), Error> {
+		let too_large = || Error::ImageTooLarge {
+			width:  width.into(),
+			height: height.into(),
+		};
+		let bytes = (width as usize)
+			.checked_mul(height as usize)
+			.and_then(|pixels| pixels.checked_mul(bytes_per_pixel))
+			.ok_or_else(too_large)?;
+		if bytes > isize::MAX as usize {
+			return Err(too_large());
+		}
+		Ok(())
+	}
+
+	/// Places the tile at its position in the image.
+	///
+	/// If the tile is deeper than 8 bits per channel, the image is promoted to 16 bits first, so
+	/// that the tile doesn't lose precision.
+	fn place(&mut self, (x, y): (usize, usize), tile: &image::DynamicImage) -> Result<(), Error> {
+		if let (true, image::DynamicImage::ImageRgba8(image)) = (is_deep(tile), &self.image) {
+			tracing::debug!("cell ({x},{y}) is deeper than 8 bits, promoting the image to 16 bits");
+			Self::check_allocation(image.width(), image.height(), 8)?;
+			self.image = image::DynamicImage::ImageRgba16(self.image.to_rgba16());
+		}
+		let (x_pixels, y_pixels) = (x as u32 * self.tile_width, y as u32 * self.tile_height);
+		match &mut self.image {
+			image::DynamicImage::ImageRgba8(image) => image.copy_from(tile, x_pixels, y_pixels)?,
+			image::DynamicImage::ImageRgba16(image) => {
+				image.copy_from(&tile.to_rgba16(), x_pixels, y_pixels)?
+			}
+			_ => unreachable!("the canvas is either RGBA8 or RGBA16"),
+		}
+		self.placed[x * self.rows + y] = true;
+		Ok(())
+	}
+
+	/// The final image, transformed according to the [orientation](Canvas::orientation) if any.
+	fn into_image(self) -> image::DynamicImage {
+		match self.orientation {
+			Some(orientation) => orientation::apply(self.image, orientation),
+			None => self.image,
+		}
+	}
+
+	/// The positions of the tiles that haven't been placed.
+	fn missing(&self) -> Vec<(usize, usize)> {
+		(0..self.columns)
+			.cartesian_product(0..self.rows)
+			.zip(&self.placed)
+			.filter_map(|(position, &placed)| (!placed).then_some(position))
+			.collect()
+	}
+}
+
+/// Determines the layout of the image and prepares a canvas for it.
+async fn prepare_canvas(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	budget: &RetryBudget,
+) -> Result<Canvas, Error> {
+	let (columns, rows, head, _, orientation) =
+		determine_layout(client, source, options, budget).await?;
+	let mut canvas = Canvas::new(columns, rows, tile_size(options, &head), &head)?;
+	canvas.orientation = orientation;
+	Ok(canvas)
+}
+
+/// Fetches the given tiles into the canvas, stopping at the first error.
+async fn stitch(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	budget: &RetryBudget,
+	canvas: Canvas,
+	cells: Vec<(usize, usize)>,
+) -> (Canvas, Result<(), Error>) {
+	let placed = canvas.placed.iter().filter(|&&placed| placed).count();
+	let progress = &TileProgress::new(
+		options.progress.as_ref(),
+		budget,
+		placed,
+		placed + cells.len(),
+	);
+	let canvas_mutex = Mutex::new(canvas);
+	let canvas = &canvas_mutex;
+	let permits = &Semaphore::new(options.tile_workers.max(1));
+	let in_flight = &InFlight::default();
+	// Fetches the cells, resolving to the ones that weren't found if they're to be deferred, or
+	// failing on them otherwise.
+	let pass = |cells: Vec<(usize, usize)>, defer_not_found: bool| {
+		let not_found = std::sync::Mutex::new(Vec::new());
+		let client = Clone::clone(&client);
 		async move {
-			let data = client
-				.as_ref()
-				.get(format!("{}=x{}-y{}-z{}", base, x, y, zoom))
-				.send()
-				.await?
-				.error_for_status()?
-				.bytes()
-				.await?;
-			image::io::Reader::new(Cursor::new(data))
-				.with_guessed_format()
-				.map_err(Error::ImageFormatGuessError)?
-				.decode()
-				.map_err(Error::ImageError)
+			futures::future::try_join_all(cells.into_iter().map(|(x, y)| {
+				let client = Clone::clone(&client);
+				let not_found = &not_found;
+				async move {
+					let cell = {
+						let _permit = permits
+							.acquire()
+							.await
+							.expect("the semaphore is never closed");
+						let _in_flight = in_flight.enter();
+						fetch_tile(client.as_ref(), source, options, budget, (x, y)).await
+					};
+					match cell {
+						Ok((cell, data)) => {
+							tracing::trace!("fetched cell ({x},{y})");
+							let mut canvas = canvas.lock().await;
+							check_tile_size(
+								options,
+								(canvas.columns, canvas.rows),
+								(canvas.tile_width, canvas.tile_height),
+								(x, y),
+								&cell,
+							)?;
+							canvas.place((x, y), &cell)?;
+							progress.advance(data.len());
+							Ok(())
+						}
+						Err(e) if defer_not_found && e.is_not_found() => {
+							tracing::trace!("cell ({x},{y}) was not found, deferring it");
+							not_found.lock().unwrap().push((x, y));
+							Ok(())
+						}
+						Err(e) => Err(e),
+					}
+				}
+			}))
+			.await?;
+			Ok::<_, Error>(not_found.into_inner().unwrap())
 		}
 	};
-	let head_task = fetch_cell((0, 0));
-	let ((columns, rows), head) = tokio::try_join!(dims_task, head_task)?;
-	tracing::trace!("determined {columns} columns \u{00D7} {rows} rows");
-	let (tile_width, tile_height) = head.dimensions();
-	let image_width = columns as u32 * tile_width;
-	let image_height = rows as u32 * tile_height;
-	tracing::trace!("cell size is {tile_width}\u{00D7}{tile_height}, total image size will be {image_width}\u{00D7}{image_height}");
-
-	let mut image = image::ImageBuffer::new(image_width, image_height);
-	image.copy_from(&head, 0, 0)?;
-
-	let image = Arc::new(Mutex::new(image));
-	let cells = (0..columns).cartesian_product(0..rows).skip(1);
-	futures::future::try_join_all(cells.map(|(x, y)| {
-		let image = Arc::clone(&image);
-		async move {
-			let cell = fetch_cell((x, y)).await?;
-			tracing::trace!("fetched cell ({x},{y})");
-			image
-				.lock()
-				.await
-				.copy_from(&cell, x as u32 * tile_width, y as u32 * tile_height)?;
-			Ok::<(), Error>(())
+
+	let result = async {
+		let not_found = pass(cells, options.retry_missing_after.is_some()).await?;
+		if let (Some(delay), false) = (options.retry_missing_after, not_found.is_empty()) {
+			tracing::info!(
+				"{} cells were not found, re-attempting them in {}ms",
+				not_found.len(),
+				delay.as_millis()
+			);
+			tokio::time::sleep(delay).await;
+			pass(not_found, false).await?;
 		}
-	}))
+		Ok(())
+	}
+	.await;
+	let (peak, average) = in_flight.stats();
+	tracing::debug!(
+		"achieved tile concurrency: peak {peak}, average {average:.2} (limit {})",
+		options.tile_workers
+	);
+	(canvas_mutex.into_inner(), result)
+}
+
+/// Rips an image from the given tile source.
+///
+/// The image is RGBA of 8 bits per channel, unless any of the tiles is deeper than that, in which
+/// case it's RGBA of 16 bits per channel, so that no precision is lost.
+pub async fn rip(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+) -> Result<image::DynamicImage, Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let canvas = prepare_canvas(Clone::clone(&client), source, options, budget).await?;
+	let cells = canvas.missing();
+	let (canvas, result) = stitch(client, source, options, budget, canvas, cells).await;
+	result.map(|_| canvas.into_image())
+}
+
+/// Rips an image like [`rip`], but reports the progress as a stream (in addition to the
+/// [callback](RipOptions::progress), if any), which ends once the rip does.
+///
+/// Prefer the callback for simple reporting, e.g. to a progress bar. The stream suits async
+/// consumers, which can drive it and the rip together (e.g. with `select!`) rather than share
+/// state with a callback.
+pub fn rip_with_progress<'a>(
+	client: impl AsRef<Client> + 'static + Send + Clone + 'a,
+	source: &'a TileSource,
+	options: &RipOptions,
+) -> (
+	impl std::future::Future<Output = Result<image::DynamicImage, Error>> + 'a,
+	impl futures::Stream<Item = Progress>,
+) {
+	let (callback, stream) = progress_stream();
+	let mut options = options.clone();
+	options.progress = Some(match options.progress.take() {
+		Some(progress) => Arc::new(move |p: &Progress| {
+			progress(p);
+			callback(p);
+		}),
+		None => callback,
+	});
+	(async move { rip(client, source, &options).await }, stream)
+}
+
+/// The result of [`rip_best_effort`].
+#[derive(Debug)]
+pub struct PartialRip {
+	/// The image as far as it got, where the [failed](PartialRip::failed) tiles are left
+	/// transparent.
+	pub image:  image::DynamicImage,
+	/// The positions of the tiles that are missing from the image (before it's
+	/// [oriented](RipOptions::auto_orient)).
+	pub failed: Vec<(usize, usize)>,
+	/// The error that stopped the rip, if any.
+	pub error:  Option<Error>,
+}
+
+impl Display for PartialRip {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}\u{00D7}{} image, {} tiles missing",
+			self.image.width(),
+			self.image.height(),
+			self.failed.len()
+		)?;
+		if let Some(error) = &self.error {
+			write!(f, " ({error})")?;
+		}
+		Ok(())
+	}
+}
+
+/// Rips an image from the given tile source like [`rip`], but if ripping the tiles fails, returns
+/// the image as far as it got instead of discarding it.
+///
+/// Fails only if the metadata of the image can't be determined, in which case there is no image to
+/// return.
+pub async fn rip_best_effort(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+) -> Result<PartialRip, Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let canvas = prepare_canvas(Clone::clone(&client), source, options, budget).await?;
+	let cells = canvas.missing();
+	let (canvas, result) = stitch(client, source, options, budget, canvas, cells).await;
+	Ok(PartialRip {
+		failed: canvas.missing(),
+		image:  canvas.into_image(),
+		error:  result.err(),
+	})
+}
+
+/// Which tiles [`rip_sample`] fetches.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum Sample {
+	/// The first tiles, in column-major order.
+	First(usize),
+	/// Tiles picked at random, reproducibly for the same seed.
+	Random {
+		/// The amount of tiles.
+		count: usize,
+		/// The seed of the random pick.
+		seed:  u64,
+	},
+}
+
+impl Sample {
+	/// The amount of tiles to sample.
+	pub fn count(&self) -> usize {
+		match *self {
+			Self::First(count) | Self::Random { count, .. } => count,
+		}
+	}
+
+	/// Picks the tiles to sample out of the given ones.
+	fn pick(&self, mut cells: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+		let count = self.count().min(cells.len());
+		if let Self::Random { seed, .. } = *self {
+			util::partial_shuffle(&mut cells, count, seed);
+		}
+		cells.truncate(count);
+		cells
+	}
+}
+
+/// The result of [`rip_sample`].
+#[derive(Debug)]
+pub struct SampledRip {
+	/// A sparse preview of the image, where the tiles that weren't sampled are left transparent.
+	pub image:   image::DynamicImage,
+	/// The positions of the sampled tiles.
+	pub sampled: Vec<(usize, usize)>,
+}
+
+impl Display for SampledRip {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}\u{00D7}{} preview of {} sampled tiles",
+			self.image.width(),
+			self.image.height(),
+			self.sampled.len()
+		)
+	}
+}
+
+/// Rips only a sample of the tiles of an image from the given tile source, as a quick check that
+/// the source and zoom level produce sensible tiles.
+///
+/// The first tile is always fetched (to determine the size of the tiles) and counts towards the
+/// sample.
+pub async fn rip_sample(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	sample: Sample,
+) -> Result<SampledRip, Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let canvas = prepare_canvas(Clone::clone(&client), source, options, budget).await?;
+	let mut cells = sample.pick(canvas.missing());
+	cells.truncate(sample.count().saturating_sub(1));
+	let (canvas, result) = stitch(client, source, options, budget, canvas, cells.clone()).await;
+	result.map(|_| SampledRip {
+		image:   canvas.into_image(),
+		sampled: std::iter::once((0, 0)).chain(cells).collect(),
+	})
+}
+
+/// Determines the dimensions of the image in pixels, e.g. to allocate a buffer for [`rip_into`].
+///
+/// This fetches the first tile to determine the size of the tiles, so consider a
+/// [tile store](RipOptions::tile_store) to avoid fetching it again in the rip.
+pub async fn determine_image_dimensions(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+) -> Result<(u32, u32), Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let (columns, rows, head, _, _) = determine_layout(client, source, options, budget).await?;
+	let (tile_width, tile_height) = tile_size(options, &head);
+	image_dimensions(columns, rows, tile_width, tile_height)
+}
+
+/// Rips an image from the given tile source into the given buffer, which must be of the
+/// dimensions of the image (see [`determine_image_dimensions`]).
+///
+/// Unlike [`rip`], this doesn't allocate the image, but it's limited to 8 bits per channel and
+/// doesn't [orient](RipOptions::auto_orient) the image or
+/// [re-attempt](RipOptions::retry_missing_after) missing tiles. The buffer is left partially
+/// written if the rip fails.
+pub async fn rip_into<I: GenericImage<Pixel = image::Rgba<u8>>>(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	buffer: &mut I,
+) -> Result<(), Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let (columns, rows, head, _, _) =
+		determine_layout(Clone::clone(&client), source, options, budget).await?;
+	let (tile_width, tile_height) = tile_size(options, &head);
+	let (width, height) = image_dimensions(columns, rows, tile_width, tile_height)?;
+	if buffer.dimensions() != (width, height) {
+		return Err(Error::BufferSizeMismatch {
+			width,
+			height,
+			actual_width: buffer.width(),
+			actual_height: buffer.height(),
+		});
+	}
+	buffer.copy_from(&head, 0, 0)?;
+
+	fetch_remaining_tiles(
+		client,
+		source,
+		options,
+		budget,
+		(columns, rows),
+		(tile_width, tile_height),
+		|(x, y), cell, _| {
+			Ok(buffer.copy_from(&cell, x as u32 * tile_width, y as u32 * tile_height)?)
+		},
+	)
 	.await
-	.unwrap();
+}
+
+/// Fetches the tiles of the image from the given tile source without stitching them, passing each
+/// tile to the sink along with its position and encoded data, in no particular order.
+///
+/// The tiles are neither [oriented](RipOptions::auto_orient) nor
+/// [re-attempted](RipOptions::retry_missing_after) if missing.
+///
+/// Returns the dimensions the stitched image would have.
+pub async fn rip_tiles(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	mut sink: impl FnMut((usize, usize), image::DynamicImage, bytes::Bytes) -> Result<(), Error>,
+) -> Result<(u32, u32), Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let (columns, rows, head, data, _) =
+		determine_layout(Clone::clone(&client), source, options, budget).await?;
+	let (tile_width, tile_height) = tile_size(options, &head);
+	let dimensions = image_dimensions(columns, rows, tile_width, tile_height)?;
+	sink((0, 0), head, data)?;
+
+	fetch_remaining_tiles(
+		client,
+		source,
+		options,
+		budget,
+		(columns, rows),
+		(tile_width, tile_height),
+		sink,
+	)
+	.await?;
+	Ok(dimensions)
+}
+
+/// Fetches the tiles of the layout of the given columns and rows, of the given tile size, but the
+/// first (which is fetched along with the layout), passing each tile to the sink along with its
+/// position and encoded data, in no particular order.
+async fn fetch_remaining_tiles(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	budget: &RetryBudget,
+	(columns, rows): (usize, usize),
+	(tile_width, tile_height): (u32, u32),
+	mut sink: impl FnMut((usize, usize), image::DynamicImage, bytes::Bytes) -> Result<(), Error>,
+) -> Result<(), Error> {
+	let progress = &TileProgress::new(options.progress.as_ref(), budget, 1, columns * rows);
+	let mut cells = futures::stream::iter((0..columns).cartesian_product(0..rows).skip(1).map(
+		|(x, y)| {
+			let client = Clone::clone(&client);
+			async move {
+				let (cell, data) =
+					fetch_tile(client.as_ref(), source, options, budget, (x, y)).await?;
+				Ok::<_, Error>(((x, y), cell, data))
+			}
+		},
+	))
+	.buffer_unordered(options.tile_workers.max(1));
+	while let Some(((x, y), cell, data)) = cells.try_next().await? {
+		tracing::trace!("fetched cell ({x},{y})");
+		check_tile_size(
+			options,
+			(columns, rows),
+			(tile_width, tile_height),
+			(x, y),
+			&cell,
+		)?;
+		let bytes = data.len();
+		sink((x, y), cell, data)?;
+		progress.advance(bytes);
+	}
+	Ok(())
+}
+
+/// Rips an image from the given tile source, streaming it to the writer as a PNG.
+///
+/// Unlike [`rip`], which keeps the whole image in memory, this fetches the tiles in rows and flushes
+/// each completed row to the encoder, so the memory usage is capped to roughly a single row of
+/// tiles and the [buffered tiles](RipOptions::max_buffered_tiles), regardless of the size of the
+/// image.
+///
+/// The tiles' EXIF orientation isn't applied, since the image is written before it's complete.
+///
+/// The writer is written on a blocking thread (see [`tokio::task::spawn_blocking`]), so that a slow
+/// writer doesn't stall the fetching.
+///
+/// The PNG is of 16 bits per channel if the first tile is deeper than 8 bits, or of 8 bits
+/// otherwise, since the depth has to be decided before any of the image is written.
+///
+/// Returns the dimensions of the image.
+pub async fn rip_png_stream(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	options: &RipOptions,
+	writer: impl std::io::Write + Send + 'static,
+) -> Result<(u32, u32), Error> {
+	let budget = &RetryBudget::new(options.max_total_retries);
+	let (columns, rows, head, _, orientation) =
+		determine_layout(Clone::clone(&client), source, options, budget).await?;
+	if let Some(orientation) = orientation {
+		tracing::warn!(
+			"the tiles have the EXIF orientation {orientation}, which isn't applied when streaming"
+		);
+	}
+	let (tile_width, tile_height) = tile_size(options, &head);
+	let (image_width, image_height) = image_dimensions(columns, rows, tile_width, tile_height)?;
+
+	let deep = is_deep(&head);
+
+	let progress = &TileProgress::new(options.progress.as_ref(), budget, 1, columns * rows);
+	// The fetched tiles are sent to the encoder over a bounded channel, so that when the encoder
+	// falls behind, the fetching pauses rather than piling up tiles in memory.
+	let (sender, mut receiver) = tokio::sync::mpsc::channel(options.max_buffered_tiles.max(1));
+	let fetch = async move {
+		let mut head = Some(head);
+		let mut cells =
+			futures::stream::iter((0..rows).flat_map(|y| (0..columns).map(move |x| (x, y))))
+				.map(|(x, y)| {
+					let head = head.take();
+					let client = Clone::clone(&client);
+					async move {
+						let cell = match head {
+							Some(head) => head,
+							None => {
+								let (cell, data) =
+									fetch_tile(client.as_ref(), source, options, budget, (x, y))
+										.await?;
+								progress.advance(data.len());
+								cell
+							}
+						};
+						check_tile_size(
+							options,
+							(columns, rows),
+							(tile_width, tile_height),
+							(x, y),
+							&cell,
+						)?;
+						Ok::<_, Error>(((x, y), cell))
+					}
+				})
+				.buffered(options.tile_workers.max(1));
+		while let Some(cell) = cells.try_next().await? {
+			if sender.send(cell).await.is_err() {
+				// The encoder failed, and its error is the one to report.
+				break;
+			}
+		}
+		Ok::<_, Error>(())
+	};
+	// The encoder writes synchronously, so it runs off the runtime, lest a slow writer stall the
+	// fetching.
+	let span = tracing::Span::current();
+	let encode = tokio::task::spawn_blocking(move || {
+		let _span = span.enter();
+		let mut encoder = png::Encoder::new(writer, image_width, image_height);
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(if deep {
+			png::BitDepth::Sixteen
+		} else {
+			png::BitDepth::Eight
+		});
+		let mut png_writer = encoder.write_header()?;
+		let mut stream = png_writer.stream_writer()?;
+		let new_band = || {
+			if deep {
+				image::DynamicImage::new_rgba16(image_width, tile_height)
+			} else {
+				image::DynamicImage::new_rgba8(image_width, tile_height)
+			}
+		};
+		let mut band = new_band();
+		while let Some(((x, y), cell)) = receiver.blocking_recv() {
+			match &mut band {
+				image::DynamicImage::ImageRgba16(band) => {
+					band.copy_from(&cell.to_rgba16(), x as u32 * tile_width, 0)?
+				}
+				band => band.copy_from(&cell, x as u32 * tile_width, 0)?,
+			}
+			if x + 1 < columns {
+				continue;
+			}
+			let band = std::mem::replace(&mut band, new_band());
+			let bytes = match band {
+				// PNG samples are big-endian.
+				image::DynamicImage::ImageRgba16(band) => band
+					.as_raw()
+					.iter()
+					.flat_map(|sample| sample.to_be_bytes())
+					.collect(),
+				band => band.into_bytes(),
+			};
+			std::io::Write::write_all(&mut stream, &bytes).map_err(Error::WriteError)?;
+			tracing::trace!("wrote row {y}");
+		}
+		stream.finish()?;
+		png_writer.finish()?;
+		Ok::<_, Error>(())
+	});
+	let (fetched, encoded) = tokio::join!(fetch, encode);
+	// A failed fetch ends the tiles early, so its error precedes the encoder's.
+	fetched?;
+	encoded.unwrap_or_else(resume_worker_panic)?;
 
-	Ok(Arc::try_unwrap(image).unwrap().into_inner())
+	Ok((image_width, image_height))
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum PageError {
 	#[error("HTTP error fetching page metadata: {0}")]
 	HttpError(#[from] reqwest::Error),
+	#[error("the item was not found, check the item ID or page URL")]
+	ItemNotFound,
 	#[error("failed to find the base image URL in the page")]
 	BaseNotFound,
 	#[error("failed to find the page title in the page")]
 	TitleNotFound,
 }
 
+/// Serializes the error like [`Error`].
+impl serde::Serialize for PageError {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let (kind, context) = match self {
+			Self::HttpError(e) => ("page_http", http_context(e)),
+			Self::ItemNotFound => ("item_not_found", serde_json::json!({})),
+			Self::BaseNotFound => ("base_not_found", serde_json::json!({})),
+			Self::TitleNotFound => ("title_not_found", serde_json::json!({})),
+		};
+		serialize_error(serializer, kind, self, context)
+	}
+}
+
+/// The URL of an image viewer of a page, see [`Page::parse`].
+static IMAGE_VIEWER_URL: LazyLock<regex::Regex> = LazyLock::new(|| {
+	regex::Regex::new(r#"<image-viewer[\s\S]+?url="(?P<url>https[^"]+)"#).unwrap()
+});
+/// The title of a page, after the site's name, see [`Page::parse`].
+static TITLE: LazyLock<regex::Regex> = LazyLock::new(|| {
+	regex::Regex::new(r"<title>\s*[^-]+-\s*(?P<title>[^<]+?)\s*</title>").unwrap()
+});
+/// The URL of an item's page in a link of a listing page, see [`Page::parse_listing`].
+static LISTING_ITEM_URL: LazyLock<regex::Regex> = LazyLock::new(|| {
+	regex::Regex::new(r#"href="(?P<url>[^"?#]*/explore-the-archive/image/[^"/?#]+)"#).unwrap()
+});
+/// The robots meta tag of a page that's excluded from search engines, see
+/// [`Page::is_error_page`].
+static NOINDEX: LazyLock<regex::Regex> = LazyLock::new(|| {
+	regex::Regex::new(r#"(?i)<meta\s+name="robots"\s+content="[^"]*noindex"#).unwrap()
+});
+/// The title of a page that wasn't found, see [`Page::is_error_page`].
+static NOT_FOUND_TITLE: LazyLock<regex::Regex> = LazyLock::new(|| {
+	regex::Regex::new(r"(?i)<title>[^<]*(404|not found|page not found)[^<]*</title>").unwrap()
+});
+
 #[derive(Debug, Hash, Default, Clone, PartialEq, PartialOrd, Eq, Ord)]
 pub struct Page {
 	pub title:    String,
@@ -292,23 +2013,49 @@ pub struct Page {
 
 impl Page {
 	pub async fn try_fetch(client: &Client, page_url: &str) -> Result<Self, PageError> {
-		let response = client.get(page_url).send().await?.text().await?;
-		let base_url = {
-			let regex =
-				regex::Regex::new(r#"<image-viewer[\s\S]+?url="(?P<url>https[^"]+)"#).unwrap();
-			regex
-				.captures(&response)
+		Self::parse(&Self::fetch_html(client, page_url).await?)
+	}
+
+	/// Fetches the HTML of the page, to be [parsed](Page::parse).
+	pub async fn fetch_html(client: &Client, page_url: &str) -> Result<String, PageError> {
+		let response = send_request(client.get(page_url)).await?;
+		if response.status() == reqwest::StatusCode::NOT_FOUND {
+			return Err(PageError::ItemNotFound);
+		}
+		Ok(response.error_for_status()?.text().await?)
+	}
+
+	/// Parses the page from its HTML.
+	///
+	/// The page's JSON-LD (structured data) is preferred where it has the metadata, falling back to
+	/// the page's elements.
+	///
+	/// Fails with [`PageError::ItemNotFound`] if the page has no image and looks like an error page
+	/// (which the site serves for unknown items with a success status).
+	pub fn parse(html: &str) -> Result<Self, PageError> {
+		let linked_data = linked_data::read(html);
+		if linked_data != linked_data::LinkedData::default() {
+			tracing::trace!("found JSON-LD metadata {linked_data:?}");
+		}
+
+		let base_url = if let Some(base_url) = linked_data.base_url {
+			base_url
+		} else {
+			match IMAGE_VIEWER_URL
+				.captures(html)
 				.and_then(|captures| captures.name("url"))
-				.ok_or(PageError::BaseNotFound)?
-				.as_str()
-				.to_owned()
+			{
+				Some(url) => url.as_str().to_owned(),
+				None if Self::is_error_page(html) => return Err(PageError::ItemNotFound),
+				None => return Err(PageError::BaseNotFound),
+			}
 		};
 
-		let title = {
-			let regex =
-				regex::Regex::new(r"<title>\s*[^-]+-\s*(?P<title>[^<]+?)\s*</title>").unwrap();
-			regex
-				.captures(&response)
+		let title = if let Some(title) = linked_data.title {
+			title
+		} else {
+			TITLE
+				.captures(html)
 				.and_then(|captures| captures.name("title"))
 				.ok_or(PageError::TitleNotFound)?
 				.as_str()
@@ -317,4 +2064,729 @@ impl Page {
 
 		Ok(Self { title, base_url })
 	}
+
+	/// Parses the base URLs of all the plates of the page from its HTML, in the order they appear.
+	///
+	/// Some pages have multiple related plates of an item (e.g. infrared, color, and raking-light
+	/// variants), each in its own image viewer, of which [`parse`](Page::parse) finds the first.
+	pub fn parse_plates(html: &str) -> Vec<String> {
+		let mut plates = Vec::<String>::new();
+		for url in IMAGE_VIEWER_URL
+			.captures_iter(html)
+			.filter_map(|captures| captures.name("url"))
+		{
+			if !plates.iter().any(|plate| plate == url.as_str()) {
+				plates.push(url.as_str().to_owned());
+			}
+		}
+		plates
+	}
+
+	/// Parses the items a listing page (e.g. search results) links to from its HTML, as the URLs of
+	/// their pages resolved against the listing page's URL, in the order they first appear.
+	pub fn parse_listing(html: &str, listing_url: &str) -> Vec<Input> {
+		let Ok(listing_url) = reqwest::Url::parse(listing_url) else {
+			return Vec::new();
+		};
+		let mut items = Vec::<Input>::new();
+		for url in LISTING_ITEM_URL
+			.captures_iter(html)
+			.filter_map(|captures| captures.name("url"))
+			.filter_map(|url| listing_url.join(url.as_str()).ok())
+		{
+			let item = Input::PageUrl(url.into());
+			if !items.contains(&item) {
+				items.push(item);
+			}
+		}
+		items
+	}
+
+	/// Whether the page looks like an error page: it's excluded from search engines, or its title
+	/// says that it wasn't found.
+	fn is_error_page(html: &str) -> bool {
+		NOINDEX.is_match(html) || NOT_FOUND_TITLE.is_match(html)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{self, MockServer, Response};
+
+	fn client() -> Arc<Client> { Arc::new(Client::new()) }
+
+	#[test]
+	fn tile_url() {
+		let source = TileSource::from("https://example.com/img".to_owned());
+		assert_eq!(
+			source.tile_url(1, 2, Zoom::new(3).unwrap()),
+			"https://example.com/img=x1-y2-z3"
+		);
+		let source = TileSource {
+			transposed: true,
+			..source
+		};
+		assert_eq!(
+			source.tile_url(1, 2, Zoom::new(3).unwrap()),
+			"https://example.com/img=y1-x2-z3"
+		);
+	}
+
+	#[tokio::test]
+	async fn rip_stitches_tiles() {
+		let server = MockServer::image(3, 2, 0);
+		let source = TileSource::from(server.base_url());
+		let image = rip(client(), &source, &RipOptions::new(Zoom::new(0).unwrap()))
+			.await
+			.unwrap()
+			.into_rgba8();
+		let size = mock::TILE_SIZE;
+		assert_eq!(image.dimensions(), (3 * size, 2 * size));
+		for (x, y) in (0..3).cartesian_product(0..2) {
+			let pixel = image.get_pixel(x as u32 * size + size / 2, y as u32 * size + size / 2);
+			assert_eq!(*pixel, mock::tile_color(x, y), "tile ({x},{y})");
+		}
+		assert_eq!(server.tile_fetches(), 6);
+	}
+
+	#[tokio::test]
+	async fn rip_into_places_tiles() {
+		let server = MockServer::image(3, 2, 0);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions::new(Zoom::new(0).unwrap());
+		let size = mock::TILE_SIZE;
+		let mut buffer = image::RgbaImage::new(3 * size, 2 * size);
+		rip_into(client(), &source, &options, &mut buffer)
+			.await
+			.unwrap();
+		for (x, y) in (0..3).cartesian_product(0..2) {
+			let (left, top) = (x as u32 * size, y as u32 * size);
+			for (dx, dy) in [(0, 0), (size - 1, size - 1)] {
+				assert_eq!(
+					*buffer.get_pixel(left + dx, top + dy),
+					mock::tile_color(x, y),
+					"tile ({x},{y})"
+				);
+			}
+		}
+
+		let mut small = image::RgbaImage::new(size, size);
+		let result = rip_into(client(), &source, &options, &mut small).await;
+		assert!(
+			matches!(result, Err(Error::BufferSizeMismatch { width, height, .. }) if (width, height) == (3 * size, 2 * size)),
+			"{result:?}"
+		);
+	}
+
+	#[tokio::test]
+	async fn rip_tiles_sinks_each_tile() {
+		let server = MockServer::image(3, 2, 0);
+		let source = TileSource::from(server.base_url());
+		let mut tiles = Vec::new();
+		let dimensions = rip_tiles(
+			client(),
+			&source,
+			&RipOptions::new(Zoom::new(0).unwrap()),
+			|position, tile, data| {
+				assert_eq!(data, mock::tile(position.0, position.1));
+				tiles.push((position, tile.into_rgba8().get_pixel(0, 0).to_owned()));
+				Ok(())
+			},
+		)
+		.await
+		.unwrap();
+		assert_eq!(dimensions, (3 * mock::TILE_SIZE, 2 * mock::TILE_SIZE));
+		tiles.sort_by_key(|(position, _)| *position);
+		let expected = (0..3)
+			.cartesian_product(0..2)
+			.map(|(x, y)| ((x, y), mock::tile_color(x, y)))
+			.collect::<Vec<_>>();
+		assert_eq!(tiles, expected);
+	}
+
+	/// A writer of [`rip_png_stream`] whose bytes can be read after the rip.
+	#[derive(Debug, Clone, Default)]
+	struct Streamed(Arc<std::sync::Mutex<Vec<u8>>>);
+
+	impl std::io::Write for Streamed {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0.lock().unwrap().extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+	}
+
+	/// A writer that takes its time with each write, noting the tiles fetched by the end of it.
+	struct SlowWriter {
+		server:  Arc<MockServer>,
+		fetches: Arc<std::sync::Mutex<Vec<usize>>>,
+	}
+
+	impl std::io::Write for SlowWriter {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			std::thread::sleep(Duration::from_millis(100));
+			self.fetches
+				.lock()
+				.unwrap()
+				.push(self.server.tile_fetches());
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+	}
+
+	#[tokio::test]
+	async fn slow_writer_bounds_the_buffered_tiles() {
+		let server = Arc::new(MockServer::image(6, 4, 0));
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			grid: Some((6, 4)),
+			tile_workers: 2,
+			max_buffered_tiles: 3,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let fetches = Arc::default();
+		let writer = SlowWriter {
+			server:  Arc::clone(&server),
+			fetches: Arc::clone(&fetches),
+		};
+		rip_png_stream(client(), &source, &options, writer)
+			.await
+			.unwrap();
+		// The first write is of the header, before any tile is encoded, so all the tiles fetched by
+		// its end are buffered or being fetched.
+		let fetched = fetches.lock().unwrap()[0];
+		assert!(
+			fetched >= options.max_buffered_tiles,
+			"only {fetched} tiles were fetched while the writer was busy"
+		);
+		assert!(
+			fetched <= options.max_buffered_tiles + options.tile_workers + 1,
+			"{fetched} tiles were fetched before any was written"
+		);
+		assert_eq!(server.tile_fetches(), 6 * 4);
+	}
+
+	#[tokio::test]
+	async fn tile_window_matches_full_buffer() {
+		let server = MockServer::image(5, 3, 0);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			// Fewer buffered tiles than a row, so that the fetching waits for the writing.
+			max_buffered_tiles: 2,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let streamed = Streamed::default();
+		let dimensions = rip_png_stream(client(), &source, &options, streamed.clone())
+			.await
+			.unwrap();
+		let streamed = streamed.0.lock().unwrap().clone();
+
+		// The whole image, encoded like the stream.
+		let image = rip(client(), &source, &options).await.unwrap().into_rgba8();
+		assert_eq!(image.dimensions(), dimensions);
+		let mut buffered = Vec::new();
+		let mut encoder = png::Encoder::new(&mut buffered, image.width(), image.height());
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(png::BitDepth::Eight);
+		let mut writer = encoder.write_header().unwrap();
+		let mut stream = writer.stream_writer().unwrap();
+		std::io::Write::write_all(&mut stream, image.as_raw()).unwrap();
+		stream.finish().unwrap();
+		writer.finish().unwrap();
+
+		assert!(
+			streamed == buffered,
+			"the streamed PNG differs from the buffered one"
+		);
+	}
+
+	#[tokio::test]
+	async fn empty_grid_fetches_nothing() {
+		let server = MockServer::image(3, 2, 0);
+		let source = TileSource::from(server.base_url());
+		for grid in [(0, 2), (3, 0)] {
+			let options = RipOptions {
+				grid: Some(grid),
+				..RipOptions::new(Zoom::new(0).unwrap())
+			};
+			let result = rip(client(), &source, &options).await;
+			assert!(
+				matches!(result, Err(Error::EmptyDimensions { columns, rows }) if (columns, rows) == grid),
+				"{result:?}"
+			);
+		}
+		assert_eq!(server.requests(), Vec::<String>::new());
+	}
+
+	/// A retry policy of a constant backoff of 20ms, for up to 100ms in total.
+	const THROTTLED: RetryPolicy = RetryPolicy {
+		max_retries:       100,
+		initial_backoff:   Duration::from_millis(20),
+		max_backoff:       Duration::from_millis(20),
+		max_backoff_total: Duration::from_millis(100),
+	};
+
+	/// A server of a 2×1 image whose tile (1,0) is throttled (HTTP 429) the given amount of times.
+	fn throttling_server(throttles: usize) -> MockServer {
+		let throttled = AtomicUsize::new(0);
+		MockServer::start(move |method, path| match (method, mock::parse_tile(path)) {
+			("GET", Some((1, 0, _)))
+				if throttled.fetch_add(1, atomic::Ordering::SeqCst) < throttles =>
+			{
+				Response::status(429)
+			}
+			(_, Some((x, y, 0))) if x < 2 && y < 1 => Response::ok(mock::tile(x, y)),
+			_ => Response::status(404),
+		})
+	}
+
+	#[tokio::test]
+	async fn reports_backoff() {
+		let server = throttling_server(2);
+		let source = TileSource::from(server.base_url());
+		let (callback, progress) = progress_stream();
+		let options = RipOptions {
+			retry: THROTTLED,
+			progress: Some(callback),
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		rip(client(), &source, &options).await.unwrap();
+		drop(options);
+		let last = progress.collect::<Vec<_>>().await.pop().unwrap();
+		assert_eq!(last.tiles_done, 2);
+		assert_eq!(last.backed_off, Duration::from_millis(40));
+	}
+
+	#[tokio::test]
+	async fn max_backoff_total_ends_retries() {
+		let server = throttling_server(usize::MAX);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			retry: THROTTLED,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let result = rip(client(), &source, &options).await;
+		assert!(
+			matches!(&result, Err(Error::HttpError(e)) if e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)),
+			"{result:?}"
+		);
+		// The first attempt, and 5 retries of 20ms until the total of 100ms.
+		let attempts = server
+			.requests()
+			.iter()
+			.filter(|request| {
+				request.starts_with("GET ") && mock::parse_tile(&request[4..]) == Some((1, 0, 0))
+			})
+			.count();
+		assert_eq!(attempts, 6);
+	}
+
+	#[tokio::test]
+	async fn slow_probes_time_out() {
+		let server = MockServer::start(|method, path| match (method, mock::parse_tile(path)) {
+			("HEAD", _) => Response::ok(Vec::new()).with_delay(Duration::from_secs(5)),
+			(_, Some((x, y, _))) => Response::ok(mock::tile(x, y)),
+			_ => Response::status(404),
+		});
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			metadata_timeout: Some(Duration::from_millis(200)),
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let started = Instant::now();
+		let result = rip(client(), &source, &options).await;
+		assert!(matches!(result, Err(Error::MetadataTimeout)), "{result:?}");
+		assert!(started.elapsed() < Duration::from_secs(5));
+	}
+
+	#[tokio::test]
+	async fn zoom_probing_stops_at_the_highest() {
+		// A server that serves every zoom level, e.g. by upscaling.
+		let server = MockServer::start(|_, path| match mock::parse_tile(path) {
+			Some((x, y, _)) => Response::ok(mock::tile(x, y)),
+			None => Response::status(404),
+		});
+		let source = TileSource::from(server.base_url());
+		let zoom = determine_max_zoom(client(), &source, 4, RetryPolicy::NONE)
+			.await
+			.unwrap();
+		assert_eq!(zoom, Zoom::HIGHEST);
+		let levels = server
+			.requests()
+			.iter()
+			.filter_map(|request| mock::parse_tile(&request[5..]))
+			.map(|(_, _, z)| z)
+			.sorted()
+			.collect::<Vec<_>>();
+		assert_eq!(levels, (1..=Zoom::HIGHEST.level()).collect::<Vec<_>>());
+	}
+
+	#[tokio::test]
+	async fn best_effort_keeps_the_tiles_before_a_failure() {
+		let server = MockServer::start(|_, path| match mock::parse_tile(path) {
+			Some((2, 1, _)) => Response::status(500),
+			Some((x, y, 0)) if x < 3 && y < 2 => Response::ok(mock::tile(x, y)),
+			_ => Response::status(404),
+		});
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			grid: Some((3, 2)),
+			// One tile at a time in column-major order, so the failing tile is the last one.
+			tile_workers: 1,
+			retry: RetryPolicy::NONE,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let partial = rip_best_effort(client(), &source, &options).await.unwrap();
+		assert!(
+			matches!(&partial.error, Some(Error::HttpError(e)) if e.status() == Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR)),
+			"{:?}",
+			partial.error
+		);
+		assert_eq!(partial.failed, [(2, 1)]);
+		let image = partial.image.into_rgba8();
+		let size = mock::TILE_SIZE;
+		assert_eq!(image.dimensions(), (3 * size, 2 * size));
+		for (x, y) in (0..3).cartesian_product(0..2) {
+			let expected = match (x, y) {
+				(2, 1) => image::Rgba([0; 4]),
+				_ => mock::tile_color(x, y),
+			};
+			let pixel = image.get_pixel(x as u32 * size + size / 2, y as u32 * size + size / 2);
+			assert_eq!(*pixel, expected, "tile ({x},{y})");
+		}
+	}
+
+	#[tokio::test]
+	async fn exhausted_retry_budget_fails_the_rip() {
+		// Every tile but the first fails.
+		let server = MockServer::start(|_, path| match mock::parse_tile(path) {
+			Some((0, 0, _)) => Response::ok(mock::tile(0, 0)),
+			_ => Response::status(503),
+		});
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			grid: Some((4, 4)),
+			tile_workers: 4,
+			retry: THROTTLED,
+			max_total_retries: Some(3),
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let result = rip(client(), &source, &options).await;
+		assert!(
+			matches!(result, Err(Error::RetryBudgetExhausted(3))),
+			"{result:?}"
+		);
+		// The rip fails once the retries of all the tiles exceed the budget, rather than once each
+		// tile exhausts its own: the first tile, the first attempts of the tiles being fetched, and
+		// the retries of the budget.
+		let requests = server.requests().len();
+		assert!(requests <= 1 + 4 + 3, "{requests} requests");
+	}
+
+	/// A server of a 2×1 image whose tile (1,0) first responds with the given responses.
+	fn flaky_server(responses: Vec<Response>) -> MockServer {
+		let responses = std::sync::Mutex::new(responses.into_iter());
+		MockServer::start(move |method, path| match (method, mock::parse_tile(path)) {
+			("GET", Some((1, 0, 0))) => {
+				(responses.lock().unwrap().next()).unwrap_or_else(|| Response::ok(mock::tile(1, 0)))
+			}
+			(_, Some((x, y, 0))) if x < 2 && y < 1 => Response::ok(mock::tile(x, y)),
+			_ => Response::status(404),
+		})
+	}
+
+	/// The amount of times the tile (1,0) of a [`flaky_server`] was fetched.
+	fn flaky_fetches(server: &MockServer) -> usize {
+		server
+			.requests()
+			.iter()
+			.filter(|request| *request == &format!("GET {}=x1-y0-z0", mock::IMAGE_PATH))
+			.count()
+	}
+
+	#[tokio::test]
+	async fn missing_tile_is_found_by_the_deferred_pass() {
+		let server = flaky_server(vec![Response::status(404)]);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			retry_missing_after: Some(Duration::from_millis(50)),
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let started = Instant::now();
+		let image = rip(client(), &source, &options).await.unwrap().into_rgba8();
+		assert!(started.elapsed() >= Duration::from_millis(50));
+		assert_eq!(*image.get_pixel(mock::TILE_SIZE, 0), mock::tile_color(1, 0));
+		assert_eq!(flaky_fetches(&server), 2);
+
+		// Without the deferred pass, the missing tile fails the rip.
+		let server = flaky_server(vec![Response::status(404)]);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions::new(Zoom::new(0).unwrap());
+		let result = rip(client(), &source, &options).await;
+		assert!(matches!(result, Err(Error::TileMissing(_))), "{result:?}");
+		assert_eq!(flaky_fetches(&server), 1);
+	}
+
+	#[tokio::test]
+	async fn partial_tile_is_refetched() {
+		let mut partial = mock::tile(1, 0);
+		partial.truncate(partial.len() / 2);
+		let server = flaky_server(vec![Response {
+			status: 206,
+			body: partial,
+			..Response::default()
+		}]);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			retry: THROTTLED,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let image = rip(client(), &source, &options).await.unwrap().into_rgba8();
+		assert_eq!(*image.get_pixel(mock::TILE_SIZE, 0), mock::tile_color(1, 0));
+		assert_eq!(flaky_fetches(&server), 2);
+	}
+
+	#[tokio::test]
+	async fn corrupt_tile_is_fetched_again() {
+		// A PNG that's still recognizable as one, but whose data was corrupted on the way.
+		let mut corrupt = mock::tile(1, 0);
+		let middle = corrupt.len() / 2;
+		corrupt[middle..].iter_mut().for_each(|byte| *byte = !*byte);
+		let server = flaky_server(vec![Response::ok(corrupt.clone())]);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			retry: THROTTLED,
+			decode_retries: 2,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let image = rip(client(), &source, &options).await.unwrap().into_rgba8();
+		assert_eq!(*image.get_pixel(mock::TILE_SIZE, 0), mock::tile_color(1, 0));
+		assert_eq!(flaky_fetches(&server), 2);
+
+		// Without decode retries, the corrupt tile fails the rip.
+		let server = flaky_server(vec![Response::ok(corrupt)]);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			decode_retries: 0,
+			..options
+		};
+		let result = rip(client(), &source, &options).await;
+		assert!(matches!(result, Err(Error::ImageError(_))), "{result:?}");
+		assert_eq!(flaky_fetches(&server), 1);
+	}
+
+	#[tokio::test]
+	async fn honors_retry_after() {
+		let server = flaky_server(vec![Response::status(503).with_header("Retry-After", "1")]);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			// The requested delay is longer than the backoff, but within the total.
+			retry: RetryPolicy {
+				max_backoff_total: Duration::from_secs(5),
+				..THROTTLED
+			},
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let started = Instant::now();
+		let image = rip(client(), &source, &options).await.unwrap().into_rgba8();
+		assert!(started.elapsed() >= Duration::from_secs(1));
+		assert_eq!(*image.get_pixel(mock::TILE_SIZE, 0), mock::tile_color(1, 0));
+		assert_eq!(flaky_fetches(&server), 2);
+	}
+
+	#[tokio::test]
+	async fn failing_probe_aborts_the_others() {
+		// The probe of the second column fails right away, while the rest are slow to succeed.
+		let server = MockServer::start(|_, path| match mock::parse_tile(path) {
+			Some((1, _, _)) => Response::status(500),
+			Some((x, y, _)) => {
+				Response::ok(mock::tile(x, y)).with_delay(Duration::from_millis(500))
+			}
+			None => Response::status(404),
+		});
+		let source = TileSource::from(server.base_url());
+		let started = Instant::now();
+		let result = determine_columns(
+			client(),
+			&source,
+			Zoom::new(0).unwrap(),
+			4,
+			RetryPolicy::NONE,
+		)
+		.await;
+		assert!(result.is_err());
+		assert!(started.elapsed() < Duration::from_millis(500));
+		// The other workers don't go on probing once their requests are done. The requests that were
+		// on their way when they were aborted may still reach the server meanwhile.
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		let requests = server.requests().len();
+		tokio::time::sleep(Duration::from_secs(1)).await;
+		assert_eq!(server.requests().len(), requests);
+	}
+
+	#[tokio::test]
+	async fn probe_panic_is_resumed() {
+		let server = MockServer::start(|_, _| Response::status(500));
+		let source = TileSource {
+			retry_classifier: Some(Arc::new(|_, _| panic!("classifier panicked"))),
+			..TileSource::from(server.base_url())
+		};
+		let probe = determine_max_zoom(client(), &source, 2, RetryPolicy::NONE);
+		let panic = futures::FutureExt::catch_unwind(std::panic::AssertUnwindSafe(probe))
+			.await
+			.unwrap_err();
+		// The panic is the worker's own, rather than one about failing to join it.
+		assert_eq!(panic.downcast_ref::<&str>(), Some(&"classifier panicked"));
+	}
+
+	#[tokio::test]
+	async fn dropped_rip_stops_fetching() {
+		let server = MockServer::start(|_, path| match mock::parse_tile(path) {
+			Some((x, y, 0)) if x < 4 && y < 4 => {
+				Response::ok(mock::tile(x, y)).with_delay(Duration::from_millis(100))
+			}
+			_ => Response::status(404),
+		});
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			grid: Some((4, 4)),
+			tile_workers: 2,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let rip = rip(client(), &source, &options);
+		assert!(tokio::time::timeout(Duration::from_millis(250), rip)
+			.await
+			.is_err());
+		// The requests that were on their way when the rip was dropped may still reach the server.
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		let fetches = server.tile_fetches();
+		assert!(fetches < 16, "{fetches} tiles fetched");
+		tokio::time::sleep(Duration::from_millis(500)).await;
+		assert_eq!(server.tile_fetches(), fetches);
+	}
+
+	#[tokio::test]
+	async fn deep_tile_promotes_the_image() {
+		// Samples that 8 bits can't represent.
+		let deep = image::Rgba([0x1234, 0x5678, 0x9abc, 0xffff]);
+		let mut deep_tile = Vec::new();
+		image::DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(
+			mock::TILE_SIZE,
+			mock::TILE_SIZE,
+			deep,
+		))
+		.write_to(
+			&mut Cursor::new(&mut deep_tile),
+			image::ImageOutputFormat::Png,
+		)
+		.unwrap();
+		// The first tile is of 8 bits, so the image is promoted once the deep tile is placed.
+		let server = MockServer::start(move |_, path| match mock::parse_tile(path) {
+			Some((0, 0, 0)) => Response::ok(mock::tile(0, 0)),
+			Some((1, 0, 0)) => Response::ok(deep_tile.clone()),
+			_ => Response::status(404),
+		});
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			grid: Some((2, 1)),
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let image = rip(client(), &source, &options).await.unwrap();
+		let image::DynamicImage::ImageRgba16(image) = image else {
+			panic!("the image is {:?} rather than RGBA16", image.color());
+		};
+		assert_eq!(*image.get_pixel(mock::TILE_SIZE, 0), deep);
+		let shallow = mock::tile_color(0, 0)
+			.0
+			.map(|sample| u16::from(sample) * 257);
+		assert_eq!(image.get_pixel(0, 0).0, shallow);
+	}
+
+	#[tokio::test]
+	async fn tile_concurrency_is_bounded() {
+		let (active, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+		let server = {
+			let (active, peak) = (Arc::clone(&active), Arc::clone(&peak));
+			MockServer::start(move |_, path| match mock::parse_tile(path) {
+				Some((x, y, 0)) if x < 4 && y < 4 => {
+					let current = active.fetch_add(1, atomic::Ordering::SeqCst) + 1;
+					peak.fetch_max(current, atomic::Ordering::SeqCst);
+					std::thread::sleep(Duration::from_millis(30));
+					active.fetch_sub(1, atomic::Ordering::SeqCst);
+					Response::ok(mock::tile(x, y))
+				}
+				_ => Response::status(404),
+			})
+		};
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			grid: Some((4, 4)),
+			tile_workers: 3,
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		rip(client(), &source, &options).await.unwrap();
+		let peak = peak.load(atomic::Ordering::SeqCst);
+		assert!((2..=3).contains(&peak), "peak of {peak} tiles at once");
+	}
+
+	#[tokio::test]
+	async fn progress_stream_alongside_the_rip() {
+		let server = MockServer::image(3, 2, 0);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions::new(Zoom::new(0).unwrap());
+		let (rip, progress) = rip_with_progress(client(), &source, &options);
+		// The stream ends once the rip does, so both are driven together to completion.
+		let (image, progress) = tokio::join!(rip, progress.collect::<Vec<_>>());
+		image.unwrap();
+		let tiles = progress
+			.iter()
+			.filter(|progress| progress.phase == Phase::Tiles)
+			.map(|progress| (progress.tiles_done, progress.tiles_total))
+			.collect::<Vec<_>>();
+		assert_eq!(tiles.first(), Some(&(1, 6)));
+		assert_eq!(tiles.last(), Some(&(6, 6)));
+		assert!(tiles.is_sorted());
+	}
+
+	#[test]
+	fn canonical_urls() {
+		let template = Input::DEFAULT_PAGE_URL_TEMPLATE;
+		let page = "https://www.deadseascrolls.org.il/explore-the-archive/image/B-1";
+		let base = "https://lh5.ggpht.com/abc";
+		let cases = [
+			(
+				Input::ItemId("B-1".into()),
+				template,
+				Input::PageUrl(page.into()),
+			),
+			(
+				Input::PageUrl(page.into()),
+				template,
+				Input::PageUrl(page.into()),
+			),
+			(
+				Input::BaseUrl(base.into()),
+				template,
+				Input::BaseUrl(base.into()),
+			),
+			(
+				Input::ItemId("B-1".into()),
+				"https://example.com/{id}",
+				Input::ItemId("https://example.com/B-1".into()),
+			),
+			(
+				Input::BaseUrl("https://example.com/img".into()),
+				template,
+				Input::ItemId("https://example.com/img".into()),
+			),
+		];
+		for (input, template, parsed) in cases {
+			let url = input.to_url(template);
+			assert_eq!(url, parsed.as_ref(), "{input:?}");
+			assert_eq!(url.parse::<Input>().unwrap(), parsed, "{input:?}");
+		}
+	}
 }
@@ -0,0 +1,128 @@
+//! JSON-LD, the structured data of a page, which is more stable than its visible elements.
+
+use std::sync::LazyLock;
+
+use serde_json::Value;
+
+/// A JSON-LD script of a page.
+static SCRIPT: LazyLock<regex::Regex> = LazyLock::new(|| {
+	regex::Regex::new(
+		r#"(?is)<script[^>]+type\s*=\s*["']application/ld\+json["'][^>]*>(?P<json>.*?)</script>"#,
+	)
+	.unwrap()
+});
+
+/// The metadata of a page from its JSON-LD.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LinkedData {
+	/// The name of the item.
+	pub title:    Option<String>,
+	/// The base URL of the image, see [`crate::Input::BaseUrl`].
+	pub base_url: Option<String>,
+}
+
+/// The types of nodes that describe the site rather than the item, whose names aren't the item's.
+const SITE_TYPES: &[&str] = &["WebSite", "Organization", "BreadcrumbList"];
+
+/// Reads the metadata from the JSON-LD scripts of the page's HTML, taking each field from the
+/// first node that has it, except for nodes that describe the site. Malformed scripts are skipped.
+pub fn read(html: &str) -> LinkedData {
+	let mut data = LinkedData::default();
+	let scripts = SCRIPT
+		.captures_iter(html)
+		.filter_map(|captures| captures.name("json"))
+		.filter_map(|json| serde_json::from_str::<Value>(json.as_str()).ok());
+	for script in scripts {
+		let nodes = nodes(&script).into_iter().filter(|node| {
+			node.get("@type")
+				.and_then(Value::as_str)
+				.is_none_or(|kind| !SITE_TYPES.contains(&kind))
+		});
+		for node in nodes {
+			if data.title.is_none() {
+				data.title = node
+					.get("name")
+					.and_then(Value::as_str)
+					.map(str::trim)
+					.filter(|title| !title.is_empty())
+					.map(str::to_owned);
+			}
+			if data.base_url.is_none() {
+				data.base_url = node.get("image").and_then(base_url);
+			}
+		}
+	}
+	data
+}
+
+/// The nodes of the JSON-LD value: the value itself, the elements of an array, or the nodes of a
+/// `@graph`.
+fn nodes(value: &Value) -> Vec<&Value> {
+	match value {
+		Value::Array(values) => values.iter().flat_map(nodes).collect(),
+		Value::Object(object) => match object.get("@graph") {
+			Some(graph) => nodes(graph),
+			None => vec![value],
+		},
+		_ => Vec::new(),
+	}
+}
+
+/// The base URL of the image of an `image` property, which is a URL, an `ImageObject`, or an array
+/// of them. Only images of the tile server count, with their parameters (after `=`) removed.
+fn base_url(image: &Value) -> Option<String> {
+	match image {
+		Value::String(url) => {
+			let base_url = url.split('=').next().unwrap_or(url);
+			(base_url.starts_with("https") && base_url.contains("ggpht.com"))
+				.then(|| base_url.to_owned())
+		}
+		Value::Array(images) => images.iter().find_map(base_url),
+		Value::Object(object) => ["contentUrl", "url"]
+			.into_iter()
+			.find_map(|key| object.get(key).and_then(base_url)),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reads_the_item_of_a_graph() {
+		let html = r#"<html><head>
+			<script type="application/ld+json">{ not json</script>
+			<script type='application/ld+json'>
+			{
+				"@context": "https://schema.org",
+				"@graph": [
+					{
+						"@type": "WebSite",
+						"name": "Google Arts & Culture",
+						"image": "https://lh3.ggpht.com/site-logo=s64"
+					},
+					{
+						"@type": "ImageObject",
+						"name": "  The Starry Night ",
+						"contentUrl": "https://lh3.ggpht.com/starry-night=s0",
+						"image": [
+							"http://lh3.ggpht.com/insecure",
+							{ "@type": "ImageObject", "url": "https://example.com/elsewhere.jpg" },
+							{ "@type": "ImageObject", "contentUrl": "https://lh3.ggpht.com/starry-night=w1024" }
+						]
+					}
+				]
+			}
+			</script>
+		</head></html>"#;
+		assert_eq!(
+			read(html),
+			LinkedData {
+				title:    Some("The Starry Night".to_owned()),
+				base_url: Some("https://lh3.ggpht.com/starry-night".to_owned()),
+			}
+		);
+		assert_eq!(read("<html></html>"), LinkedData::default());
+	}
+}
@@ -1,91 +1,692 @@
+mod archive;
+mod audit;
+mod batch;
+mod compare;
+mod doctor;
+mod events;
+mod hook;
+mod iiif;
+mod manifest;
+// The mock server is shared with the library's tests, and this crate uses only part of it.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "mock.rs"]
+mod mock;
+mod record;
+
 use std::{
-	io::{Cursor, IsTerminal, Write},
+	io::{BufWriter, Cursor, IsTerminal, Write},
 	path::PathBuf,
 	process::ExitCode,
-	sync::Arc,
-	time::{Instant, SystemTime},
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Arc,
+	},
+	time::{Duration, Instant, SystemTime},
 };
 
 use clap::Parser;
-use image::ImageOutputFormat;
+use futures::{FutureExt as _, StreamExt as _, TryStreamExt as _};
+use image::{DynamicImage, ImageOutputFormat};
+use itertools::Itertools as _;
 use tokio::fs;
 use tracing::{metadata::LevelFilter, Instrument};
-use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
+use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _, Layer as _};
 
-const DEFAULT_EXTENSION: &str = "png";
 const OUTPUT_HELP: &str = const_format::formatcp!(
-	"Output file name. Default: <Item ID>.{} or {}_<unix-ms>.{} if the item ID cannot be determined.",
-	DEFAULT_EXTENSION,
+	"Output file name. Default: <Item ID>.<format extension> or {}_<unix-ms>.<format extension> if \
+	 the item ID cannot be determined.",
 	env!("CARGO_PKG_NAME"),
-	DEFAULT_EXTENSION
 );
 
 #[derive(clap::Parser)]
-#[clap(author, version, about)]
+#[clap(
+	author,
+	version,
+	about,
+	args_conflicts_with_subcommands = true,
+	subcommand_negates_reqs = true
+)]
 struct Cli {
-	/// URL to the image page, image base, or item ID.
-	image:   String,
-	/// The zoom / resolution level. Must be >= 0. Leave unspecified for maximum.
-	#[clap(short, long, value_parser = cli_validate_zoom)]
-	zoom:    Option<usize>,
+	#[clap(subcommand)]
+	command:                 Option<Command>,
+	/// URLs to the image pages, image bases, or item IDs. Multiple images are each written to
+	/// their auto-generated file name.
+	#[clap(required_unless_present_any = ["batch_csv", "from_manifest", "from_tiles"])]
+	images:                  Vec<String>,
+	/// Rip the images listed in this CSV file, with the columns input, zoom, format, and output
+	/// (all but the input may be empty), and print the results as CSV. See the README.
+	#[clap(long, value_name = "PATH", conflicts_with_all = ["images", "output"])]
+	batch_csv:               Option<PathBuf>,
+	/// The zoom / resolution level, from 0 to 30. Leave unspecified for maximum, or "smart" for the
+	/// highest level that adds detail in proportion to its size (see the README).
+	#[clap(short, long, value_parser = parse_zoom)]
+	zoom:                    Option<Zoom>,
+	/// The maximum ratio of a zoom level's growth in bytes to its growth in detail over the level
+	/// below it for the smart zoom level to pick it. Higher picks higher levels.
+	#[clap(long, default_value_t = deathrip::DEFAULT_KNEE_THRESHOLD)]
+	smart_zoom_threshold:    f64,
+	/// The amount of tiles of each zoom level to fetch to measure it for the smart zoom level.
+	#[clap(long, default_value_t = 4)]
+	smart_zoom_samples:      usize,
+	/// The highest zoom level to use when the zoom is unspecified. Ignored when the zoom is specified.
+	#[clap(long)]
+	max_zoom:                Option<deathrip::Zoom>,
+	/// The lowest zoom level to use when the zoom is unspecified or smart, e.g. so that the smart
+	/// zoom level doesn't pick a thumbnail of a small image. Ignored when the zoom is specified.
+	#[clap(long)]
+	min_zoom:                Option<deathrip::Zoom>,
 	/// The output file. If missing, it will be auto-generated, unless the output is piped.
 	#[clap(help = OUTPUT_HELP, short, long)]
-	output:  Option<PathBuf>,
-	/// The output format. Possible options are: png | jp[e]g[<Q>] | bmp | gif | tiff | tga | ico | [open]exr | farbfeld.
-	/// The variable Q is a number within [0,100] that controls quality (higher is better).
-	#[clap(short, long, default_value = "png", value_parser = parse_format)]
-	format:  ImageOutputFormat,
+	output:                  Option<PathBuf>,
+	/// The title of the image, which names the output file by default, instead of the title scraped
+	/// from its page.
+	#[clap(long, conflicts_with_all = ["batch_csv", "ignore_title"])]
+	title:                   Option<String>,
+	/// Name the output file by the time of the rip (as when no title is found), even when a title is
+	/// found.
+	#[clap(long)]
+	ignore_title:            bool,
+	/// The output format.
+	#[clap(help = format_help(), short, long, default_value = "png", value_parser = FormatParser, hide_possible_values = true)]
+	format:                  Format,
+	/// Print the image to the standard output as a base64 data URI (data:<MIME type>;base64,...) of
+	/// the output format rather than write it to a file, e.g. to embed it in HTML or Markdown.
+	#[clap(long, conflicts_with_all = ["output", "tile_window", "tiles_dir", "all_zooms", "animate", "progressive", "manifest", "hash_manifest", "tile_audit"])]
+	data_uri:                bool,
 	/// Verbose output. Overridden by quiet.
-	#[clap(short, long)]
-	verbose: bool,
+	#[clap(short, long, global = true)]
+	verbose:                 bool,
 	/// Suppress output. Overrides verbose.
-	#[clap(short, long)]
-	quiet:   bool,
-}
-
-fn parse_format(format: &str) -> Result<ImageOutputFormat, &'static str> {
-	let format = format.to_ascii_lowercase();
-	match format.as_str() {
-		"png" => Ok(ImageOutputFormat::Png),
-		"bmp" => Ok(ImageOutputFormat::Bmp),
-		"gif" => Ok(ImageOutputFormat::Gif),
-		"ico" => Ok(ImageOutputFormat::Ico),
-		"farbfeld" => Ok(ImageOutputFormat::Farbfeld),
-		"tga" => Ok(ImageOutputFormat::Tga),
-		"exr" | "openexr" => Ok(ImageOutputFormat::OpenExr),
-		"tiff" => Ok(ImageOutputFormat::Tiff),
-		_ => {
-			let jpg_len = if format.starts_with("jpg") {
-				Some(3)
-			} else if format.starts_with("jpeg") {
-				Some(4)
-			} else {
-				None
-			};
-			if let Some(jpg_len) = jpg_len {
-				let quality = &format[jpg_len..];
-				if quality.is_empty() {
-					Ok(ImageOutputFormat::Jpeg(100))
-				} else if let Ok(quality) = quality.parse::<u8>() {
-					Ok(ImageOutputFormat::Jpeg(quality.min(100)))
-				} else {
-					Err("couldn't parse the quality, it should be a number within [0,100]")
+	#[clap(short, long, global = true)]
+	quiet:                   bool,
+	/// On failure, print the error to the standard error as a line of JSON, with its kind, message,
+	/// context (e.g. the URL and HTTP status of a failed request), and input, rather than as a log
+	/// message. With multiple images, each failed image is printed.
+	#[clap(long)]
+	json_errors:             bool,
+	/// Only log warnings and errors, and print a one-line summary of each ripped image to stderr.
+	#[clap(long, global = true, conflicts_with_all = ["verbose", "quiet"])]
+	summary_only:            bool,
+	/// Time limit in seconds for determining the image metadata (page, zoom level and dimensions),
+	/// separate from the time it takes to download the image.
+	#[clap(long, value_parser = parse_seconds)]
+	metadata_timeout:        Option<Duration>,
+	/// Time limit in seconds for connecting to a server, which should be quick even when the server
+	/// is slow to respond.
+	#[clap(long, global = true, value_parser = parse_seconds)]
+	connect_timeout:         Option<Duration>,
+	/// Time limit in seconds for each request, from connecting until its response is read. Tiles
+	/// that time out are retried. The metadata time limit still bounds all of its requests
+	/// together.
+	#[clap(long, global = true, value_parser = parse_seconds)]
+	read_timeout:            Option<Duration>,
+	/// Limit the download bandwidth of the tiles to this many bytes per second, optionally with a K,
+	/// M, or G suffix (powers of 1024), e.g. 2M. Shared by all the images.
+	#[clap(long, value_name = "RATE", value_parser = parse_rate)]
+	limit_rate:              Option<u64>,
+	/// The bandwidth limit of --limit-rate, shared by all the images.
+	#[clap(skip)]
+	bandwidth_limit:         Option<Arc<deathrip::BandwidthLimit>>,
+	/// The maximum amount of idle connections to keep open to each host for reuse. Unlimited by
+	/// default. Each tile worker takes a connection of its own (over HTTP/1.1, the only version
+	/// spoken), so less than --tile-workers makes tile requests reconnect, see the README.
+	#[clap(long, global = true, value_name = "N")]
+	connection_pool_size:    Option<usize>,
+	/// Seconds to keep an idle connection open for reuse. 90 by default.
+	#[clap(long, global = true, value_parser = parse_seconds)]
+	connection_idle_timeout: Option<Duration>,
+	/// The string that separates the image base URL from the tile parameters.
+	#[clap(long, global = true, default_value = deathrip::TileSource::DEFAULT_PREFIX)]
+	tile_prefix:             String,
+	/// The string that separates the tile parameters from one another.
+	#[clap(long, global = true, default_value = deathrip::TileSource::DEFAULT_SEPARATOR)]
+	tile_separator:          String,
+	/// The X parameter of the tile URLs is the row and Y is the column, rather than the other way
+	/// around.
+	#[clap(long, global = true)]
+	transposed_tiles:        bool,
+	/// Stream the image to the output a row of tiles at a time instead of keeping all of it in
+	/// memory. Only supports the PNG format.
+	#[clap(long)]
+	tile_window:             bool,
+	/// Rip these images of the image's page, for pages with multiple images (e.g. all the fragments
+	/// of a scroll), as a comma-separated list of numbers and ranges from 1, e.g. 2-5,8. Each is
+	/// written to its own file, named after the page with the number appended.
+	#[clap(long, value_name = "RANGES", value_parser = parse_pages, conflicts_with_all = ["batch_csv", "from_manifest"])]
+	pages:                   Option<Pages>,
+	/// Take the images for listing pages (e.g. search results) and rip the items they link to.
+	#[clap(long, conflicts_with_all = ["batch_csv", "from_manifest"])]
+	listing:                 bool,
+	/// Rip at most this many of the items of the listing pages, see --listing.
+	#[clap(long, value_name = "N", requires = "listing")]
+	max_items:               Option<usize>,
+	/// Rip all the plates of the image's page (e.g. infrared, color, and raking-light variants) and
+	/// combine them into an animated PNG (APNG), a frame per plate. Requires a page URL or item ID.
+	#[clap(long, conflicts_with_all = ["tile_window", "sample", "manifest", "from_manifest", "batch_csv", "format"])]
+	animate:                 bool,
+	/// The seconds each plate is shown for in the animation, see --animate.
+	#[clap(long, requires = "animate", default_value = "1", value_parser = parse_seconds)]
+	frame_duration:          Duration,
+	/// Rip every zoom level from 0 up to the zoom level, each stitched to its own file
+	/// z<level>.<extension> in this directory, e.g. for a multi-resolution viewer.
+	#[clap(long, value_name = "DIR", conflicts_with_all = ["tile_window", "tiles_dir", "sample", "output", "animate", "manifest", "hash_manifest"])]
+	all_zooms:               Option<PathBuf>,
+	/// Decode the written image and check its dimensions before declaring success.
+	#[clap(long, conflicts_with_all = ["tile_window", "tiles_dir", "all_zooms"])]
+	verify_output:           bool,
+	/// First rip a lower zoom level and write it to the output file as a placeholder, which the
+	/// full image replaces once it's written, so that an interrupted rip still leaves a usable image.
+	#[clap(long, conflicts_with_all = ["tile_window", "tiles_dir", "sample", "all_zooms", "animate"])]
+	progressive:             bool,
+	/// The zoom level of the placeholder of --progressive. Defaults to 2 levels below the zoom level.
+	#[clap(long, requires = "progressive")]
+	progressive_zoom:        Option<deathrip::Zoom>,
+	/// Save the tiles to this directory, as x<column>_y<row>_z<zoom>.<extension> (which --from-tiles
+	/// reads), instead of stitching them.
+	#[clap(long, value_name = "DIR", conflicts_with_all = ["tile_window", "sample", "output", "manifest", "hash_manifest"])]
+	#[clap(conflicts_with = "animate")]
+	tiles_dir:               Option<PathBuf>,
+	/// Save the tiles as they're served (the default).
+	#[clap(long, requires = "tiles_dir")]
+	tiles_raw:               bool,
+	/// Re-encode the tiles to this format (e.g. to make a uniform dataset) rather than save them as
+	/// they're served.
+	#[clap(long, value_name = "FORMAT", requires = "tiles_dir", conflicts_with = "tiles_raw", value_parser = parse_tiles_format)]
+	tiles_format:            Option<ImageOutputFormat>,
+	/// A regex replacement applied to every tile URL, in the form /<pattern>/<replacement>/. The
+	/// replacement may refer to capture groups, e.g. $1.
+	#[clap(long, global = true, value_parser = parse_rewrite)]
+	rewrite:                 Option<(regex::Regex, String)>,
+	/// Also retry requests that fail with this HTTP status, e.g. 403 for servers that rate-limit with
+	/// it, rather than taking it for a missing tile or the image's boundary. May be given multiple
+	/// times.
+	#[clap(long, global = true, value_name = "STATUS")]
+	retry_status:            Vec<u16>,
+	/// The maximum amount of tiles to fetch in parallel.
+	#[clap(long, default_value_t = 16)]
+	tile_workers:            usize,
+	/// A directory to cache fetched tiles in, so that they're not fetched again in later rips.
+	#[clap(long)]
+	cache_dir:               Option<PathBuf>,
+	/// Re-attempt tiles that weren't found once more, this many seconds after fetching the rest of
+	/// the tiles, instead of failing right away.
+	#[clap(long, value_parser = parse_seconds)]
+	retry_missing_after:     Option<Duration>,
+	/// Write the metadata of the rip (base URL, zoom level, and tiles) to a sidecar
+	/// <file>.manifest.json, which can be re-ripped with --from-manifest.
+	#[clap(long)]
+	manifest:                bool,
+	/// Re-rip the image of this manifest (see --manifest), e.g. to another format, using its
+	/// metadata rather than determining it again.
+	#[clap(long, value_name = "PATH", conflicts_with_all = ["images", "batch_csv", "zoom", "max_zoom", "min_zoom"])]
+	from_manifest:           Option<PathBuf>,
+	/// Instead of re-ripping the image of the manifest, check whether its tiles or maximum zoom level
+	/// have changed since, and exit with the code 2 if they have.
+	#[clap(long, requires = "from_manifest")]
+	check_updates:           bool,
+	/// Stitch the tiles saved in this directory, named x<column>_y<row>_z<zoom>.<extension>, without
+	/// the network. Stitches the highest zoom level of the tiles unless --zoom is given, and fails if
+	/// its grid is missing tiles.
+	#[clap(long, value_name = "DIR", conflicts_with_all = ["images", "batch_csv", "from_manifest", "listing", "pages", "animate", "all_zooms", "tiles_dir", "watch", "max_zoom", "min_zoom", "cache_dir"])]
+	#[clap(conflicts_with_all = ["tile_prefix", "tile_separator", "transposed_tiles", "rewrite"])]
+	from_tiles:              Option<PathBuf>,
+	/// The tiles of --from-tiles.
+	#[clap(skip)]
+	tile_dir:                Option<Arc<deathrip::TileDir>>,
+	/// Save the HTML of the image's page to this file when the page is fetched (i.e. not for base
+	/// URLs), to diagnose failures to find the image in it.
+	#[clap(long, value_name = "PATH")]
+	dump_html:               Option<PathBuf>,
+	/// Print the dimensions of each ripped image to the standard error, in the form
+	/// <width>x<height>, e.g. to interpret the raw formats.
+	#[clap(long)]
+	print_dimensions:        bool,
+	/// Only resolve the images (logging each step from the input to the URL of the first tile) and
+	/// print the URLs of their first tiles, without ripping them.
+	#[clap(long, conflicts_with = "batch_csv")]
+	normalize_url:           bool,
+	/// Run this shell command on each tile as soon as it's fetched, e.g. to process the tiles while
+	/// the rip goes on. The tile is in a temporary file, see the README for the details the command
+	/// gets.
+	#[clap(long, value_name = "COMMAND")]
+	on_tile_fetched:         Option<String>,
+	/// The maximum amount of tile hooks (see --on-tile-fetched) to run at the same time.
+	#[clap(long, value_name = "N", default_value_t = 4)]
+	tile_hook_workers:       usize,
+	/// Fetch only this many tiles, as a quick sanity check, leaving the rest of the image
+	/// transparent. The first tiles are fetched, unless a seed is given.
+	#[clap(long, conflicts_with = "tile_window")]
+	sample:                  Option<usize>,
+	/// Sample random tiles, picked reproducibly by this seed.
+	#[clap(long, requires = "sample")]
+	sample_seed:             Option<u64>,
+	/// Fail if a tile's size differs from the first tile's (beyond edge tiles being smaller), rather
+	/// than just warning about it.
+	#[clap(long)]
+	strict_tile_sizes:       bool,
+	/// Fetch a tile again, up to this many times, when it fails to decode (e.g. due to corruption
+	/// on the way). Defaults to once if given without a value.
+	#[clap(long, value_name = "RETRIES", num_args = 0..=1, default_missing_value = "1", default_value_t = 0)]
+	retry_on_decode_error:   u32,
+	/// Place the tiles as this size (WxH, e.g. 512x512) instead of the size of the first tile, for
+	/// sources whose first tile is of a different size than the rest.
+	#[clap(long, value_name = "WxH", value_parser = parse_size)]
+	tile_size:               Option<(u32, u32)>,
+	/// In the tile window mode, the maximum amount of fetched tiles that wait to be written. When
+	/// writing falls behind, fetching pauses until it catches up.
+	#[clap(long, default_value_t = deathrip::RipOptions::DEFAULT_MAX_BUFFERED_TILES)]
+	max_buffered_tiles:      usize,
+	/// Composite the image onto this background color (a name or #RRGGBB) before writing it. Formats
+	/// without transparency (JPEG) are composited onto white by default.
+	#[clap(long, value_name = "COLOR", value_parser = parse_color)]
+	flatten:                 Option<image::Rgb<u8>>,
+	/// Pad the image on its right and bottom edges to an even width and height, which some video
+	/// encoders and JPEG chroma subsampling require. The padding is transparent, or the --flatten
+	/// color for formats without transparency.
+	#[clap(long, conflicts_with_all = ["tile_window", "tiles_dir"])]
+	even_dimensions:         bool,
+	/// The color type of PNG output. The auto option picks the narrowest color type and bit depth
+	/// that represent the image losslessly, e.g. grayscale for grayscale scans. Ignored in the tile
+	/// window mode.
+	#[clap(long, value_enum, default_value_t = PngColorType::Auto)]
+	png_color_type:          PngColorType,
+	/// Give up on an image once its tiles were retried this many times in total (e.g. because the
+	/// server keeps failing), rather than retrying each tile to its own limit.
+	#[clap(long, value_name = "N")]
+	max_total_retries:       Option<usize>,
+	/// Don't rotate or flip the image according to the EXIF orientation of its tiles.
+	#[clap(long)]
+	no_auto_orient:          bool,
+	/// The maximum amount of pixels a tile may have. Larger tiles are rejected as likely corrupt or
+	/// malicious. 0 for no limit.
+	#[clap(long, default_value_t = deathrip::RipOptions::DEFAULT_MAX_TILE_PIXELS)]
+	max_tile_pixels:         u64,
+	/// Write the SHA-256 checksum of each output file to a sidecar <file>.sha256, which can be
+	/// verified later with `sha256sum -c`.
+	#[clap(long)]
+	hash_manifest:           bool,
+	/// Write a record of every tile (position, URL, HTTP status, size, SHA-256, and fetch time) as a
+	/// JSON array to a sidecar <file>.tiles.json.
+	#[clap(long, conflicts_with_all = ["all_zooms", "animate"])]
+	tile_audit:              bool,
+	/// Write a IIIF Presentation API 3.0 manifest of the image to a sidecar <file>.iiif.json, for
+	/// IIIF viewers, with both files served from this base URL.
+	#[clap(long, value_name = "URL", value_parser = parse_base_url, conflicts_with_all = ["tiles_dir", "all_zooms", "data_uri"])]
+	iiif_manifest:           Option<reqwest::Url>,
+	/// Also write the tiles as they're served, the image, and its sidecar files (e.g. --manifest) to
+	/// this zip or tar file, e.g. to share them as a dataset.
+	#[clap(long, value_name = "PATH", conflicts_with_all = ["batch_csv", "all_zooms", "animate", "data_uri"])]
+	archive:                 Option<PathBuf>,
+	/// Keep checking for updates to the image (see --check-updates) every this many seconds after
+	/// ripping it, and re-rip it whenever it's updated, until interrupted (e.g. with Ctrl-C). Each
+	/// rip is written to its own file, with the time of the rip (in Unix seconds) appended to its
+	/// name, e.g. for monitoring an item that's being digitized.
+	#[clap(long, value_name = "SECONDS", value_parser = parse_seconds, conflicts_with_all = ["batch_csv", "from_manifest", "listing", "pages", "animate", "all_zooms", "tiles_dir", "data_uri", "archive"])]
+	watch:                   Option<Duration>,
+	/// Record every HTTP request (its method, URL, headers, status, and timing) as newline-delimited
+	/// JSON to this file, e.g. to attach to a bug report. Credentials are redacted from the headers.
+	#[clap(long, global = true, value_name = "PATH")]
+	record_requests:         Option<PathBuf>,
+	/// Write newline-delimited JSON progress events to this file descriptor (on Unix) or file, see
+	/// the README for their schema.
+	#[clap(long, value_name = "FD|PATH")]
+	print_progress_to:       Option<String>,
+	/// When ripping multiple images, fail only the image whose rip panics (crashes on a bug), rather
+	/// than aborting the rest.
+	#[clap(long)]
+	catch_panics:            bool,
+	/// Seconds to wait between consecutive images, to be polite to the server. Only applies when
+	/// ripping multiple images.
+	#[clap(long, value_parser = parse_seconds)]
+	between_items_delay:     Option<Duration>,
+	/// Resolve the host to the address (IPv4 or IPv6) instead of using the system's DNS, like
+	/// curl's --resolve but without a port. May be given multiple times.
+	#[clap(long, global = true, value_name = "HOST:ADDR", value_parser = parse_resolve)]
+	resolve:                 Vec<(String, std::net::IpAddr)>,
+	/// Disable TLS certificate verification. This is insecure, see the README.
+	#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+	#[clap(long, global = true)]
+	insecure:                bool,
+	/// A PEM file of a root certificate to trust in addition to the system ones.
+	#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+	#[clap(long, global = true)]
+	cacert:                  Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+	/// Runs diagnostics on fetching an image, reporting the status of each step.
+	Doctor(doctor::Args),
+	/// Compares two zoom levels of an image by their dimensions and estimated sizes, and optionally
+	/// by how much detail the higher one adds.
+	CompareZooms(compare::Args),
+	/// Prints a shell completion script.
+	Completions {
+		/// The shell to generate the completions for.
+		shell: clap_complete::Shell,
+	},
+}
+
+/// Creates the tile source of the base URL according to the CLI arguments.
+fn tile_source(cli: &Cli, base_url: String) -> deathrip::TileSource {
+	deathrip::TileSource {
+		base_url,
+		prefix: cli.tile_prefix.clone(),
+		separator: cli.tile_separator.clone(),
+		url_rewrite: cli.rewrite.clone().map(|(regex, replacement)| {
+			Arc::new(move |url: &str| regex.replace_all(url, replacement.as_str()).into_owned())
+				as deathrip::UrlRewrite
+		}),
+		transposed: cli.transposed_tiles,
+		retry_classifier: (!cli.retry_status.is_empty()).then(|| {
+			let statuses = cli.retry_status.clone();
+			Arc::new(
+				move |error: &reqwest::Error, status: Option<reqwest::StatusCode>| match status {
+					Some(status) if statuses.contains(&status.as_u16()) => {
+						deathrip::RetryDecision::Retry
+					}
+					status => deathrip::classify(error, status),
+				},
+			) as deathrip::RetryClassifier
+		}),
+	}
+}
+
+/// Parses a `/<pattern>/<replacement>/` URL rewrite, where the delimiter is the first character.
+fn parse_rewrite(rewrite: &str) -> Result<(regex::Regex, String), String> {
+	let mut chars = rewrite.chars();
+	let delimiter = chars.next().ok_or("the rewrite is empty")?;
+	let parts = chars.as_str().split(delimiter).collect::<Vec<_>>();
+	match parts.as_slice() {
+		[pattern, replacement, ""] => Ok((
+			regex::Regex::new(pattern).map_err(|e| e.to_string())?,
+			(*replacement).to_owned(),
+		)),
+		_ => Err(format!(
+			"the rewrite should be in the form {delimiter}<pattern>{delimiter}<replacement>{delimiter}"
+		)),
+	}
+}
+
+fn format_help() -> String {
+	format!(
+		"The output format. Possible options are: auto | {} | raw | rgb. The variable Q is a number \
+		 within [0,100] that controls quality (higher is better), {} by default for JPEG. The auto \
+		 option picks PNG for line-art and text, and JPEG for photos. The raw and rgb options write \
+		 the bare pixels, see the README.",
+		deathrip::formats_help(),
+		deathrip::DEFAULT_JPEG_QUALITY
+	)
+}
+
+/// An output format argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Format {
+	/// Infer the format from the image, see [`deathrip::infer_output_format`].
+	Auto,
+	/// A specific format.
+	Fixed(ImageOutputFormat),
+	/// The bare pixels of 8 bits per channel, RGBA with alpha or RGB without it.
+	Raw { alpha: bool },
+}
+
+fn parse_format(format: &str) -> Result<Format, &'static str> {
+	if format.eq_ignore_ascii_case("auto") {
+		Ok(Format::Auto)
+	} else if format.eq_ignore_ascii_case("raw") {
+		Ok(Format::Raw { alpha: true })
+	} else if format.eq_ignore_ascii_case("rgb") {
+		Ok(Format::Raw { alpha: false })
+	} else {
+		deathrip::parse_format(format).map(|(_, format)| Format::Fixed(format))
+	}
+}
+
+/// The value parser of [`Format`], which also lists the format names, e.g. for shell completions.
+#[derive(Debug, Clone, Copy)]
+struct FormatParser;
+
+impl clap::builder::TypedValueParser for FormatParser {
+	type Value = Format;
+
+	fn parse_ref(
+		&self,
+		cmd: &clap::Command,
+		arg: Option<&clap::Arg>,
+		value: &std::ffi::OsStr,
+	) -> Result<Self::Value, clap::Error> {
+		clap::builder::StringValueParser::new()
+			.try_map(|format| parse_format(&format))
+			.parse_ref(cmd, arg, value)
+	}
+
+	fn possible_values(
+		&self,
+	) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+		let names = std::iter::once("auto")
+			.chain(
+				deathrip::supported_formats()
+					.iter()
+					.flat_map(|spec| spec.names()),
+			)
+			.chain(["raw", "rgb"]);
+		Some(Box::new(names.map(clap::builder::PossibleValue::new)))
+	}
+}
+
+/// The color type of PNG output, see `--png-color-type`.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum PngColorType {
+	/// The narrowest that represents the image losslessly.
+	Auto,
+	/// Grayscale.
+	Gray,
+	/// Grayscale with alpha.
+	GrayAlpha,
+	/// RGB.
+	Rgb,
+	/// RGB with alpha.
+	Rgba,
+}
+
+impl From<PngColorType> for Option<deathrip::PngColorType> {
+	fn from(color_type: PngColorType) -> Self {
+		match color_type {
+			PngColorType::Auto => None,
+			PngColorType::Gray => Some(deathrip::PngColorType::Gray),
+			PngColorType::GrayAlpha => Some(deathrip::PngColorType::GrayAlpha),
+			PngColorType::Rgb => Some(deathrip::PngColorType::Rgb),
+			PngColorType::Rgba => Some(deathrip::PngColorType::Rgba),
+		}
+	}
+}
+
+/// A filter to scale images with, see `compare-zooms --scale-filter`.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ScaleFilter {
+	/// Nearest neighbor, the fastest and blockiest.
+	Nearest,
+	/// Linear.
+	Triangle,
+	/// Cubic (Catmull-Rom).
+	Catmullrom,
+	/// Gaussian.
+	Gaussian,
+	/// Lanczos with a window of 3, the slowest and sharpest.
+	Lanczos3,
+}
+
+impl From<ScaleFilter> for image::imageops::FilterType {
+	fn from(filter: ScaleFilter) -> Self {
+		match filter {
+			ScaleFilter::Nearest => Self::Nearest,
+			ScaleFilter::Triangle => Self::Triangle,
+			ScaleFilter::Catmullrom => Self::CatmullRom,
+			ScaleFilter::Gaussian => Self::Gaussian,
+			ScaleFilter::Lanczos3 => Self::Lanczos3,
+		}
+	}
+}
+
+/// A zoom level argument, see `--zoom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Zoom {
+	/// The given level.
+	Level(deathrip::Zoom),
+	/// The highest level that adds detail in proportion to its size.
+	Smart,
+}
+
+/// The images of a page to rip, see `--pages`.
+#[derive(Debug, Clone)]
+struct Pages(Vec<std::ops::RangeInclusive<usize>>);
+
+impl Pages {
+	/// The numbers of the selected images out of the given amount, in the order they were given
+	/// and without repetitions, failing if any of them is out of range.
+	fn select(&self, count: usize) -> Result<Vec<usize>, String> {
+		let mut selected = Vec::new();
+		for range in &self.0 {
+			if *range.end() > count {
+				return Err(format!(
+					"page {} is out of range, the page has {count} images",
+					range.end()
+				));
+			}
+			for number in range.clone() {
+				if !selected.contains(&number) {
+					selected.push(number);
 				}
-			} else {
-				Err("unrecognized image output format")
 			}
 		}
+		Ok(selected)
 	}
 }
 
-fn cli_validate_zoom(zoom: &str) -> Result<usize, &'static str> {
-	let zoom = zoom
-		.parse::<isize>()
-		.map_err(|_| "zoom should be a number >= 0")?;
-	if zoom >= 0 {
-		Ok(zoom as usize)
-	} else {
-		Err("Zoom level must be >= 0")
+/// Parses a comma-separated list of page numbers and ranges, e.g. `2-5,8`.
+fn parse_pages(pages: &str) -> Result<Pages, String> {
+	pages
+		.split(',')
+		.map(|range| {
+			let range = range.trim();
+			let (start, end) = range.split_once('-').unwrap_or((range, range));
+			let parse = |number: &str| {
+				number
+					.trim()
+					.parse::<usize>()
+					.ok()
+					.filter(|&number| number > 0)
+					.ok_or_else(|| format!("{number:?} should be a page number from 1"))
+			};
+			let (start, end) = (parse(start)?, parse(end)?);
+			if start > end {
+				return Err(format!("the range {range} is backwards"));
+			}
+			Ok(start..=end)
+		})
+		.collect::<Result<_, _>>()
+		.map(Pages)
+}
+
+/// The amount of zoom levels below the zoom level of the placeholder of `--progressive`, by default.
+const PROGRESSIVE_ZOOM_DROP: usize = 2;
+
+/// The amount of zoom levels ripped at the same time by `--all-zooms`.
+const PYRAMID_WORKERS: usize = 2;
+
+/// The amount of the highest zoom levels the smart zoom level picks from.
+const SMART_ZOOM_LEVELS: usize = 4;
+
+/// The size in bytes of a data URI beyond which `--data-uri` warns that it may be too long to embed.
+const DATA_URI_WARN_BYTES: usize = 2 * 1024 * 1024;
+
+/// The background the image is flattened onto for formats without transparency, see `--flatten`.
+const DEFAULT_FLATTEN: image::Rgb<u8> = image::Rgb([u8::MAX; 3]);
+
+/// Parses a color by name or in the form `#RRGGBB`.
+fn parse_color(color: &str) -> Result<image::Rgb<u8>, &'static str> {
+	const ERROR: &str = "the color should be white, black, gray, red, green, blue, or #RRGGBB";
+	let rgb = match color.to_ascii_lowercase().as_str() {
+		"white" => [255, 255, 255],
+		"black" => [0, 0, 0],
+		"gray" | "grey" => [128, 128, 128],
+		"red" => [255, 0, 0],
+		"green" => [0, 128, 0],
+		"blue" => [0, 0, 255],
+		color => {
+			let hex = color.strip_prefix('#').unwrap_or(color);
+			let value = (hex.len() == 6)
+				.then(|| u32::from_str_radix(hex, 16).ok())
+				.flatten()
+				.ok_or(ERROR)?;
+			let [_, r, g, b] = value.to_be_bytes();
+			[r, g, b]
+		}
+	};
+	Ok(image::Rgb(rgb))
+}
+
+fn parse_resolve(resolve: &str) -> Result<(String, std::net::IpAddr), &'static str> {
+	// Host names can't contain colons, so the address (which may be IPv6) starts after the first.
+	let (host, addr) = resolve
+		.split_once(':')
+		.filter(|(host, _)| !host.is_empty())
+		.ok_or("should be in the form HOST:ADDR")?;
+	let addr = addr
+		.trim_start_matches('[')
+		.trim_end_matches(']')
+		.parse()
+		.map_err(|_| "the address should be an IPv4 or IPv6 address")?;
+	Ok((host.to_owned(), addr))
+}
+
+fn parse_base_url(url: &str) -> Result<reqwest::Url, &'static str> {
+	reqwest::Url::parse(url)
+		.ok()
+		.filter(|url| !url.cannot_be_a_base())
+		.ok_or("should be an absolute URL, e.g. https://example.com/scrolls/")
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), &'static str> {
+	size.split_once(['x', 'X'])
+		.and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+		.filter(|&(width, height)| width > 0 && height > 0)
+		.ok_or("the size should be in the form WxH of positive numbers, e.g. 512x512")
+}
+
+fn parse_tiles_format(format: &str) -> Result<ImageOutputFormat, &'static str> {
+	deathrip::parse_format(format).map(|(_, format)| format)
+}
+
+/// Parses a rate in bytes per second, optionally with a K, M, or G suffix (powers of 1024).
+fn parse_rate(rate: &str) -> Result<u64, &'static str> {
+	let (number, multiplier) = match rate.char_indices().last() {
+		Some((i, 'k' | 'K')) => (&rate[..i], 1 << 10),
+		Some((i, 'm' | 'M')) => (&rate[..i], 1 << 20),
+		Some((i, 'g' | 'G')) => (&rate[..i], 1 << 30),
+		_ => (rate, 1),
+	};
+	let rate = number
+		.parse::<f64>()
+		.ok()
+		.filter(|rate| rate.is_finite() && *rate > 0.0)
+		.ok_or("the rate should be a positive number of bytes, e.g. 500K or 2M")?;
+	Ok(((rate * f64::from(multiplier)) as u64).max(1))
+}
+
+fn parse_seconds(seconds: &str) -> Result<Duration, &'static str> {
+	seconds
+		.parse::<f64>()
+		.ok()
+		.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+		.ok_or("duration should be a non-negative number of seconds")
+}
+
+fn parse_zoom(zoom: &str) -> Result<Zoom, deathrip::ParseZoomError> {
+	match zoom {
+		"smart" => Ok(Zoom::Smart),
+		zoom => zoom.parse().map(Zoom::Level),
 	}
 }
 
@@ -93,119 +694,1982 @@ impl<'a> From<&'a Cli> for LevelFilter {
 	fn from(cli: &'a Cli) -> Self {
 		match (cli.quiet, cli.verbose) {
 			(true, _) => LevelFilter::OFF,
+			(false, _) if cli.summary_only => LevelFilter::WARN,
 			(false, false) => LevelFilter::INFO,
 			(false, true) => LevelFilter::TRACE,
 		}
 	}
 }
 
-async fn cli() -> Result<(), Box<dyn std::error::Error>> {
-	let cli = Cli::parse();
+/// Creates the HTTP client according to the CLI arguments.
+fn client(cli: &Cli) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+	let mut builder = reqwest::Client::builder();
+	if let Some(timeout) = cli.connect_timeout {
+		builder = builder.connect_timeout(timeout);
+	}
+	if let Some(timeout) = cli.read_timeout {
+		builder = builder.timeout(timeout);
+	}
+	if let Some(size) = cli.connection_pool_size {
+		builder = builder.pool_max_idle_per_host(size);
+	}
+	if let Some(timeout) = cli.connection_idle_timeout {
+		builder = builder.pool_idle_timeout(timeout);
+	}
+	for (host, addr) in &cli.resolve {
+		// The port is taken from the URL.
+		builder = builder.resolve(host, std::net::SocketAddr::new(*addr, 0));
+	}
+	#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+	{
+		if cli.insecure {
+			tracing::warn!(
+				"TLS certificate verification is disabled, the connection may be intercepted"
+			);
+			builder = builder.danger_accept_invalid_certs(true);
+		}
+		if let Some(cacert) = &cli.cacert {
+			let pem = std::fs::read(cacert)?;
+			builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+		}
+	}
+	Ok(builder.build()?)
+}
+
+/// Resolves the path of the output file, unless the output is piped, and creates its directory.
+async fn output_path(
+	item: &Item<'_>,
+	title: &str,
+	extension: &str,
+) -> std::io::Result<Option<PathBuf>> {
+	if !item.to_file && !std::io::stdout().is_terminal() {
+		return Ok(None);
+	}
+	let mut path = item
+		.output
+		.clone()
+		.unwrap_or_else(|| PathBuf::from(format!("{title}.{extension}")));
+	if let Some(stamp) = item.stamp {
+		let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+		file_name.push(format!("_{stamp}"));
+		if let Some(extension) = path.extension() {
+			file_name.push(".");
+			file_name.push(extension);
+		}
+		path.set_file_name(file_name);
+	}
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).await?;
+	}
+	Ok(Some(path))
+}
+
+/// Writes the SHA-256 checksum of the file to a sidecar file next to it, in the format of
+/// `sha256sum` (so it can be verified with `sha256sum -c`), resolving to the sidecar's path.
+fn write_checksum(path: &std::path::Path) -> std::io::Result<PathBuf> {
+	use sha2::Digest as _;
+	let mut hasher = sha2::Sha256::new();
+	std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	let hash = hasher
+		.finalize()
+		.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect::<String>();
+	let mut checksum_path = path.as_os_str().to_owned();
+	checksum_path.push(".sha256");
+	let checksum_path = PathBuf::from(checksum_path);
+	std::fs::write(&checksum_path, format!("{hash}  {file_name}\n"))?;
+	Ok(checksum_path)
+}
+
+/// An image to rip, along with the settings that may differ between images.
+#[derive(Debug, Clone)]
+struct Item<'a> {
+	/// The image, as given on the command line.
+	image:    &'a str,
+	/// The zoom level, or `None` for the maximum.
+	zoom:     Option<Zoom>,
+	format:   Format,
+	/// The output file, or `None` to auto-generate it.
+	output:   Option<PathBuf>,
+	/// Whether to write the image to a file even if the output is piped.
+	to_file:  bool,
+	/// The metadata of the image, if already known.
+	manifest: Option<manifest::Manifest>,
+	/// The image of the page to rip when its page has multiple images, see `--pages`.
+	plate:    Option<Plate>,
+	/// The time of the rip in Unix seconds, to append to the output file name, see `--watch`.
+	stamp:    Option<u64>,
+}
+
+/// An image of a page with multiple images, see `--pages`.
+#[derive(Debug, Clone)]
+struct Plate {
+	/// The number of the image in the page, starting from 1.
+	number:   usize,
+	/// The title of the page.
+	title:    String,
+	/// The base URL of the image.
+	base_url: String,
+}
+
+impl<'a> Item<'a> {
+	/// An item of the given image with the settings of the CLI arguments.
+	fn new(cli: &Cli, image: &'a str) -> Self {
+		let batch = cli.images.len() > 1;
+		Self {
+			image,
+			zoom: cli.zoom,
+			format: cli.format.clone(),
+			output: if batch { None } else { cli.output.clone() },
+			to_file: batch,
+			manifest: None,
+			plate: None,
+			stamp: None,
+		}
+	}
+}
+
+/// The outcome of ripping an image.
+#[derive(Debug, Clone)]
+struct Ripped {
+	/// The file the image was written to, or `None` if it was written to the standard output.
+	path:       Option<PathBuf>,
+	dimensions: (u32, u32),
+	/// The size of the written image in bytes, if known.
+	bytes:      Option<u64>,
+	/// The metadata of the rip, if its tiles were determined up front.
+	manifest:   Option<manifest::Manifest>,
+}
+
+impl std::fmt::Display for Ripped {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let (width, height) = self.dimensions;
+		write!(f, "{width}\u{00D7}{height}")?;
+		if let Some(manifest) = &self.manifest {
+			write!(
+				f,
+				" at zoom {} ({}\u{00D7}{} tiles)",
+				manifest.zoom, manifest.columns, manifest.rows
+			)?;
+		}
+		if let Some(bytes) = self.bytes {
+			write!(f, ", {}", deathrip::ByteSize(bytes))?;
+		}
+		match &self.path {
+			Some(path) => write!(f, ", to {}", path.display()),
+			None => write!(f, ", to the output stream"),
+		}
+	}
+}
+
+async fn cli(mut cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+	cli.bandwidth_limit = cli
+		.limit_rate
+		.map(|rate| Arc::new(deathrip::BandwidthLimit::new(rate)));
+
+	if let Some(Command::Completions { shell }) = cli.command {
+		let mut command = <Cli as clap::CommandFactory>::command();
+		// Generated into a buffer since the generator panics on write errors, e.g. a closed output.
+		let mut buf = Vec::new();
+		clap_complete::generate(shell, &mut command, env!("CARGO_PKG_NAME"), &mut buf);
+		OutputStream::lock().write_all(&buf)?;
+		return Ok(());
+	}
 
 	let verbosity = LevelFilter::from(&cli);
-	if verbosity != LevelFilter::OFF {
-		tracing_subscriber::registry()
-			.with(
-				tracing_subscriber::fmt::layer()
-					.with_writer(std::io::stderr)
-					.without_time(),
-			)
-			.with(
+	let log = (verbosity != LevelFilter::OFF).then(|| {
+		tracing_subscriber::fmt::layer()
+			.with_writer(std::io::stderr)
+			.without_time()
+			.with_filter(
 				tracing_subscriber::filter::Targets::new()
 					.with_target(env!("CARGO_PKG_NAME"), verbosity),
 			)
-			.init();
+	});
+	let recorder = (cli.record_requests.as_deref())
+		.map(record::RequestRecorder::create)
+		.transpose()?
+		.map(|recorder| {
+			recorder.with_filter(
+				tracing_subscriber::filter::Targets::new()
+					.with_target(deathrip::REQUEST_TARGET, LevelFilter::DEBUG),
+			)
+		});
+	tracing_subscriber::registry()
+		.with(log)
+		.with(recorder)
+		.init();
+
+	let client = Arc::new(client(&cli)?);
+
+	if let Some(Command::Doctor(args)) = &cli.command {
+		return doctor::run(&cli, args, client).await;
+	}
+	if let Some(Command::CompareZooms(args)) = &cli.command {
+		return compare::run(&cli, args, client).await;
 	}
 
-	let time_start = Instant::now();
+	if cli.listing {
+		cli.images = listing_images(&cli, &client).await?;
+	}
 
-	let client = Arc::new(reqwest::Client::new());
+	let events = cli
+		.print_progress_to
+		.as_deref()
+		.map(events::EventWriter::open)
+		.transpose()?;
+	let events = events.as_ref();
 
-	tracing::info!("determining metadata");
-	let (url, out) = {
-		if let Ok(input) = deathrip::Input::try_from(cli.image.as_str()) {
-			let normalized = match input {
-				deathrip::Input::BaseUrl(url) => Ok((url, None)),
-				deathrip::Input::PageUrl(url) => Err(url),
-				deathrip::Input::ItemId(id) => Err(format!(
-					"https://www.deadseascrolls.org.il/explore-the-archive/image/{id}"
-				)),
-			};
-			match normalized {
-				Ok(base) => base,
-				Err(page_url) => {
-					tracing::info!("fetching metadata from page URL");
-					let page = deathrip::Page::try_fetch(&client, &page_url).await?;
-					(page.base_url, Some(page.title))
-				}
+	if let Some(path) = &cli.batch_csv {
+		return batch::run(&cli, client, path, events, OutputStream::lock()).await;
+	}
+	if cli.normalize_url {
+		for image in &cli.images {
+			let (_, source, zoom) = resolve(&cli, Arc::clone(&client), &Item::new(&cli, image))
+				.instrument(tracing::info_span!("image", image = image.as_str()))
+				.await?;
+			writeln!(OutputStream::lock(), "{}", source.tile_url(0, 0, zoom))?;
+		}
+		return Ok(());
+	}
+	if let Some(path) = &cli.from_manifest {
+		let manifest = manifest::Manifest::read(path)?;
+		if cli.check_updates {
+			let changes = check_updates(&cli, client, &manifest)
+				.instrument(tracing::info_span!(
+					"image",
+					image = manifest.input.as_str()
+				))
+				.await?;
+			if changes.is_empty() {
+				writeln!(OutputStream::lock(), "unchanged")?;
+				return Ok(());
 			}
-		} else {
-			tracing::error!("failed to determine the image type.");
-			std::process::exit(1);
+			writeln!(OutputStream::lock(), "changed: {}", changes.join(", "))?;
+			std::process::exit(2);
 		}
+		let input = manifest.input.clone();
+		let item = Item {
+			manifest: Some(manifest),
+			..Item::new(&cli, &input)
+		};
+		return rip_image(&cli, client, &item, events).await.map(drop);
+	}
+	if let Some(dir) = &cli.from_tiles {
+		let tile_dir = deathrip::TileDir::scan(dir)
+			.map_err(|e| format!("failed to read the tile directory {}: {e}", dir.display()))?;
+		let tile_dir = Arc::new(tile_dir);
+		cli.tile_dir = Some(Arc::clone(&tile_dir));
+		let manifest = tile_dir_manifest(&cli, &tile_dir)?;
+		let input = manifest.input.clone();
+		let item = Item {
+			manifest: Some(manifest),
+			..Item::new(&cli, &input)
+		};
+		return rip_image(&cli, client, &item, events).await.map(drop);
+	}
+	let items = match (&cli.pages, cli.animate) {
+		// The animation selects the pages' images itself.
+		(Some(pages), false) => {
+			let mut items = Vec::new();
+			for image in &cli.images {
+				items.extend(
+					page_items(&cli, &client, image, pages)
+						.instrument(tracing::info_span!("image", image = image.as_str()))
+						.await?,
+				);
+			}
+			items
+		}
+		_ => cli
+			.images
+			.iter()
+			.map(|image| Item::new(&cli, image))
+			.collect(),
 	};
+	if let Some(interval) = cli.watch {
+		let [item] = items.as_slice() else {
+			return Err("only a single image can be watched".into());
+		};
+		return watch(&cli, client, item, interval, events).await;
+	}
+	if let [item] = items.as_slice() {
+		return rip_image(&cli, Arc::clone(&client), item, events)
+			.await
+			.map(drop);
+	}
+	if cli.output.is_some() {
+		return Err("the output file can't be specified when ripping multiple images".into());
+	}
+	if cli.title.is_some() {
+		return Err("the title can't be specified when ripping multiple images".into());
+	}
+	if cli.archive.is_some() {
+		return Err("the archive can't be specified when ripping multiple images".into());
+	}
+	let mut failures = 0;
+	for (i, item) in items.iter().enumerate() {
+		if i > 0 {
+			if let Some(delay) = cli.between_items_delay {
+				tracing::info!("waiting {}ms before the next image", delay.as_millis());
+				tokio::time::sleep(delay).await;
+			}
+		}
+		let image = item.image;
+		let plate = item.plate.as_ref().map(|plate| plate.number);
+		let span_image = tracing::info_span!("image", image, plate);
+		match rip_isolated(&cli, Arc::clone(&client), item, events)
+			.instrument(span_image)
+			.await
+		{
+			Ok(Ok(_)) => {}
+			Ok(Err(panic)) if cli.json_errors => {
+				let error = serde_json::json!({ "kind": "panic", "message": panic });
+				print_json_error(error, Some(image));
+				failures += 1;
+			}
+			Ok(Err(panic)) => {
+				tracing::error!("ripping {image} panicked: {panic}");
+				failures += 1;
+			}
+			Err(e) if cli.json_errors => {
+				print_json_error(json_error(&*e), Some(image));
+				failures += 1;
+			}
+			Err(e) => {
+				tracing::error!("failed to rip {image}: {e}");
+				failures += 1;
+			}
+		}
+	}
+	match failures {
+		0 => Ok(()),
+		failures => Err(format!("failed to rip {failures} of {} images", items.len()).into()),
+	}
+}
 
-	let page = deathrip::Page {
-		title:    out.unwrap_or_else(|| {
-			format!(
-				"{}_{}",
-				env!("CARGO_PKG_NAME"),
+/// The metadata of the tiles of the directory to stitch (see `--from-tiles`), failing if the grid of
+/// the zoom level is missing tiles.
+fn tile_dir_manifest(
+	cli: &Cli,
+	tile_dir: &deathrip::TileDir,
+) -> Result<manifest::Manifest, Box<dyn std::error::Error>> {
+	let dir = tile_dir.dir.display();
+	let max_zoom = *tile_dir
+		.zooms()
+		.last()
+		.ok_or_else(|| format!("found no tiles in {dir}"))?;
+	let zoom = match cli.zoom {
+		Some(Zoom::Level(zoom)) => zoom,
+		Some(Zoom::Smart) => return Err("the smart zoom level needs the network".into()),
+		None => max_zoom,
+	};
+	let (columns, rows) = tile_dir
+		.grid(zoom)
+		.ok_or_else(|| format!("found no tiles of zoom level {zoom} in {dir}"))?;
+	tracing::info!("found {columns}\u{00D7}{rows} tiles of zoom level {zoom} in {dir}");
+	let missing = tile_dir.missing(zoom);
+	if !missing.is_empty() {
+		const LISTED: usize = 10;
+		let mut list = missing
+			.iter()
+			.take(LISTED)
+			.map(|(x, y)| format!("({x},{y})"))
+			.join(", ");
+		if missing.len() > LISTED {
+			list.push_str(", ...");
+		}
+		return Err(format!(
+			"the tile directory is missing {} tiles of zoom level {zoom}: {list}",
+			missing.len()
+		)
+		.into());
+	}
+	let title = tile_dir.dir.file_name().map_or_else(
+		|| "tiles".to_owned(),
+		|name| name.to_string_lossy().into_owned(),
+	);
+	Ok(manifest::Manifest {
+		input: dir.to_string(),
+		title,
+		base_url: tile_dir.source().base_url,
+		zoom,
+		columns,
+		rows,
+		max_zoom: Some(max_zoom),
+	})
+}
+
+/// The page URLs of the items the listing pages link to, see `--listing`.
+async fn listing_images(
+	cli: &Cli,
+	client: &reqwest::Client,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+	let mut images = Vec::new();
+	for listing_url in &cli.images {
+		tracing::info!(listing_url, "fetching the listing page");
+		let html = deathrip::Page::fetch_html(client, listing_url).await?;
+		let items = deathrip::Page::parse_listing(&html, listing_url);
+		tracing::info!(listing_url, "found {} items", items.len());
+		for item in items {
+			let image = item.to_url(deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE);
+			if !images.contains(&image) {
+				images.push(image);
+			}
+		}
+	}
+	if images.is_empty() {
+		return Err("found no items on the listing pages".into());
+	}
+	if let Some(max_items) = cli.max_items.filter(|&max_items| max_items < images.len()) {
+		tracing::info!(
+			"ripping the first {max_items} of the {} items",
+			images.len()
+		);
+		images.truncate(max_items);
+	}
+	Ok(images)
+}
+
+/// The items of the selected images of the image's page, see `--pages`.
+async fn page_items<'a>(
+	cli: &Cli,
+	client: &reqwest::Client,
+	image: &'a str,
+	pages: &Pages,
+) -> Result<Vec<Item<'a>>, Box<dyn std::error::Error>> {
+	let Ok(input) = deathrip::Input::try_from(image);
+	let page_url = input
+		.page_url(deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE)
+		.ok_or("selecting pages requires the image's page URL or item ID")?;
+	tracing::info!(page_url, "fetching the images of the page");
+	let html = deathrip::Page::fetch_html(client, &page_url).await?;
+	let page = deathrip::Page::parse(&html)?;
+	let plates = deathrip::Page::parse_plates(&html);
+	let selected = pages.select(plates.len())?;
+	tracing::info!("selected {} of the {} images", selected.len(), plates.len());
+	// Like multiple images, multiple pages are each written to their auto-generated file name.
+	let single = cli.images.len() == 1 && selected.len() == 1;
+	Ok(selected
+		.into_iter()
+		.map(|number| Item {
+			output: if single { cli.output.clone() } else { None },
+			to_file: !single,
+			plate: Some(Plate {
+				number,
+				title: page.title.clone(),
+				base_url: plates[number - 1].clone(),
+			}),
+			..Item::new(cli, image)
+		})
+		.collect())
+}
+
+/// Rips the image like [`rip_image`], but with `--catch-panics`, a panic is caught and resolves to
+/// its message (in the inner `Err`) rather than unwinding, so that the other images can go on.
+async fn rip_isolated(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	events: Option<&events::EventWriter>,
+) -> Result<Result<Ripped, String>, Box<dyn std::error::Error>> {
+	if !cli.catch_panics {
+		return rip_image(cli, client, item, events).await.map(Ok);
+	}
+	match std::panic::AssertUnwindSafe(rip_image(cli, client, item, events))
+		.catch_unwind()
+		.await
+	{
+		Ok(result) => result.map(Ok),
+		Err(panic) => {
+			let message = panic
+				.downcast_ref::<&str>()
+				.map(|message| message.to_string())
+				.or_else(|| panic.downcast_ref::<String>().cloned())
+				.unwrap_or_else(|| "unknown panic".to_owned());
+			if let Some(events) = events {
+				events.done(item.image, Some(&format!("panicked: {message}")));
+			}
+			Ok(Err(message))
+		}
+	}
+}
+
+/// Determines the tiles (and the maximum zoom level, if the manifest has it) of the manifest's image
+/// again, without downloading any, resolving to the descriptions of what changed since.
+async fn check_updates(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	manifest: &manifest::Manifest,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+	let source = tile_source(cli, manifest.base_url.clone());
+	let retry = deathrip::RetryPolicy::default();
+	let mut changes = Vec::new();
+	if let Some(max_zoom) = manifest.max_zoom {
+		let zoom = deathrip::determine_max_zoom(Arc::clone(&client), &source, 4, retry).await?;
+		tracing::info!("determined zoom level of {zoom}");
+		if zoom != max_zoom {
+			changes.push(format!("zoom {max_zoom}\u{2192}{zoom}"));
+		}
+	}
+	let options = deathrip::RipOptions::new(manifest.zoom);
+	let (columns, rows) = deathrip::determine_dimensions(
+		client,
+		&source,
+		manifest.zoom,
+		options.num_workers_half,
+		retry,
+	)
+	.await?;
+	tracing::info!("determined {columns}\u{00D7}{rows} tiles");
+	if (columns, rows) != (manifest.columns, manifest.rows) {
+		changes.push(format!(
+			"tiles {}x{}\u{2192}{columns}x{rows}",
+			manifest.columns, manifest.rows
+		));
+	}
+	Ok(changes)
+}
+
+/// Rips the image, and then re-rips it whenever its tiles or maximum zoom level change (see
+/// `--check-updates`), checking every interval until interrupted, see `--watch`. Each rip is
+/// written to its own file, with the time of the rip appended to its name.
+async fn watch(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	interval: Duration,
+	events: Option<&events::EventWriter>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let interrupted = tokio::signal::ctrl_c();
+	tokio::pin!(interrupted);
+	loop {
+		let item = Item {
+			to_file: true,
+			stamp: Some(
 				SystemTime::now()
 					.duration_since(SystemTime::UNIX_EPOCH)
-					.map(|time| time.as_millis())
-					.unwrap_or(0)
-			)
-		}),
-		base_url: url,
+					.map_or(0, |time| time.as_secs()),
+			),
+			..item.clone()
+		};
+		let ripped = tokio::select! {
+			ripped = rip_image(cli, Arc::clone(&client), &item, events) => ripped?,
+			_ = &mut interrupted => {
+				tracing::info!("interrupted, stopped watching");
+				return Ok(());
+			}
+		};
+		let manifest = ripped
+			.manifest
+			.ok_or("the tiles of the image weren't determined, so it can't be watched")?;
+		loop {
+			tracing::info!("checking for updates in {}s", interval.as_secs_f64());
+			tokio::select! {
+				() = tokio::time::sleep(interval) => {}
+				_ = &mut interrupted => {
+					tracing::info!("interrupted, stopped watching");
+					return Ok(());
+				}
+			}
+			match check_updates(cli, Arc::clone(&client), &manifest).await {
+				Ok(changes) if changes.is_empty() => tracing::info!("unchanged"),
+				Ok(changes) => {
+					tracing::info!("changed: {}, re-ripping", changes.join(", "));
+					break;
+				}
+				Err(e) => tracing::warn!("failed to check for updates: {e}"),
+			}
+		}
+	}
+}
+
+/// Rips the image of the item according to the CLI arguments, and writes it, reporting the
+/// progress and outcome to the progress events, if any.
+async fn rip_image(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	events: Option<&events::EventWriter>,
+) -> Result<Ripped, Box<dyn std::error::Error>> {
+	let time_start = Instant::now();
+	// The time the rip spent backing off between retries so far, in milliseconds, for the summary.
+	let backed_off = Arc::new(AtomicU64::new(0));
+	let progress = {
+		let (events, backed_off) = (
+			events.map(|events| events.callback(item.image)),
+			Arc::clone(&backed_off),
+		);
+		Arc::new(move |progress: &deathrip::Progress| {
+			let ms = u64::try_from(progress.backed_off.as_millis()).unwrap_or(u64::MAX);
+			backed_off.fetch_max(ms, Ordering::Relaxed);
+			if let Some(events) = &events {
+				events(progress);
+			}
+		}) as deathrip::ProgressCallback
+	};
+	let hook = cli
+		.on_tile_fetched
+		.as_deref()
+		.map(|command| hook::TileHook::new(command, item.image, cli.tile_hook_workers));
+	let audit = cli.tile_audit.then(Arc::<audit::TileAudit>::default);
+	let archive = (cli.archive.as_deref())
+		.map(archive::Archive::create)
+		.transpose()?
+		.map(Arc::new);
+	let callbacks = (hook.iter().map(hook::TileHook::callback))
+		.chain(audit.iter().map(audit::TileAudit::callback))
+		.chain(archive.iter().map(archive::Archive::callback))
+		.collect::<Vec<_>>();
+	let on_tile = (!callbacks.is_empty()).then(|| {
+		Arc::new(move |tile: &deathrip::FetchedTile| {
+			callbacks.iter().for_each(|callback| callback(tile));
+		}) as deathrip::TileCallback
+	});
+	let mut result = rip_and_write(cli, client, item, Some(progress), on_tile).await;
+	if let Some(hook) = hook {
+		let finished = hook.finish().await;
+		result = result.and_then(|ripped| finished.map(|()| ripped).map_err(Into::into));
+	}
+	// The sidecar files of the image, for the archive.
+	let mut sidecars = Vec::new();
+	let result = result.and_then(|ripped| {
+		match (&ripped.path, cli.hash_manifest) {
+			(Some(path), true) => {
+				let checksum_path = write_checksum(path)?;
+				tracing::info!("wrote the checksum to {}", checksum_path.display());
+				sidecars.push(checksum_path);
+			}
+			(None, true) => tracing::warn!("not writing a checksum for the output stream"),
+			(_, false) => {}
+		}
+		let backoff = match backed_off.load(Ordering::Relaxed) {
+			0 => String::new(),
+			ms => format!(", backing off for {ms}ms between retries"),
+		};
+		tracing::info!("ripped {ripped}{backoff}");
+		if cli.summary_only {
+			// Standard error, since the standard output may be the image.
+			eprintln!(
+				"ripped {ripped}{backoff}, in {:.2}s",
+				time_start.elapsed().as_secs_f64()
+			);
+		}
+		if cli.print_dimensions {
+			let (width, height) = ripped.dimensions;
+			eprintln!("{width}x{height}");
+		}
+		match (&ripped.path, &ripped.manifest, cli.manifest) {
+			(Some(path), Some(manifest), true) => {
+				let manifest_path = manifest.write_beside(path)?;
+				tracing::info!("wrote the manifest to {}", manifest_path.display());
+				sidecars.push(manifest_path);
+			}
+			(None, _, true) => tracing::warn!("not writing a manifest for the output stream"),
+			_ => {}
+		}
+		match (&ripped.path, &audit) {
+			(Some(path), Some(audit)) => {
+				let audit_path = audit.write_beside(path)?;
+				tracing::info!("wrote the tile audit to {}", audit_path.display());
+				sidecars.push(audit_path);
+			}
+			(None, Some(_)) => tracing::warn!("not writing a tile audit for the output stream"),
+			(_, None) => {}
+		}
+		match (&ripped.path, &cli.iiif_manifest) {
+			(Some(path), Some(base_url)) => {
+				let iiif_path = iiif::write_beside(path, ripped.dimensions, base_url)?;
+				tracing::info!("wrote the IIIF manifest to {}", iiif_path.display());
+				sidecars.push(iiif_path);
+			}
+			(None, Some(_)) => tracing::warn!("not writing a IIIF manifest for the output stream"),
+			(_, None) => {}
+		}
+		Ok(ripped)
+	});
+	let result = match (&archive, &cli.archive) {
+		(Some(archive), Some(archive_path)) => {
+			let result = result.and_then(|ripped| {
+				finish_archive(cli, archive, &ripped, &sidecars)?;
+				tracing::info!("wrote the archive to {}", archive_path.display());
+				Ok(ripped)
+			});
+			if result.is_err() {
+				let _ = archive.finish();
+				let _ = std::fs::remove_file(archive_path);
+			}
+			result
+		}
+		_ => result,
 	};
+	if let Some(events) = events {
+		let error = result.as_ref().err().map(ToString::to_string);
+		events.done(item.image, error.as_deref());
+	}
+	result
+}
 
-	let span_zoom = tracing::info_span!("determining zoom level").entered();
-	let zoom = if let Some(zoom) = cli.zoom {
+/// Adds the image and its sidecar files to the archive and finishes it, see `--archive`.
+fn finish_archive(
+	cli: &Cli,
+	archive: &archive::Archive,
+	ripped: &Ripped,
+	sidecars: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+	match &ripped.path {
+		Some(path) => archive.add_file(path)?,
+		// The tiles are the output.
+		None if cli.tiles_dir.is_some() => {}
+		None => tracing::warn!("not archiving the output stream"),
+	}
+	for sidecar in sidecars {
+		archive.add_file(sidecar)?;
+	}
+	archive.finish()
+}
+
+/// Determines the zoom level to rip: the given level, the smart level (see `--zoom smart`), or the
+/// maximum level (clamped to `--max-zoom`), either of the latter floored to `--min-zoom`.
+async fn determine_zoom(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	source: &deathrip::TileSource,
+	zoom: Option<Zoom>,
+) -> Result<deathrip::Zoom, Box<dyn std::error::Error>> {
+	if let Some(Zoom::Level(zoom)) = zoom {
 		tracing::trace!("user supplied zoom level {zoom}");
-		zoom
+		return Ok(zoom);
+	}
+	if let (Some(min_zoom), Some(max_zoom)) = (cli.min_zoom, cli.max_zoom) {
+		if min_zoom > max_zoom {
+			return Err(format!(
+				"the minimum zoom level {min_zoom} is above the maximum {max_zoom}"
+			)
+			.into());
+		}
+	}
+	let retry = deathrip::RetryPolicy::default();
+	let max_zoom = deathrip::determine_max_zoom(Arc::clone(&client), source, 4, retry).await?;
+	tracing::info!("determined zoom level of {max_zoom}");
+	let max_zoom = match cli.max_zoom {
+		Some(clamp) if max_zoom > clamp => {
+			tracing::info!("clamped zoom level {max_zoom} to the maximum of {clamp}");
+			clamp
+		}
+		_ => max_zoom,
+	};
+	// The floor can't be above the image's highest level.
+	let min_zoom = cli.min_zoom.map(|min_zoom| {
+		if min_zoom > max_zoom {
+			tracing::warn!(
+				"the image's zoom level of {max_zoom} is below the minimum of {min_zoom}"
+			);
+		}
+		min_zoom.min(max_zoom)
+	});
+	if zoom != Some(Zoom::Smart) {
+		return Ok(max_zoom);
+	}
+	let lowest = max_zoom.below(SMART_ZOOM_LEVELS - 1);
+	let levels = deathrip::enumerate_zooms(
+		client,
+		source,
+		lowest..=max_zoom,
+		cli.smart_zoom_samples,
+		retry,
+	)
+	.await?;
+	let zoom = deathrip::pick_knee_zoom(&levels, cli.smart_zoom_threshold).unwrap_or(max_zoom);
+	tracing::info!("picked the smart zoom level of {zoom}");
+	match min_zoom {
+		Some(min_zoom) if zoom < min_zoom => {
+			tracing::info!("raised the smart zoom level {zoom} to the minimum of {min_zoom}");
+			Ok(min_zoom)
+		}
+		_ => Ok(zoom),
+	}
+}
+
+/// Resolves the input of the item to the image's page (with the title for the output file), tile
+/// source, and zoom level, logging every step of the way.
+async fn resolve(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+) -> Result<(deathrip::Page, deathrip::TileSource, deathrip::Zoom), Box<dyn std::error::Error>> {
+	tracing::info!("determining metadata");
+	let metadata = async {
+		let Ok(input) = deathrip::Input::try_from(item.image);
+		let kind = match &input {
+			deathrip::Input::BaseUrl(_) => "base URL",
+			deathrip::Input::PageUrl(_) => "page URL",
+			deathrip::Input::ItemId(_) => "item ID",
+		};
+		tracing::info!(input = item.image, kind, "detected the input");
+		// The page of a plate was already fetched to select it.
+		let page_url = input
+			.page_url(deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE)
+			.filter(|_| item.plate.is_none());
+		if let Some(page_url) = &page_url {
+			tracing::info!(page_url, "fetching metadata from page URL");
+		}
+		let resolved = match (&item.plate, page_url, &cli.dump_html) {
+			(Some(plate), ..) => {
+				tracing::info!(plate = plate.number, "using an image of the page");
+				deathrip::ResolvedImage {
+					base_url: plate.base_url.clone(),
+					title:    Some(format!("{}_{}", plate.title, plate.number)),
+				}
+			}
+			(None, Some(page_url), Some(path)) => {
+				let html = deathrip::Page::fetch_html(&client, &page_url).await?;
+				fs::write(path, &html).await?;
+				tracing::info!("dumped the page's HTML to {}", path.display());
+				let page = deathrip::Page::parse(&html)?;
+				deathrip::ResolvedImage {
+					base_url: page.base_url,
+					title:    Some(page.title),
+				}
+			}
+			_ => {
+				deathrip::resolve_input(&client, input, deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE)
+					.await?
+			}
+		};
+		if let Some(title) = &resolved.title {
+			tracing::info!(title, "scraped the page");
+		}
+		let (url, out) = (resolved.base_url, resolved.title);
+		tracing::info!(base_url = url, "resolved the base URL");
+
+		let page = deathrip::Page {
+			title:    title(cli, out),
+			base_url: url,
+		};
+		let source = tile_source(cli, page.base_url.clone());
+
+		let zoom = determine_zoom(cli, Arc::clone(&client), &source, item.zoom)
+			.instrument(tracing::info_span!("determining zoom level"))
+			.await?;
+		tracing::info!(
+			%zoom,
+			tile_url = source.tile_url(0, 0, zoom),
+			"resolved the first tile"
+		);
+		Ok::<_, Box<dyn std::error::Error>>((page, source, zoom))
+	};
+	Ok(match cli.metadata_timeout {
+		Some(timeout) => tokio::time::timeout(timeout, metadata)
+			.await
+			.map_err(|_| deathrip::Error::MetadataTimeout)??,
+		None => metadata.await?,
+	})
+}
+
+/// The title of the image, which names the output file by default: `--title`, or else the scraped
+/// title unless `--ignore-title`, or else a name with the current time.
+fn title(cli: &Cli, scraped: Option<String>) -> String {
+	if let Some(title) = &cli.title {
+		return title.clone();
+	}
+	scraped.filter(|_| !cli.ignore_title).unwrap_or_else(|| {
+		format!(
+			"{}_{}",
+			env!("CARGO_PKG_NAME"),
+			SystemTime::now()
+				.duration_since(SystemTime::UNIX_EPOCH)
+				.map(|time| time.as_millis())
+				.unwrap_or(0)
+		)
+	})
+}
+
+/// The rip options of the zoom level according to the CLI arguments.
+fn rip_options(
+	cli: &Cli,
+	zoom: deathrip::Zoom,
+	progress: Option<deathrip::ProgressCallback>,
+	on_tile: Option<deathrip::TileCallback>,
+) -> deathrip::RipOptions {
+	let mut options = deathrip::RipOptions::new(zoom);
+	options.tile_workers = cli.tile_workers;
+	options.retry_missing_after = cli.retry_missing_after;
+	options.max_tile_pixels = (cli.max_tile_pixels != 0).then_some(cli.max_tile_pixels);
+	options.progress = progress;
+	options.on_tile = on_tile;
+	options.auto_orient = !cli.no_auto_orient;
+	options.strict_tile_sizes = cli.strict_tile_sizes;
+	options.tile_size = cli.tile_size;
+	options.decode_retries = cli.retry_on_decode_error;
+	options.max_total_retries = cli.max_total_retries;
+	options.max_buffered_tiles = cli.max_buffered_tiles;
+	options.tile_store = match (&cli.tile_dir, &cli.cache_dir) {
+		(Some(tile_dir), _) => Some(Arc::clone(tile_dir) as Arc<dyn deathrip::TileStore>),
+		(None, Some(dir)) => Some(Arc::new(deathrip::DiskStore::new(dir.clone())) as _),
+		(None, None) => None,
+	};
+	options.bandwidth_limit = cli.bandwidth_limit.clone();
+	options
+}
+
+/// Rips all the plates of the item's page and writes them as the frames of an animated PNG, see
+/// `--animate`.
+async fn rip_plates(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	progress: Option<deathrip::ProgressCallback>,
+	on_tile: Option<deathrip::TileCallback>,
+) -> Result<Ripped, Box<dyn std::error::Error>> {
+	let time_start = Instant::now();
+	let Ok(input) = deathrip::Input::try_from(item.image);
+	let page_url = input
+		.page_url(deathrip::Input::DEFAULT_PAGE_URL_TEMPLATE)
+		.ok_or("animating the plates requires the image's page URL or item ID")?;
+	tracing::info!(page_url, "fetching the plates from page URL");
+	let html = deathrip::Page::fetch_html(&client, &page_url).await?;
+	let page = deathrip::Page::parse(&html)?;
+	let mut plates = deathrip::Page::parse_plates(&html);
+	tracing::info!("found {} plates", plates.len());
+	if let Some(pages) = &cli.pages {
+		let selected = pages.select(plates.len())?;
+		plates = selected
+			.into_iter()
+			.map(|number| plates[number - 1].clone())
+			.collect();
+		tracing::info!("selected {} plates", plates.len());
+	}
+
+	let mut frames = Vec::with_capacity(plates.len());
+	for (plate, base_url) in plates.into_iter().enumerate() {
+		let span_plate = tracing::info_span!("plate", plate, base_url);
+		let source = tile_source(cli, base_url);
+		let zoom = determine_zoom(cli, Arc::clone(&client), &source, item.zoom)
+			.instrument(span_plate.clone())
+			.await?;
+		if let Some(progress) = &progress {
+			progress(&deathrip::Progress::detected(deathrip::Detection::Zoom(
+				zoom,
+			)));
+		}
+		let options = rip_options(cli, zoom, progress.clone(), on_tile.clone());
+		let frame = deathrip::rip(Arc::clone(&client), &source, &options)
+			.instrument(span_plate)
+			.await?;
+		frames.push(frame);
+	}
+	let dimensions = frames.iter().fold((0, 0), |(width, height), frame| {
+		(width.max(frame.width()), height.max(frame.height()))
+	});
+
+	let mut buf = Vec::new();
+	deathrip::write_apng(&mut buf, &frames, cli.frame_duration)?;
+	let out_path = output_path(item, &title(cli, Some(page.title)), "png").await?;
+	if let Some(out_path) = &out_path {
+		tracing::info!(
+			"writing the animation to output file {}",
+			out_path.display()
+		);
+		fs::write(out_path, &buf).await?;
 	} else {
-		let zoom = deathrip::determine_max_zoom(Arc::clone(&client), &page.base_url, 4).await?;
-		tracing::info!("determined zoom level of {zoom}");
-		zoom
+		tracing::info!("writing the animation to output stream");
+		OutputStream::lock().write_all(&buf)?;
+	}
+	tracing::info!("finished in {}ms", time_start.elapsed().as_millis());
+	Ok(Ripped {
+		path: out_path,
+		dimensions,
+		bytes: Some(buf.len() as u64),
+		manifest: None,
+	})
+}
+
+/// Rips the image of the item according to the CLI arguments, and writes it.
+async fn rip_and_write(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	progress: Option<deathrip::ProgressCallback>,
+	on_tile: Option<deathrip::TileCallback>,
+) -> Result<Ripped, Box<dyn std::error::Error>> {
+	if cli.animate {
+		return rip_plates(cli, client, item, progress, on_tile).await;
+	}
+	let time_start = Instant::now();
+	let (page, source, zoom, mut grid, max_zoom) = match &item.manifest {
+		Some(manifest) => {
+			tracing::info!(
+				base_url = manifest.base_url,
+				zoom = %manifest.zoom,
+				"using the metadata of the manifest"
+			);
+			let page = deathrip::Page {
+				title:    title(cli, Some(manifest.title.clone())),
+				base_url: manifest.base_url.clone(),
+			};
+			let source = tile_source(cli, manifest.base_url.clone());
+			let grid = Some((manifest.columns, manifest.rows));
+			(page, source, manifest.zoom, grid, manifest.max_zoom)
+		}
+		None => {
+			let (page, source, zoom) = resolve(cli, Arc::clone(&client), item).await?;
+			// The zoom level is the maximum only if it was neither given nor clamped.
+			let max_zoom =
+				(item.zoom.is_none() && cli.max_zoom.is_none_or(|max| zoom < max)).then_some(zoom);
+			(page, source, zoom, None, max_zoom)
+		}
 	};
-	drop(span_zoom);
+	if let Some(progress) = &progress {
+		progress(&deathrip::Progress::detected(deathrip::Detection::Zoom(
+			zoom,
+		)));
+	}
+	let mut options = rip_options(cli, zoom, progress, on_tile);
+	if (cli.manifest || cli.watch.is_some()) && grid.is_none() {
+		// The manifest needs the tiles, so they're determined up front rather than by the rip.
+		let timeout = cli
+			.metadata_timeout
+			.map(|timeout| timeout.saturating_sub(time_start.elapsed()));
+		let dimensions = deathrip::determine_dimensions(
+			Arc::clone(&client),
+			&source,
+			zoom,
+			options.num_workers_half,
+			options.retry,
+		);
+		grid = Some(match timeout {
+			Some(timeout) => tokio::time::timeout(timeout, dimensions)
+				.await
+				.map_err(|_| deathrip::Error::MetadataTimeout)??,
+			None => dimensions.await?,
+		});
+	}
+	options.grid = grid;
+	let manifest = grid.map(|(columns, rows)| manifest::Manifest {
+		input: item.image.to_owned(),
+		title: page.title.clone(),
+		base_url: page.base_url.clone(),
+		zoom,
+		columns,
+		rows,
+		max_zoom,
+	});
 
-	let image = deathrip::rip(client, &page.base_url, zoom, 8)
-		.instrument(tracing::info_span!("ripping image"))
+	// The metadata time limit bounds the whole discovery phase, so the dimension detection gets
+	// whatever is left of it.
+	options.metadata_timeout = cli
+		.metadata_timeout
+		.map(|timeout| timeout.saturating_sub(time_start.elapsed()));
+
+	if let Some(dir) = &cli.all_zooms {
+		return rip_pyramid(cli, client, item, &source, &options, dir).await;
+	}
+
+	if let Some(dir) = &cli.tiles_dir {
+		fs::create_dir_all(dir).await?;
+		tracing::info!("saving the tiles to {}", dir.display());
+		let mut bytes = 0;
+		let zoom = options.zoom;
+		let dimensions = deathrip::rip_tiles(client, &source, &options, |position, tile, data| {
+			let path =
+				|extension: &str| dir.join(deathrip::TileDir::file_name(position, zoom, extension));
+			bytes += match &cli.tiles_format {
+				Some(format) => {
+					let extension =
+						deathrip::FormatSpec::of(format).map_or("png", |spec| spec.extension());
+					let mut file = std::fs::File::create(path(extension))
+						.map(BufWriter::new)
+						.map_err(deathrip::Error::WriteError)?;
+					fit_format(cli, tile, format).write_to(&mut file, format.clone())?;
+					let file = file.into_inner().map_err(|e| e.into_error());
+					file.and_then(|file| file.metadata())
+						.map_err(deathrip::Error::WriteError)?
+						.len()
+				}
+				None => {
+					let extension = image::guess_format(&data)
+						.map_or("bin", |format| format.extensions_str()[0]);
+					std::fs::write(path(extension), &data).map_err(deathrip::Error::WriteError)?;
+					data.len() as u64
+				}
+			};
+			Ok(())
+		})
+		.instrument(tracing::info_span!("ripping tiles"))
 		.await?;
+		tracing::info!("finished in {}ms", time_start.elapsed().as_millis());
+		return Ok(Ripped {
+			path: None,
+			dimensions,
+			bytes: Some(bytes),
+			manifest,
+		});
+	}
+
+	if cli.tile_window {
+		if !matches!(
+			item.format,
+			Format::Auto | Format::Fixed(ImageOutputFormat::Png)
+		) {
+			return Err("the tile window mode only supports the PNG format".into());
+		}
+		let out_path = output_path(item, &page.title, "png").await?;
+		let span_rip = tracing::info_span!("ripping image");
+		let (w, h) = if let Some(out_path) = &out_path {
+			tracing::info!(
+				"streaming ripped image to output file {}",
+				out_path.display()
+			);
+			let out_file = BufWriter::new(std::fs::File::create(out_path)?);
+			deathrip::rip_png_stream(client, &source, &options, out_file)
+				.instrument(span_rip)
+				.await?
+		} else {
+			tracing::info!("streaming ripped image to output stream");
+			deathrip::rip_png_stream(client, &source, &options, OutputStream::shared())
+				.instrument(span_rip)
+				.await?
+		};
+		tracing::info!(
+			"finished ripping {w}\u{00D7}{h} image in {}ms",
+			time_start.elapsed().as_millis()
+		);
+		let bytes = match &out_path {
+			Some(out_path) => Some(fs::metadata(out_path).await?.len()),
+			None => None,
+		};
+		return Ok(Ripped {
+			path: out_path,
+			dimensions: (w, h),
+			bytes,
+			manifest,
+		});
+	}
+
+	let placeholder = if cli.progressive {
+		write_placeholder(
+			cli,
+			Arc::clone(&client),
+			item,
+			&page.title,
+			&source,
+			&options,
+		)
+		.await?
+	} else {
+		None
+	};
+
+	let image = match cli.sample {
+		Some(count) => {
+			let sample = match cli.sample_seed {
+				Some(seed) => deathrip::Sample::Random { count, seed },
+				None => deathrip::Sample::First(count),
+			};
+			let rip = deathrip::rip_sample(client, &source, &options, sample)
+				.instrument(tracing::info_span!("ripping image sample"))
+				.await?;
+			tracing::info!(
+				"sampled {} tiles: {}",
+				rip.sampled.len(),
+				rip.sampled
+					.iter()
+					.map(|(x, y)| format!("({x},{y})"))
+					.join(" ")
+			);
+			rip.image
+		}
+		None => {
+			deathrip::rip(client, &source, &options)
+				.instrument(tracing::info_span!("ripping image"))
+				.await?
+		}
+	};
 	let dur_rip = time_start.elapsed();
 	tracing::info!("finished ripping image in {}ms", dur_rip.as_millis());
 
-	let atty = std::io::stdout().is_terminal();
-	if atty {
-		let out_path = cli
-			.output
-			.unwrap_or_else(|| PathBuf::from(format!("{}.{DEFAULT_EXTENSION}", page.title)));
+	let format = match &item.format {
+		Format::Auto => {
+			let format = deathrip::infer_output_format(&image);
+			tracing::info!("picked the {format:?} output format");
+			format
+		}
+		Format::Fixed(format) => format.clone(),
+		Format::Raw { .. } if cli.data_uri => {
+			return Err("the data URI doesn't support the raw formats".into());
+		}
+		Format::Raw { alpha } => {
+			if cli.verify_output {
+				tracing::warn!("not verifying the raw pixels, which have no dimensions to verify");
+			}
+			let image = pad_even(cli, image);
+			let (path, dimensions, bytes) =
+				write_raw(cli, item, &page.title, image, *alpha).await?;
+			tracing::info!("finished in {}ms", time_start.elapsed().as_millis());
+			return Ok(Ripped {
+				path,
+				dimensions,
+				bytes: Some(bytes),
+				manifest,
+			});
+		}
+	};
+	let image = fit_format(cli, pad_even(cli, image), &format);
+
+	let dimensions = (image.width(), image.height());
+	let extension = deathrip::FormatSpec::of(&format).map_or("png", |spec| spec.extension());
+	let out_path = if cli.data_uri {
+		None
+	} else {
+		output_path(item, &page.title, extension).await?
+	};
+	let bytes = if let Some(out_path) = &out_path {
 		tracing::info!("writing ripped image to output file {}", out_path.display());
-		if let Some(parent) = out_path.parent() {
-			fs::create_dir_all(parent).await?;
+		let verify = cli.verify_output.then_some(dimensions);
+		let bytes = write_atomically(out_path, verify, |file| {
+			Ok(image.write_to(file, format.clone())?)
+		})?;
+		if let Some(placeholder) = placeholder.filter(|placeholder| placeholder != out_path) {
+			fs::remove_file(placeholder).await?;
 		}
-		let mut out_file = fs::File::create(out_path).await?.into_std().await;
-		image.write_to(&mut out_file, cli.format)?;
+		bytes
 	} else {
 		tracing::info!("writing ripped image to output stream");
-		let (w, h) = image.dimensions();
+		let (w, h) = dimensions;
 		let mut buf = Vec::with_capacity(w as usize * h as usize * 3);
-		image.write_to(&mut Cursor::new(&mut buf), cli.format)?;
-		std::io::stdout().write_all(&buf)?;
-	}
+		image.write_to(&mut Cursor::new(&mut buf), format.clone())?;
+		if cli.verify_output {
+			verify_output(Cursor::new(&buf), dimensions)?;
+		}
+		if cli.data_uri {
+			let uri = data_uri(&buf, &format);
+			if uri.len() > DATA_URI_WARN_BYTES {
+				tracing::warn!(
+					"the data URI is {}, which may be too long to embed (e.g. for browsers and editors)",
+					deathrip::ByteSize(uri.len() as u64)
+				);
+			}
+			writeln!(OutputStream::lock(), "{uri}")?;
+		} else {
+			OutputStream::lock().write_all(&buf)?;
+		}
+		buf.len() as u64
+	};
 
 	let dur_total = time_start.elapsed();
 	tracing::info!("finished in {}ms", dur_total.as_millis());
+	Ok(Ripped {
+		path: out_path,
+		dimensions,
+		bytes: Some(bytes),
+		manifest,
+	})
+}
+
+/// Rips a lower zoom level of the image than the options' one and writes it to the output file,
+/// see `--progressive`, resolving to the path it was written to, if any.
+async fn write_placeholder(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	title: &str,
+	source: &deathrip::TileSource,
+	options: &deathrip::RipOptions,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+	let zoom = cli
+		.progressive_zoom
+		.unwrap_or(options.zoom.below(PROGRESSIVE_ZOOM_DROP));
+	if zoom >= options.zoom {
+		tracing::warn!(
+			"not writing a placeholder, since its zoom level {zoom} isn't lower than {}",
+			options.zoom
+		);
+		return Ok(None);
+	}
+	let mut options = options.clone();
+	options.zoom = zoom;
+	options.grid = None;
+	options.progress = None;
+	options.on_tile = None;
+	let image = deathrip::rip(client, source, &options)
+		.instrument(tracing::info_span!("ripping placeholder", %zoom))
+		.await?;
+	let format = match &item.format {
+		Format::Auto => deathrip::infer_output_format(&image),
+		Format::Fixed(format) => format.clone(),
+		Format::Raw { .. } => return Err("the placeholder doesn't support the raw formats".into()),
+	};
+	let image = fit_format(cli, image, &format);
+	let extension = deathrip::FormatSpec::of(&format).map_or("png", |spec| spec.extension());
+	let Some(path) = output_path(item, title, extension).await? else {
+		tracing::warn!("not writing a placeholder to the output stream");
+		return Ok(None);
+	};
+	write_atomically(
+		&path,
+		None,
+		|file| Ok(image.write_to(file, format.clone())?),
+	)?;
+	tracing::info!(
+		"wrote a {}\u{00D7}{} placeholder of zoom level {zoom} to {}",
+		image.width(),
+		image.height(),
+		path.display()
+	);
+	Ok(Some(path))
+}
+
+/// The base64 data URI of the encoded image, see `--data-uri`.
+fn data_uri(encoded: &[u8], format: &ImageOutputFormat) -> String {
+	use base64::Engine as _;
+	let mime_type = deathrip::FormatSpec::of(format).map_or("image/png", |spec| spec.mime_type());
+	format!(
+		"data:{mime_type};base64,{}",
+		base64::engine::general_purpose::STANDARD.encode(encoded)
+	)
+}
+
+/// Writes the file by writing a temporary file beside it and renaming it to the path, so that the
+/// path never has a partially written file. Resolves to the size of the file in bytes.
+///
+/// If given dimensions, the file is [verified](verify_output) to be an image of them before it's
+/// renamed.
+///
+/// The parent directory is created right before writing, and if it's removed while writing (e.g.
+/// on transient storage), it's created again and the file is written once more.
+fn write_atomically(
+	path: &std::path::Path,
+	verify: Option<(u32, u32)>,
+	write: impl Fn(&mut BufWriter<std::fs::File>) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+	let mut part_path = path.as_os_str().to_owned();
+	part_path.push(".part");
+	let part_path = PathBuf::from(part_path);
+	let attempt = || -> Result<u64, Box<dyn std::error::Error>> {
+		if let Some(parent) = path
+			.parent()
+			.filter(|parent| !parent.as_os_str().is_empty())
+		{
+			std::fs::create_dir_all(parent)?;
+		}
+		let written = (|| {
+			let mut file = BufWriter::new(std::fs::File::create(&part_path)?);
+			write(&mut file)?;
+			let file = file.into_inner().map_err(|e| e.into_error())?;
+			file.sync_all()?;
+			if let Some(dimensions) = verify {
+				let file = std::io::BufReader::new(std::fs::File::open(&part_path)?);
+				verify_output(file, dimensions)?;
+			}
+			Ok(file.metadata()?.len())
+		})();
+		match written {
+			Ok(bytes) => {
+				std::fs::rename(&part_path, path)?;
+				Ok(bytes)
+			}
+			Err(e) => {
+				let _ = std::fs::remove_file(&part_path);
+				Err(e)
+			}
+		}
+	};
+	match attempt() {
+		Err(e)
+			if e.downcast_ref::<std::io::Error>()
+				.is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+		{
+			tracing::warn!(
+				"the directory of {} was removed while writing it, retrying",
+				path.display()
+			);
+			attempt()
+		}
+		result => result,
+	}
+}
+
+/// Rips every zoom level of the image up to the options' one, each stitched to its own file
+/// `z<level>.<extension>` in the directory, see `--all-zooms`.
+async fn rip_pyramid(
+	cli: &Cli,
+	client: Arc<reqwest::Client>,
+	item: &Item<'_>,
+	source: &deathrip::TileSource,
+	options: &deathrip::RipOptions,
+	dir: &std::path::Path,
+) -> Result<Ripped, Box<dyn std::error::Error>> {
+	let time_start = Instant::now();
+	let format = match &item.format {
+		Format::Auto => None,
+		Format::Fixed(format) => Some(format.clone()),
+		Format::Raw { .. } => return Err("the zoom pyramid doesn't support the raw formats".into()),
+	};
+	let levels = deathrip::enumerate_zooms(
+		Arc::clone(&client),
+		source,
+		deathrip::Zoom::LOWEST..=options.zoom,
+		1,
+		options.retry,
+	)
+	.instrument(tracing::info_span!("measuring the zoom levels"))
+	.await?;
+	let estimate = levels.iter().map(|level| level.estimated_bytes).sum();
+	tracing::warn!(
+		"ripping {} zoom levels, which may take ~{} in total",
+		levels.len(),
+		deathrip::ByteSize(estimate)
+	);
+	fs::create_dir_all(dir).await?;
+
+	let ripped = futures::stream::iter(&levels)
+		.map(|level| {
+			let client = Arc::clone(&client);
+			let mut options = options.clone();
+			options.zoom = level.zoom;
+			options.grid = Some((level.columns, level.rows));
+			let format = format.clone();
+			async move {
+				let image = deathrip::rip(client, source, &options)
+					.instrument(tracing::info_span!("ripping zoom level", zoom = %level.zoom))
+					.await?;
+				let format = format.unwrap_or_else(|| deathrip::infer_output_format(&image));
+				let image = fit_format(cli, image, &format);
+				let extension =
+					deathrip::FormatSpec::of(&format).map_or("png", |spec| spec.extension());
+				let path = dir.join(format!("z{}.{extension}", level.zoom));
+				let mut file = BufWriter::new(std::fs::File::create(&path)?);
+				image.write_to(&mut file, format)?;
+				let bytes = file
+					.into_inner()
+					.map_err(|e| e.into_error())?
+					.metadata()?
+					.len();
+				let dimensions = (image.width(), image.height());
+				tracing::info!(
+					"wrote zoom level {} ({}\u{00D7}{}, {}) to {}",
+					level.zoom,
+					dimensions.0,
+					dimensions.1,
+					deathrip::ByteSize(bytes),
+					path.display()
+				);
+				Ok::<_, Box<dyn std::error::Error>>((dimensions, bytes))
+			}
+		})
+		.buffered(PYRAMID_WORKERS)
+		.try_collect::<Vec<_>>()
+		.await?;
+	let bytes = ripped.iter().map(|(_, bytes)| bytes).sum();
+	tracing::info!(
+		"wrote {} zoom levels, {} in total, in {}ms",
+		ripped.len(),
+		deathrip::ByteSize(bytes),
+		time_start.elapsed().as_millis()
+	);
+	Ok(Ripped {
+		path:       Some(dir.to_owned()),
+		dimensions: ripped.last().map_or((0, 0), |(dimensions, _)| *dimensions),
+		bytes:      Some(bytes),
+		manifest:   None,
+	})
+}
+
+/// Verifies that the encoded image decodes to the given dimensions, see `--verify-output`.
+fn verify_output(
+	data: impl std::io::BufRead + std::io::Seek,
+	(width, height): (u32, u32),
+) -> Result<(), deathrip::Error> {
+	let failed = |reason: String| deathrip::Error::OutputVerificationFailed(reason);
+	let image = image::io::Reader::new(data)
+		.with_guessed_format()
+		.map_err(|e| failed(e.to_string()))?
+		.decode()
+		.map_err(|e| failed(e.to_string()))?;
+	if (image.width(), image.height()) != (width, height) {
+		return Err(failed(format!(
+			"it's {}\u{00D7}{} instead of {width}\u{00D7}{height}",
+			image.width(),
+			image.height()
+		)));
+	}
+	tracing::debug!("verified the written image");
 	Ok(())
 }
 
+/// Pads the image to even dimensions if requested, see `--even-dimensions`.
+fn pad_even(cli: &Cli, image: DynamicImage) -> DynamicImage {
+	if !cli.even_dimensions {
+		return image;
+	}
+	let (width, height) = (image.width(), image.height());
+	let image = deathrip::pad_even(image);
+	if (image.width(), image.height()) != (width, height) {
+		tracing::info!(
+			"padded the image from {width}x{height} to {}x{} for even dimensions",
+			image.width(),
+			image.height()
+		);
+	}
+	image
+}
+
+/// Adapts the image to what the format supports: reduces its depth and flattens it if needed, and
+/// picks the PNG color type.
+fn fit_format(cli: &Cli, image: DynamicImage, format: &ImageOutputFormat) -> DynamicImage {
+	let color = image.color();
+	let deep = color.bytes_per_pixel() > color.channel_count();
+	let image = match (image, deathrip::FormatSpec::of(format)) {
+		(image, Some(spec)) if deep && !spec.deep => {
+			tracing::info!(
+				"the {} format doesn't support 16 bits per channel, reducing the image to 8 bits",
+				spec.name
+			);
+			DynamicImage::ImageRgba8(image.to_rgba8())
+		}
+		(image, _) => image,
+	};
+	let background = cli.flatten.or_else(|| {
+		deathrip::FormatSpec::of(format)
+			.filter(|spec| !spec.alpha)
+			.map(|_| DEFAULT_FLATTEN)
+	});
+	let image = match background {
+		Some(background) => {
+			tracing::debug!("flattening the image onto {background:?}");
+			deathrip::flatten(&image, background)
+		}
+		None => image,
+	};
+	if matches!(format, ImageOutputFormat::Png) {
+		let image = deathrip::fit_png(image, cli.png_color_type.into());
+		tracing::debug!("writing the PNG with the {:?} color type", image.color());
+		image
+	} else {
+		image
+	}
+}
+
+/// Writes the image's bare pixels (see `--format raw`), flattening it if it's without alpha.
+///
+/// Resolves to the output file (`None` for the standard output), the dimensions of the image, and
+/// the amount of bytes written.
+async fn write_raw(
+	cli: &Cli,
+	item: &Item<'_>,
+	title: &str,
+	image: DynamicImage,
+	alpha: bool,
+) -> Result<(Option<PathBuf>, (u32, u32), u64), Box<dyn std::error::Error>> {
+	let image = match cli.flatten.or((!alpha).then_some(DEFAULT_FLATTEN)) {
+		Some(background) => {
+			tracing::debug!("flattening the image onto {background:?}");
+			deathrip::flatten(&image, background)
+		}
+		None => image,
+	};
+	let dimensions = (image.width(), image.height());
+	let (pixels, extension) = if alpha {
+		(image.into_rgba8().into_raw(), "rgba")
+	} else {
+		(image.into_rgb8().into_raw(), "rgb")
+	};
+	let out_path = output_path(item, title, extension).await?;
+	if let Some(out_path) = &out_path {
+		tracing::info!("writing raw pixels to output file {}", out_path.display());
+		fs::write(out_path, &pixels).await?;
+	} else {
+		tracing::info!("writing raw pixels to output stream");
+		OutputStream::lock().write_all(&pixels)?;
+	}
+	Ok((out_path, dimensions, pixels.len() as u64))
+}
+
+/// The object of the error for `--json-errors`: its kind, message, and context (see
+/// [`deathrip::Error`]'s serialization).
+fn json_error(e: &(dyn std::error::Error + 'static)) -> serde_json::Value {
+	if let Some(e) = e.downcast_ref::<deathrip::Error>() {
+		return serde_json::to_value(e).unwrap_or_default();
+	}
+	if let Some(e) = e.downcast_ref::<deathrip::PageError>() {
+		return serde_json::to_value(e).unwrap_or_default();
+	}
+	let kind = if e.is::<std::io::Error>() {
+		"io"
+	} else {
+		"other"
+	};
+	serde_json::json!({ "kind": kind, "message": e.to_string() })
+}
+
+/// Prints the error object with the input it's of (if any) as a line of JSON to the standard error,
+/// see `--json-errors`.
+fn print_json_error(error: serde_json::Value, input: Option<&str>) {
+	eprintln!("{}", json_error_line(error, input));
+}
+
+/// The line of JSON of [`print_json_error`].
+fn json_error_line(mut error: serde_json::Value, input: Option<&str>) -> String {
+	if let Some(error) = error.as_object_mut() {
+		error.insert("input".to_owned(), input.into());
+	}
+	error.to_string()
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> ExitCode {
-	if let Err(e) = cli().await {
-		tracing::error!("{e}");
-		ExitCode::FAILURE
-	} else {
-		ExitCode::SUCCESS
+	let cli = Cli::parse();
+	let json_errors = cli.json_errors;
+	let input = match (&cli.from_manifest, &cli.from_tiles, cli.images.as_slice()) {
+		(Some(path), ..) | (_, Some(path), _) => Some(path.display().to_string()),
+		(.., [image]) => Some(image.clone()),
+		_ => None,
+	};
+	match self::cli(cli).await {
+		Ok(()) => ExitCode::SUCCESS,
+		// Like other Unix tools, exit quietly when the reader of the output closed it, e.g. `head`.
+		Err(e) if OUTPUT_CLOSED.load(Ordering::SeqCst) => {
+			tracing::debug!("the output stream was closed: {e}");
+			ExitCode::SUCCESS
+		}
+		Err(e) if json_errors => {
+			print_json_error(json_error(&*e), input.as_deref());
+			ExitCode::FAILURE
+		}
+		Err(e) => {
+			tracing::error!("{e}");
+			ExitCode::FAILURE
+		}
+	}
+}
+
+/// Whether the reader of the standard output closed it, see [`OutputStream`].
+static OUTPUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// The standard output, noting when its reader closes it, since the encoders writing to it don't
+/// necessarily keep the kind of its errors.
+struct OutputStream<W = std::io::StdoutLock<'static>>(W);
+
+impl OutputStream {
+	fn lock() -> Self { Self(std::io::stdout().lock()) }
+}
+
+impl OutputStream<std::io::Stdout> {
+	/// The standard output unlocked, which, unlike a locked one, can be written from another thread.
+	fn shared() -> Self { Self(std::io::stdout()) }
+}
+
+impl<W> OutputStream<W> {
+	fn note<T>(result: std::io::Result<T>) -> std::io::Result<T> {
+		if let Err(e) = &result {
+			if e.kind() == std::io::ErrorKind::BrokenPipe {
+				OUTPUT_CLOSED.store(true, Ordering::SeqCst);
+			}
+		}
+		result
+	}
+}
+
+impl<W: Write> Write for OutputStream<W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { Self::note(self.0.write(buf)) }
+
+	fn flush(&mut self) -> std::io::Result<()> { Self::note(self.0.flush()) }
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use image::GenericImageView as _;
+
+	use super::*;
+	use crate::mock::{self, tile_color, MockServer, Response, TempDir, TILE_SIZE};
+
+	/// Runs the rip of the single image of the arguments, like the CLI does, writing to a file even
+	/// though the standard output of the tests isn't a terminal.
+	async fn run(args: &[&str]) -> Result<Ripped, Box<dyn std::error::Error>> {
+		let cli = Cli::parse_from(std::iter::once("deathrip").chain(args.iter().copied()));
+		let client = Arc::new(client(&cli)?);
+		let item = Item {
+			to_file: true,
+			..Item::new(&cli, &cli.images[0])
+		};
+		rip_image(&cli, client, &item, None).await
+	}
+
+	/// Stitches the tile directory like `--from-tiles` does, to the output file.
+	async fn run_from_tiles(
+		dir: &Path,
+		output: &Path,
+	) -> Result<Ripped, Box<dyn std::error::Error>> {
+		let mut cli = Cli::parse_from([
+			"deathrip".as_ref(),
+			"--from-tiles".as_ref(),
+			dir.as_os_str(),
+			"--output".as_ref(),
+			output.as_os_str(),
+		]);
+		let tile_dir = Arc::new(deathrip::TileDir::scan(dir)?);
+		cli.tile_dir = Some(Arc::clone(&tile_dir));
+		let manifest = tile_dir_manifest(&cli, &tile_dir)?;
+		let input = manifest.input.clone();
+		let item = Item {
+			manifest: Some(manifest),
+			to_file: true,
+			..Item::new(&cli, &input)
+		};
+		let client = Arc::new(client(&cli)?);
+		rip_image(&cli, client, &item, None).await
+	}
+
+	fn assert_tiles(image: &DynamicImage, (columns, rows): (usize, usize)) {
+		assert_eq!(
+			image.dimensions(),
+			(columns as u32 * TILE_SIZE, rows as u32 * TILE_SIZE)
+		);
+		for (x, y) in (0..columns).cartesian_product(0..rows) {
+			let pixel = image.get_pixel(x as u32 * TILE_SIZE, y as u32 * TILE_SIZE);
+			assert_eq!(pixel, tile_color(x, y), "tile ({x},{y})");
+		}
+	}
+
+	#[tokio::test]
+	async fn tiles_dir_round_trips_through_from_tiles() {
+		let server = MockServer::image(3, 2, 1);
+		let dir = TempDir::new("tiles-dir");
+		let tiles = dir.join("tiles");
+		let tiles_arg = tiles.to_str().unwrap();
+		run(&[&server.base_url(), "--zoom", "1", "--tiles-dir", tiles_arg])
+			.await
+			.unwrap();
+		let output = dir.join("stitched.png");
+		run_from_tiles(&tiles, &output).await.unwrap();
+		assert_tiles(&image::open(&output).unwrap(), (3, 2));
+
+		// A missing tile fails the stitching up front.
+		let name = deathrip::TileDir::file_name((2, 0), deathrip::Zoom::new(1).unwrap(), "png");
+		std::fs::remove_file(tiles.join(name)).unwrap();
+		let error = run_from_tiles(&tiles, &output).await.unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"the tile directory is missing 1 tiles of zoom level 1: (2,0)"
+		);
+	}
+
+	#[tokio::test]
+	async fn archive_tiles_are_named_like_tiles_dir() {
+		let server = MockServer::image(2, 2, 0);
+		let dir = TempDir::new("archive");
+		let archive = dir.join("rip.zip");
+		let output = dir.join("image.png");
+		run(&[
+			&server.base_url(),
+			"--zoom",
+			"0",
+			"--archive",
+			archive.to_str().unwrap(),
+			"--output",
+			output.to_str().unwrap(),
+		])
+		.await
+		.unwrap();
+		let mut zip = zip::ZipArchive::new(std::fs::File::open(&archive).unwrap()).unwrap();
+		let names = zip
+			.file_names()
+			.map(str::to_owned)
+			.sorted()
+			.collect::<Vec<_>>();
+		assert_eq!(
+			names,
+			[
+				"image.png",
+				"tiles/x0_y0_z0.png",
+				"tiles/x0_y1_z0.png",
+				"tiles/x1_y0_z0.png",
+				"tiles/x1_y1_z0.png"
+			]
+		);
+		// The archived tiles stitch back.
+		let tiles = dir.join("tiles");
+		std::fs::create_dir(&tiles).unwrap();
+		for name in &names[1..] {
+			let mut file = zip.by_name(name).unwrap();
+			let mut tile = std::fs::File::create(dir.join(name)).unwrap();
+			std::io::copy(&mut file, &mut tile).unwrap();
+		}
+		drop(zip);
+		let stitched = dir.join("stitched.png");
+		run_from_tiles(&tiles, &stitched).await.unwrap();
+		assert_tiles(&image::open(&stitched).unwrap(), (2, 2));
+	}
+
+	#[tokio::test]
+	async fn reports_detections_before_tiles() {
+		let server = MockServer::start(|method, path| {
+			let response = match mock::parse_tile(path) {
+				Some((0, y, z)) if y < 3 && z <= 2 => Response::ok(mock::tile(0, y)),
+				_ => Response::status(404),
+			};
+			// The rows are determined after the columns.
+			let is_row_probe = path
+				.rsplit('-')
+				.next()
+				.is_some_and(|axis| axis.starts_with('y'));
+			match (method, is_row_probe) {
+				("HEAD", true) => response.with_delay(Duration::from_millis(100)),
+				_ => response,
+			}
+		});
+		let dir = TempDir::new("detections");
+		let output = dir.join("image.png");
+		let cli = Cli::parse_from([
+			"deathrip",
+			&server.base_url(),
+			"--output",
+			output.to_str().unwrap(),
+		]);
+		let client = Arc::new(client(&cli).unwrap());
+		let item = Item {
+			to_file: true,
+			..Item::new(&cli, &cli.images[0])
+		};
+		let (callback, progress) = deathrip::progress_stream();
+		rip_and_write(&cli, client, &item, Some(callback), None)
+			.await
+			.unwrap();
+		let progress = progress.collect::<Vec<_>>().await;
+		let detections = progress
+			.iter()
+			.filter_map(|progress| progress.detected)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			detections,
+			[
+				deathrip::Detection::Zoom(deathrip::Zoom::new(2).unwrap()),
+				deathrip::Detection::Columns(1),
+				deathrip::Detection::Rows(3),
+			]
+		);
+		let last_detection = progress
+			.iter()
+			.rposition(|progress| progress.detected.is_some())
+			.unwrap();
+		let first_tiles = progress
+			.iter()
+			.position(|progress| progress.phase == deathrip::Phase::Tiles)
+			.unwrap();
+		assert!(last_detection < first_tiles);
+	}
+
+	#[tokio::test]
+	async fn manifest_round_trips_without_probing() {
+		let server = MockServer::image(3, 2, 1);
+		let dir = TempDir::new("manifest");
+		let output = dir.join("image.png");
+		run(&[
+			&server.base_url(),
+			"--manifest",
+			"--output",
+			output.to_str().unwrap(),
+		])
+		.await
+		.unwrap();
+		let manifest_path = dir.join("image.png.manifest.json");
+		let manifest = manifest::Manifest::read(&manifest_path).unwrap();
+		assert_eq!(manifest.base_url, server.base_url());
+		assert_eq!(manifest.zoom, deathrip::Zoom::new(1).unwrap());
+		assert_eq!((manifest.columns, manifest.rows), (3, 2));
+
+		let probed = server.requests().len();
+		let rerip = dir.join("rerip.png");
+		let cli = Cli::parse_from([
+			"deathrip".as_ref(),
+			"--from-manifest".as_ref(),
+			manifest_path.as_os_str(),
+			"--output".as_ref(),
+			rerip.as_os_str(),
+		]);
+		let input = manifest.input.clone();
+		let item = Item {
+			manifest: Some(manifest),
+			to_file: true,
+			..Item::new(&cli, &input)
+		};
+		let client = Arc::new(client(&cli).unwrap());
+		rip_image(&cli, client, &item, None).await.unwrap();
+		assert_tiles(&image::open(&rerip).unwrap(), (3, 2));
+		// Only the tiles are fetched again.
+		let requests = server.requests();
+		let fetched = &requests[probed..];
+		assert_eq!(fetched.len(), 6, "{fetched:?}");
+		assert!(fetched.iter().all(|request| request.starts_with("GET ")));
+	}
+
+	#[tokio::test]
+	async fn json_errors_are_json() {
+		let server = MockServer::image(3, 2, 0);
+		let image = server.url("/ggpht.com/missing");
+		let error = run(&[&image, "--zoom", "0"]).await.unwrap_err();
+		let line = json_error_line(json_error(&*error), Some(&image));
+		assert!(!line.contains('\n'));
+		let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+		assert_eq!(json["kind"], "tile_missing", "{json}");
+		assert_eq!(json["message"], error.to_string());
+		assert_eq!(json["input"], image);
+
+		let error = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+		let json: serde_json::Value =
+			serde_json::from_str(&json_error_line(json_error(&error), None)).unwrap();
+		assert_eq!(
+			json,
+			serde_json::json!({ "kind": "io", "message": "no such file", "input": null })
+		);
+	}
+
+	#[test]
+	fn completions_of_each_shell() {
+		use clap::ValueEnum as _;
+		for &shell in clap_complete::Shell::value_variants() {
+			let mut command = <Cli as clap::CommandFactory>::command();
+			let mut script = Vec::new();
+			clap_complete::generate(shell, &mut command, env!("CARGO_PKG_NAME"), &mut script);
+			let script = String::from_utf8(script).unwrap();
+			for word in ["deathrip", "completions", "format"] {
+				assert!(script.contains(word), "{shell} completions lack {word}");
+			}
+		}
+	}
+
+	#[test]
+	fn data_uri_decodes_back() {
+		use base64::Engine as _;
+		let image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 2, |x, y| {
+			tile_color(x as usize, y as usize)
+		}));
+		for (format, mime_type) in [
+			(ImageOutputFormat::Png, "image/png"),
+			(ImageOutputFormat::Jpeg(90), "image/jpeg"),
+		] {
+			let mut encoded = Vec::new();
+			image
+				.write_to(&mut std::io::Cursor::new(&mut encoded), format.clone())
+				.unwrap();
+			let uri = data_uri(&encoded, &format);
+			let data = uri
+				.strip_prefix(&format!("data:{mime_type};base64,"))
+				.unwrap_or_else(|| panic!("{uri}"));
+			let decoded = base64::engine::general_purpose::STANDARD
+				.decode(data)
+				.unwrap();
+			assert_eq!(decoded, encoded);
+			let decoded = image::load_from_memory(&decoded).unwrap();
+			assert_eq!(decoded.dimensions(), (3, 2));
+			if format == ImageOutputFormat::Png {
+				assert_eq!(decoded.to_rgba8(), image.to_rgba8());
+			}
+		}
 	}
 }
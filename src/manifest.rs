@@ -0,0 +1,45 @@
+//! Manifests: the metadata of a rip, written next to the output file with `--manifest` and read
+//! back with `--from-manifest` to re-rip the image without determining its metadata again.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The metadata of a rip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+	/// The input of the image, as given on the command line.
+	pub input:    String,
+	/// The title of the image, which names the output file by default.
+	pub title:    String,
+	/// The base URL of the image.
+	pub base_url: String,
+	/// The zoom level of the rip.
+	pub zoom:     deathrip::Zoom,
+	/// The columns of tiles in the zoom level.
+	pub columns:  usize,
+	/// The rows of tiles in the zoom level.
+	pub rows:     usize,
+	/// The maximum zoom level of the image when it was ripped, if it was determined.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_zoom: Option<deathrip::Zoom>,
+}
+
+impl Manifest {
+	/// Reads a manifest from the file.
+	pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		let file = std::fs::File::open(path)?;
+		Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+	}
+
+	/// Writes the manifest to a sidecar `<file>.manifest.json` next to the output file, resolving to
+	/// the sidecar's path.
+	pub fn write_beside(&self, path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+		let mut manifest_path = path.as_os_str().to_owned();
+		manifest_path.push(".manifest.json");
+		let manifest_path = PathBuf::from(manifest_path);
+		let file = std::fs::File::create(&manifest_path)?;
+		serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)?;
+		Ok(manifest_path)
+	}
+}
@@ -0,0 +1,214 @@
+//! A mock tile server for the tests, serving on background threads of a local port.
+
+use std::{
+	io::{BufRead, BufReader, Write},
+	net::{SocketAddr, TcpListener, TcpStream},
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+/// The width and height of the tiles of [`MockServer::image`].
+pub const TILE_SIZE: u32 = 8;
+
+/// The path of the image of [`MockServer::image`], which passes for a base URL of an image.
+pub const IMAGE_PATH: &str = "/ggpht.com/img";
+
+/// A response of the mock server.
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+	pub status:  u16,
+	pub headers: Vec<(&'static str, String)>,
+	pub body:    Vec<u8>,
+	/// The time to wait before responding.
+	pub delay:   Duration,
+}
+
+impl Response {
+	/// An HTTP 200 response with the body.
+	pub fn ok(body: Vec<u8>) -> Self {
+		Self {
+			status: 200,
+			body,
+			..Self::default()
+		}
+	}
+
+	/// An empty response with the status.
+	pub fn status(status: u16) -> Self {
+		Self {
+			status,
+			..Self::default()
+		}
+	}
+
+	pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+		self.headers.push((name, value.into()));
+		self
+	}
+
+	pub fn with_delay(mut self, delay: Duration) -> Self {
+		self.delay = delay;
+		self
+	}
+}
+
+/// The handler of the requests, given their method and path.
+type Handler = dyn Fn(&str, &str) -> Response + Send + Sync;
+
+/// A mock server on a local port.
+pub struct MockServer {
+	address:  SocketAddr,
+	/// The requests served so far, as `<method> <path>`.
+	requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+	/// Starts a server that responds to requests with the handler.
+	pub fn start(handler: impl Fn(&str, &str) -> Response + Send + Sync + 'static) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let handler: Arc<Handler> = Arc::new(handler);
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		{
+			let requests = Arc::clone(&requests);
+			std::thread::spawn(move || {
+				for stream in listener.incoming().flatten() {
+					let (handler, requests) = (Arc::clone(&handler), Arc::clone(&requests));
+					std::thread::spawn(move || serve(stream, &*handler, &requests));
+				}
+			});
+		}
+		Self { address, requests }
+	}
+
+	/// Starts a server of an image at [`IMAGE_PATH`] whose zoom levels up to `max_zoom` are each a grid of
+	/// the given columns and rows of [`TILE_SIZE`] tiles, each of the [color](tile_color) of its
+	/// position.
+	pub fn image(columns: usize, rows: usize, max_zoom: usize) -> Self {
+		Self::start(move |_, path| match parse_tile(path) {
+			Some((x, y, z)) if x < columns && y < rows && z <= max_zoom => Response::ok(tile(x, y)),
+			_ => Response::status(404),
+		})
+	}
+
+	/// The URL of the path on the server.
+	pub fn url(&self, path: &str) -> String { format!("http://{}{path}", self.address) }
+
+	/// The base URL of the image of [`MockServer::image`].
+	pub fn base_url(&self) -> String { self.url(IMAGE_PATH) }
+
+	/// The requests served so far, as `<method> <path>`.
+	pub fn requests(&self) -> Vec<String> { self.requests.lock().unwrap().clone() }
+
+	/// The amount of tiles fetched (rather than probed) so far.
+	pub fn tile_fetches(&self) -> usize {
+		self.requests()
+			.iter()
+			.filter(|request| request.starts_with("GET ") && parse_tile(&request[4..]).is_some())
+			.count()
+	}
+}
+
+/// Serves the requests of a (keep-alive) connection.
+fn serve(stream: TcpStream, handler: &Handler, requests: &Mutex<Vec<String>>) {
+	let mut reader = BufReader::new(stream.try_clone().unwrap());
+	let mut writer = stream;
+	loop {
+		let mut request_line = String::new();
+		if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+			return;
+		}
+		// Skip the headers.
+		loop {
+			let mut header = String::new();
+			if reader.read_line(&mut header).unwrap_or(0) == 0 {
+				return;
+			}
+			if header == "\r\n" {
+				break;
+			}
+		}
+		let mut parts = request_line.split_whitespace();
+		let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+		requests.lock().unwrap().push(format!("{method} {path}"));
+		let response = handler(method, path);
+		std::thread::sleep(response.delay);
+		let mut head = format!(
+			"HTTP/1.1 {} Mock\r\nContent-Length: {}\r\n",
+			response.status,
+			response.body.len()
+		);
+		for (name, value) in &response.headers {
+			head.push_str(&format!("{name}: {value}\r\n"));
+		}
+		head.push_str("\r\n");
+		let written = writer
+			.write_all(head.as_bytes())
+			.and_then(|()| match method {
+				"HEAD" => Ok(()),
+				_ => writer.write_all(&response.body),
+			});
+		if written.is_err() {
+			return;
+		}
+	}
+}
+
+/// The column, row, and zoom level of a tile's path, e.g. `/ggpht.com/img=x1-y2-z3`.
+pub fn parse_tile(path: &str) -> Option<(usize, usize, usize)> {
+	let params = path.strip_prefix(IMAGE_PATH)?.strip_prefix('=')?;
+	let (mut x, mut y, mut z) = (None, None, None);
+	for param in params.split('-') {
+		let (axis, value) = param.split_at(1);
+		let value = value.parse::<usize>().ok()?;
+		match axis {
+			"x" => x = Some(value),
+			"y" => y = Some(value),
+			"z" => z = Some(value),
+			_ => return None,
+		}
+	}
+	Some((x?, y?, z?))
+}
+
+/// The color of the tile at the position, unique for small grids.
+pub fn tile_color(x: usize, y: usize) -> image::Rgba<u8> {
+	image::Rgba([(x * 40) as u8, (y * 40) as u8, 200, 255])
+}
+
+/// The encoded PNG of the tile at the position.
+pub fn tile(x: usize, y: usize) -> Vec<u8> {
+	let tile = image::RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, tile_color(x, y));
+	let mut data = Vec::new();
+	image::DynamicImage::ImageRgba8(tile)
+		.write_to(
+			&mut std::io::Cursor::new(&mut data),
+			image::ImageOutputFormat::Png,
+		)
+		.unwrap();
+	data
+}
+
+/// A fresh temporary directory of a test, removed when dropped, even if the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+	/// Creates the directory of the test of the given name, emptying any leftover of a previous run.
+	pub fn new(name: &str) -> Self {
+		let dir = std::env::temp_dir().join(format!("deathrip-{name}-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		Self(dir)
+	}
+}
+
+impl std::ops::Deref for TempDir {
+	type Target = Path;
+
+	fn deref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+}
@@ -0,0 +1,125 @@
+//! EXIF orientation.
+
+use image::DynamicImage;
+
+/// Reads the EXIF orientation (1 to 8) from the encoded image, if it has any.
+pub fn read(data: &[u8]) -> Option<u32> {
+	let exif = exif::Reader::new()
+		.read_from_container(&mut std::io::Cursor::new(data))
+		.ok()?;
+	exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+		.value
+		.get_uint(0)
+}
+
+/// Transforms the image so that an image with the given EXIF orientation is upright.
+pub fn apply(image: DynamicImage, orientation: u32) -> DynamicImage {
+	match orientation {
+		2 => image.fliph(),
+		3 => image.rotate180(),
+		4 => image.flipv(),
+		5 => image.rotate90().fliph(),
+		6 => image.rotate90(),
+		7 => image.rotate270().fliph(),
+		8 => image.rotate270(),
+		_ => image,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{io::Cursor, sync::Arc};
+
+	use super::*;
+	use crate::mock::{self, MockServer, Response};
+
+	/// The samples of the gray image, by row.
+	fn rows(image: &DynamicImage) -> Vec<Vec<u8>> {
+		let image = image.to_luma8();
+		image
+			.rows()
+			.map(|row| row.map(|pixel| pixel.0[0]).collect())
+			.collect()
+	}
+
+	#[test]
+	fn applies_each_orientation() {
+		// 1 2 3
+		// 4 5 6
+		let image = DynamicImage::ImageLuma8(
+			image::GrayImage::from_raw(3, 2, vec![1, 2, 3, 4, 5, 6]).unwrap(),
+		);
+		let expected: [&[&[u8]]; 8] = [
+			&[&[1, 2, 3], &[4, 5, 6]],
+			&[&[3, 2, 1], &[6, 5, 4]],
+			&[&[6, 5, 4], &[3, 2, 1]],
+			&[&[4, 5, 6], &[1, 2, 3]],
+			&[&[1, 4], &[2, 5], &[3, 6]],
+			&[&[4, 1], &[5, 2], &[6, 3]],
+			&[&[6, 3], &[5, 2], &[4, 1]],
+			&[&[3, 6], &[2, 5], &[1, 4]],
+		];
+		for (orientation, expected) in (1..).zip(expected) {
+			assert_eq!(
+				rows(&apply(image.clone(), orientation)),
+				expected,
+				"orientation {orientation}"
+			);
+		}
+	}
+
+	/// The tile at the position as a JPEG of the given EXIF orientation.
+	fn oriented_tile(x: usize, y: usize, orientation: u8) -> Vec<u8> {
+		let tile = image::RgbImage::from_pixel(
+			mock::TILE_SIZE,
+			mock::TILE_SIZE,
+			image::Rgb(mock::tile_color(x, y).0[..3].try_into().unwrap()),
+		);
+		let mut jpeg = Vec::new();
+		DynamicImage::ImageRgb8(tile)
+			.write_to(
+				&mut Cursor::new(&mut jpeg),
+				image::ImageOutputFormat::Jpeg(100),
+			)
+			.unwrap();
+		// A big-endian TIFF of a single IFD entry: the orientation, a short.
+		let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+		exif.extend_from_slice(&[0, orientation, 0, 0, 0, 0, 0, 0]);
+		// An APP1 segment right after the start of the image.
+		let mut segment = vec![0xff, 0xe1];
+		segment.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+		segment.extend_from_slice(&exif);
+		jpeg.splice(2..2, segment);
+		jpeg
+	}
+
+	#[tokio::test]
+	async fn stitched_image_is_upright() {
+		assert_eq!(read(&oriented_tile(0, 0, 6)), Some(6));
+		assert_eq!(read(&mock::tile(0, 0)), None);
+
+		let server = MockServer::start(|_, path| match mock::parse_tile(path) {
+			Some((x, 0, 0)) if x < 2 => Response::ok(oriented_tile(x, 0, 6)),
+			_ => Response::status(404),
+		});
+		let source = crate::TileSource::from(server.base_url());
+		let options = crate::RipOptions {
+			grid: Some((2, 1)),
+			..crate::RipOptions::new(crate::Zoom::new(0).unwrap())
+		};
+		let image = crate::rip(Arc::new(reqwest::Client::new()), &source, &options)
+			.await
+			.unwrap()
+			.into_rgba8();
+		// The row of tiles is rotated clockwise into a column, from the top.
+		let size = mock::TILE_SIZE;
+		assert_eq!(image.dimensions(), (size, 2 * size));
+		for x in 0..2 {
+			let pixel = image.get_pixel(size / 2, x as u32 * size + size / 2);
+			let expected = mock::tile_color(x, 0);
+			// JPEG is lossy.
+			let close = (pixel.0.iter().zip(expected.0)).all(|(&a, b)| a.abs_diff(b) <= 4);
+			assert!(close, "tile ({x},0) is {pixel:?} rather than {expected:?}");
+		}
+	}
+}
@@ -0,0 +1,150 @@
+//! Progress reporting.
+
+use std::{
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc,
+	},
+	time::{Duration, Instant},
+};
+
+use crate::retry::RetryBudget;
+
+/// A phase of a rip.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+	/// Determining the layout of the image.
+	Metadata,
+	/// Fetching the tiles.
+	Tiles,
+}
+
+/// A metadata value that was determined, see [`Progress::detected`].
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+	/// The zoom level.
+	Zoom(crate::Zoom),
+	/// The columns of tiles.
+	Columns(usize),
+	/// The rows of tiles.
+	Rows(usize),
+}
+
+/// The progress of a rip, see [`RipOptions::progress`](crate::RipOptions::progress).
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+	/// The current phase.
+	pub phase:       Phase,
+	/// The amount of tiles done, including tiles that were already done when the phase started
+	/// (e.g. the first tile, which is fetched with the metadata).
+	pub tiles_done:  usize,
+	/// The total amount of tiles, or 0 if not yet known.
+	pub tiles_total: usize,
+	/// The amount of tile bytes fetched in the phase.
+	pub bytes:       u64,
+	/// The estimated time until the phase is done, if it can be estimated yet.
+	pub eta:         Option<Duration>,
+	/// The metadata value that was just determined, if that's what's being reported.
+	pub detected:    Option<Detection>,
+	/// The total time spent backing off between the retries of the tiles so far, see
+	/// [`RetryPolicy`](crate::RetryPolicy).
+	pub backed_off:  Duration,
+}
+
+impl Progress {
+	/// The progress of determining the given metadata value.
+	pub fn detected(detection: Detection) -> Self {
+		Self {
+			phase:       Phase::Metadata,
+			tiles_done:  0,
+			tiles_total: 0,
+			bytes:       0,
+			eta:         None,
+			detected:    Some(detection),
+			backed_off:  Duration::ZERO,
+		}
+	}
+}
+
+/// A progress callback, see [`RipOptions::progress`](crate::RipOptions::progress).
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// A progress callback that sends the progress to the returned stream, which ends once the
+/// callback (and all of its clones) is dropped, e.g. with the options of the rip it's set on.
+///
+/// The stream is unbounded, so it never holds the rip back, but it should be consumed as the rip
+/// goes lest the progress pile up.
+pub fn progress_stream() -> (ProgressCallback, impl futures::Stream<Item = Progress>) {
+	let (sender, receiver) = futures::channel::mpsc::unbounded();
+	let callback = Arc::new(move |progress: &Progress| {
+		// The receiver may have been dropped by a consumer that lost interest.
+		let _ = sender.unbounded_send(*progress);
+	});
+	(callback, receiver)
+}
+
+/// Tracks the progress of fetching tiles and reports it to the callback, if any.
+pub(crate) struct TileProgress<'a> {
+	callback: Option<&'a ProgressCallback>,
+	/// The retries of the rip, for the time spent backing off.
+	budget:   &'a RetryBudget,
+	start:    Instant,
+	/// The amount of tiles that were done before the tracking started.
+	initial:  usize,
+	total:    usize,
+	done:     AtomicUsize,
+	bytes:    AtomicU64,
+}
+
+impl<'a> TileProgress<'a> {
+	/// Starts tracking, reporting the initial progress.
+	pub fn new(
+		callback: Option<&'a ProgressCallback>,
+		budget: &'a RetryBudget,
+		done: usize,
+		total: usize,
+	) -> Self {
+		let progress = Self {
+			callback,
+			budget,
+			start: Instant::now(),
+			initial: done,
+			total,
+			done: AtomicUsize::new(done),
+			bytes: AtomicU64::new(0),
+		};
+		progress.report(done, 0);
+		progress
+	}
+
+	/// Marks a tile of the given size in bytes as done.
+	pub fn advance(&self, bytes: usize) {
+		if self.callback.is_none() {
+			return;
+		}
+		let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+		let bytes = self.bytes.fetch_add(bytes as u64, Ordering::SeqCst) + bytes as u64;
+		self.report(done, bytes);
+	}
+
+	fn report(&self, done: usize, bytes: u64) {
+		let Some(callback) = self.callback else {
+			return;
+		};
+		let fetched = done - self.initial;
+		let eta = (fetched > 0).then(|| {
+			self.start
+				.elapsed()
+				.mul_f64(self.total.saturating_sub(done) as f64 / fetched as f64)
+		});
+		callback(&Progress {
+			phase: Phase::Tiles,
+			tiles_done: done,
+			tiles_total: self.total,
+			bytes,
+			eta,
+			detected: None,
+			backed_off: self.budget.backed_off(),
+		});
+	}
+}
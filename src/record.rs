@@ -0,0 +1,142 @@
+//! The `--record-requests` file: a record of every HTTP request, e.g. to attach to a bug report.
+
+use std::{
+	fs::File,
+	io::{LineWriter, Write as _},
+	path::Path,
+	sync::Mutex,
+	time::SystemTime,
+};
+
+/// The record of a request, see [`deathrip::send_request`].
+#[derive(Debug, Default, serde::Serialize)]
+struct RequestRecord {
+	/// The time the request was answered (or failed) in Unix milliseconds.
+	time_ms:     u64,
+	method:      String,
+	url:         String,
+	/// The headers of the request, with credentials redacted.
+	headers:     serde_json::Value,
+	/// The HTTP status of the response, if any.
+	status:      Option<u64>,
+	/// The error of the request, if any.
+	error:       Option<String>,
+	/// The time it took to get the response in milliseconds.
+	duration_ms: u64,
+}
+
+impl tracing::field::Visit for RequestRecord {
+	fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+		match field.name() {
+			"status" => self.status = Some(value),
+			"duration_ms" => self.duration_ms = value,
+			_ => {}
+		}
+	}
+
+	fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+		match field.name() {
+			"method" => self.method = value.to_owned(),
+			"url" => self.url = value.to_owned(),
+			"headers" => self.headers = serde_json::from_str(value).unwrap_or_default(),
+			"error" => self.error = Some(value.to_owned()),
+			_ => {}
+		}
+	}
+
+	fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+		// Display fields (e.g. %url) are recorded through their Debug, which displays them.
+		self.record_str(field, &format!("{value:?}"));
+	}
+}
+
+/// Writes the records of the requests as newline-delimited JSON to a file, from the events of
+/// [`deathrip::REQUEST_TARGET`].
+pub struct RequestRecorder {
+	file: Mutex<LineWriter<File>>,
+}
+
+impl RequestRecorder {
+	/// Creates the file to record the requests to.
+	pub fn create(path: &Path) -> std::io::Result<Self> {
+		Ok(Self {
+			file: Mutex::new(LineWriter::new(File::create(path)?)),
+		})
+	}
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RequestRecorder {
+	fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+		let mut record = RequestRecord {
+			time_ms: SystemTime::now()
+				.duration_since(SystemTime::UNIX_EPOCH)
+				.map_or(0, |time| time.as_millis() as u64),
+			..RequestRecord::default()
+		};
+		event.record(&mut record);
+		let mut file = self.file.lock().unwrap();
+		let written = serde_json::to_writer(&mut *file, &record)
+			.map_err(std::io::Error::from)
+			.and_then(|()| file.write_all(b"\n"));
+		if let Err(e) = written {
+			// Logging it would record it as well, were it a request.
+			eprintln!("failed to record a request: {e}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use tracing_subscriber::{layer::SubscriberExt as _, Layer as _};
+
+	use super::*;
+	use crate::mock::{MockServer, TempDir};
+
+	#[tokio::test]
+	async fn records_each_request() {
+		let dir = TempDir::new("record");
+		let path = dir.join("requests.jsonl");
+		let recorder = RequestRecorder::create(&path).unwrap().with_filter(
+			tracing_subscriber::filter::Targets::new()
+				.with_target(deathrip::REQUEST_TARGET, tracing::Level::DEBUG),
+		);
+		let subscriber = tracing_subscriber::registry().with(recorder);
+		let guard = tracing::subscriber::set_default(subscriber);
+
+		let server = MockServer::image(3, 2, 0);
+		let client = Arc::new(reqwest::Client::new());
+		let source = deathrip::TileSource::from(server.base_url());
+		let options = deathrip::RipOptions::new(deathrip::Zoom::new(0).unwrap());
+		deathrip::rip(Arc::clone(&client), &source, &options)
+			.await
+			.unwrap();
+		let request = client
+			.get(server.url("/private"))
+			.header(reqwest::header::AUTHORIZATION, "Bearer secret");
+		deathrip::send_request(request).await.unwrap();
+		drop(guard);
+
+		let records = std::fs::read_to_string(&path).unwrap();
+		let records = records
+			.lines()
+			.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(records.len(), server.requests().len());
+		let mut recorded = records
+			.iter()
+			.map(|record| {
+				let url = reqwest::Url::parse(record["url"].as_str().unwrap()).unwrap();
+				format!("{} {}", record["method"].as_str().unwrap(), url.path())
+			})
+			.collect::<Vec<_>>();
+		let mut requests = server.requests();
+		recorded.sort();
+		requests.sort();
+		assert_eq!(recorded, requests);
+		let private = records.last().unwrap();
+		assert_eq!(private["status"], 404);
+		assert_eq!(private["headers"]["authorization"], "[redacted]");
+	}
+}
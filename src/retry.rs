@@ -0,0 +1,343 @@
+//! Retrying requests.
+
+use std::{
+	fmt,
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use reqwest::StatusCode;
+
+/// How failed requests are retried.
+///
+/// Which failures are retried is up to the [classifier](crate::TileSource::retry_classifier), by
+/// default [`classify`].
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+	/// The maximum amount of retries of a request.
+	pub max_retries:       u32,
+	/// The delay before the first retry, which doubles with each retry.
+	pub initial_backoff:   Duration,
+	/// The maximum delay before a retry.
+	pub max_backoff:       Duration,
+	/// The maximum time to spend backing off between the retries of a request, after which it's
+	/// given up on even if it has retries left.
+	pub max_backoff_total: Duration,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_retries:       8,
+			initial_backoff:   Duration::from_millis(200),
+			max_backoff:       Duration::from_secs(10),
+			max_backoff_total: Duration::from_secs(60),
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// A policy that never retries.
+	pub const NONE: Self = Self {
+		max_retries:       0,
+		initial_backoff:   Duration::ZERO,
+		max_backoff:       Duration::ZERO,
+		max_backoff_total: Duration::ZERO,
+	};
+
+	/// The delay before the given retry (starting from 0).
+	pub fn backoff(&self, retry: u32) -> Duration {
+		self.initial_backoff
+			.saturating_mul(2u32.saturating_pow(retry))
+			.min(self.max_backoff)
+	}
+
+	/// The delay before the given retry (starting from 0) of a request that has already backed off
+	/// for the given time, or `None` if the request should be given up on.
+	pub fn next_backoff(&self, retry: u32, backed_off: Duration) -> Option<Duration> {
+		self.next_delay(retry, backed_off, None)
+	}
+
+	/// Like [`next_backoff`](Self::next_backoff), but delaying by the delay the server asked for
+	/// (see [`retry_after`]) instead of the backoff, if it asked for one. The requested delay isn't
+	/// capped by the [maximum backoff](Self::max_backoff), but still counts towards the
+	/// [total](Self::max_backoff_total).
+	pub fn next_delay(
+		&self,
+		retry: u32,
+		backed_off: Duration,
+		requested: Option<Duration>,
+	) -> Option<Duration> {
+		let delay = requested.unwrap_or_else(|| self.backoff(retry));
+		(retry < self.max_retries && backed_off.saturating_add(delay) <= self.max_backoff_total)
+			.then_some(delay)
+	}
+}
+
+/// What to do about a failed request, see [`RetryClassifier`].
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+	/// The failure is transient: retry the request according to the [`RetryPolicy`], failing once
+	/// its retries run out.
+	Retry,
+	/// The resource doesn't exist: a dimension probe went past the image's boundary, or a tile is
+	/// [missing](crate::Error::TileMissing).
+	Boundary,
+	/// Fail.
+	Fatal,
+}
+
+/// Classifies a failed tile or dimension probe request by its error and its HTTP status (if it got
+/// a response), see [`TileSource::retry_classifier`](crate::TileSource::retry_classifier).
+pub type RetryClassifier =
+	Arc<dyn Fn(&reqwest::Error, Option<StatusCode>) -> RetryDecision + Send + Sync>;
+
+/// The default [classifier](RetryClassifier): transient failures (rate-limiting (HTTP 429), server
+/// errors (HTTP 5xx), timeouts, and connection errors) are retried, other client errors (HTTP 4xx)
+/// are boundaries, and anything else is fatal.
+pub fn classify(error: &reqwest::Error, status: Option<StatusCode>) -> RetryDecision {
+	match status {
+		Some(StatusCode::TOO_MANY_REQUESTS) => RetryDecision::Retry,
+		Some(status) if status.is_server_error() => RetryDecision::Retry,
+		Some(status) if status.is_client_error() => RetryDecision::Boundary,
+		_ if error.is_timeout() || error.is_connect() => RetryDecision::Retry,
+		_ => RetryDecision::Fatal,
+	}
+}
+
+/// Classifies the error with the classifier, or the [default](classify) if there's none.
+///
+/// A response that asked to be retried later (see [`retry_after`]) is retried even if it's
+/// classified as a boundary: servers that generate tiles on demand may respond so (e.g. with HTTP
+/// 404) until the tile is ready, unlike a tile that doesn't exist.
+pub(crate) fn decide(
+	classifier: Option<&RetryClassifier>,
+	error: &reqwest::Error,
+	retry_after: Option<Duration>,
+) -> RetryDecision {
+	let decision = match classifier {
+		Some(classifier) => classifier(error, error.status()),
+		None => classify(error, error.status()),
+	};
+	match decision {
+		RetryDecision::Boundary if retry_after.is_some() => RetryDecision::Retry,
+		decision => decision,
+	}
+}
+
+/// The delay the response asks to wait before retrying with its `Retry-After` header, given in
+/// seconds or as an HTTP date.
+pub(crate) fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+	let value = response
+		.headers()
+		.get(reqwest::header::RETRY_AFTER)?
+		.to_str()
+		.ok()?
+		.trim();
+	match value.parse() {
+		Ok(seconds) => Some(Duration::from_secs(seconds)),
+		Err(_) => httpdate::parse_http_date(value)
+			.ok()?
+			.duration_since(std::time::SystemTime::now())
+			.ok()
+			.or(Some(Duration::ZERO)),
+	}
+}
+
+/// Logs that the tile at the position is retried, e.g. `tile (3,4) attempt 2/6 after 503 Service
+/// Unavailable, in 500ms`, given the amount of retries so far, the maximum amount of retries, the
+/// cause of the retry, and the delay before the next attempt.
+///
+/// The first retry is logged at INFO, so that a flaky server explains a slow rip, and the rest at
+/// DEBUG.
+pub(crate) fn log_tile_retry(
+	(x, y): (usize, usize),
+	retry: u32,
+	max_retries: u32,
+	cause: &dyn fmt::Display,
+	backoff: Duration,
+) {
+	let (attempt, attempts) = (retry + 2, max_retries + 1);
+	let backoff_ms = backoff.as_millis() as u64;
+	if retry == 0 {
+		tracing::info!(
+			x,
+			y,
+			attempt,
+			attempts,
+			%cause,
+			backoff_ms,
+			"tile ({x},{y}) attempt {attempt}/{attempts} after {cause}, in {backoff_ms}ms"
+		);
+	} else {
+		tracing::debug!(
+			x,
+			y,
+			attempt,
+			attempts,
+			%cause,
+			backoff_ms,
+			"tile ({x},{y}) attempt {attempt}/{attempts} after {cause}, in {backoff_ms}ms"
+		);
+	}
+}
+
+/// The retries a rip may make across all of its tiles, see
+/// [`RipOptions::max_total_retries`](crate::RipOptions::max_total_retries).
+///
+/// It also accounts for the time spent backing off, which is [reported](crate::Progress::backed_off)
+/// with the progress and logged when the rip is over.
+#[derive(Debug, Default)]
+pub(crate) struct RetryBudget {
+	/// The maximum amount of retries, or `None` for no limit.
+	max:        Option<usize>,
+	/// The amount of retries taken so far.
+	used:       AtomicUsize,
+	/// The total time spent backing off so far, in nanoseconds.
+	backed_off: AtomicU64,
+}
+
+impl RetryBudget {
+	/// A budget of the given amount of retries, or `None` for no limit.
+	pub fn new(max: Option<usize>) -> Self {
+		Self {
+			max,
+			used: AtomicUsize::new(0),
+			backed_off: AtomicU64::new(0),
+		}
+	}
+
+	/// Takes a retry from the budget, failing with the size of the budget if it's exhausted.
+	pub fn take(&self) -> Result<(), usize> {
+		let used = self.used.fetch_add(1, Ordering::SeqCst);
+		match self.max {
+			Some(max) if used >= max => Err(max),
+			_ => Ok(()),
+		}
+	}
+
+	/// Accounts for backing off for the given time.
+	pub fn back_off(&self, backoff: Duration) {
+		let nanos = u64::try_from(backoff.as_nanos()).unwrap_or(u64::MAX);
+		self.backed_off.fetch_add(nanos, Ordering::SeqCst);
+	}
+
+	/// The total time spent backing off so far.
+	pub fn backed_off(&self) -> Duration {
+		Duration::from_nanos(self.backed_off.load(Ordering::SeqCst))
+	}
+}
+
+impl Drop for RetryBudget {
+	fn drop(&mut self) {
+		let retries = *self.used.get_mut();
+		if retries > 0 {
+			let backed_off = Duration::from_nanos(*self.backed_off.get_mut());
+			tracing::debug!(
+				"retried tiles {retries} times, backing off for {}ms in total",
+				backed_off.as_millis()
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{MockServer, Response};
+
+	const POLICY: RetryPolicy = RetryPolicy {
+		max_retries:       4,
+		initial_backoff:   Duration::from_millis(100),
+		max_backoff:       Duration::from_millis(300),
+		max_backoff_total: Duration::from_millis(500),
+	};
+
+	#[test]
+	fn backoff_doubles_up_to_the_maximum() {
+		let backoffs = (0..4)
+			.map(|retry| POLICY.backoff(retry).as_millis())
+			.collect::<Vec<_>>();
+		assert_eq!(backoffs, [100, 200, 300, 300]);
+		assert_eq!(POLICY.backoff(u32::MAX), POLICY.max_backoff);
+	}
+
+	#[test]
+	fn next_backoff_gives_up() {
+		let ms = Duration::from_millis;
+		assert_eq!(POLICY.next_backoff(0, Duration::ZERO), Some(ms(100)));
+		// Out of retries.
+		assert_eq!(POLICY.next_backoff(4, Duration::ZERO), None);
+		// Out of backoff time.
+		assert_eq!(POLICY.next_backoff(2, ms(300)), None);
+		assert_eq!(POLICY.next_backoff(1, ms(300)), Some(ms(200)));
+		// The requested delay isn't capped, but counts towards the total.
+		assert_eq!(
+			POLICY.next_delay(0, Duration::ZERO, Some(ms(400))),
+			Some(ms(400))
+		);
+		assert_eq!(POLICY.next_delay(0, ms(200), Some(ms(400))), None);
+		assert_eq!(RetryPolicy::NONE.next_backoff(0, Duration::ZERO), None);
+	}
+
+	#[test]
+	fn budget() {
+		let budget = RetryBudget::new(Some(2));
+		assert_eq!(budget.take(), Ok(()));
+		assert_eq!(budget.take(), Ok(()));
+		assert_eq!(budget.take(), Err(2));
+		let unlimited = RetryBudget::new(None);
+		assert!((0..100).all(|_| unlimited.take().is_ok()));
+	}
+
+	/// Fetches the server's response, and the error of its status.
+	async fn fetch(response: Response) -> (reqwest::Response, reqwest::Error) {
+		let server = MockServer::start(move |_, _| response.clone());
+		let response = reqwest::get(server.url("/")).await.unwrap();
+		let error = reqwest::get(server.url("/"))
+			.await
+			.unwrap()
+			.error_for_status()
+			.unwrap_err();
+		(response, error)
+	}
+
+	#[tokio::test]
+	async fn classifies_statuses() {
+		for (status, decision) in [
+			(429, RetryDecision::Retry),
+			(503, RetryDecision::Retry),
+			(404, RetryDecision::Boundary),
+			(400, RetryDecision::Boundary),
+		] {
+			let (_, error) = fetch(Response::status(status)).await;
+			assert_eq!(classify(&error, error.status()), decision, "{status}");
+			assert_eq!(decide(None, &error, None), decision, "{status}");
+		}
+		// A boundary that asks to be retried later is retried.
+		let (response, error) = fetch(Response::status(404).with_header("Retry-After", "2")).await;
+		let delay = retry_after(&response);
+		assert_eq!(delay, Some(Duration::from_secs(2)));
+		assert_eq!(decide(None, &error, delay), RetryDecision::Retry);
+		// The classifier overrides the default.
+		let fatal: RetryClassifier = Arc::new(|_, _| RetryDecision::Fatal);
+		assert_eq!(decide(Some(&fatal), &error, None), RetryDecision::Fatal);
+	}
+
+	#[tokio::test]
+	async fn parses_retry_after() {
+		let (response, _) = fetch(Response::status(503)).await;
+		assert_eq!(retry_after(&response), None);
+		let (response, _) = fetch(Response::status(503).with_header("Retry-After", " 7 ")).await;
+		assert_eq!(retry_after(&response), Some(Duration::from_secs(7)));
+		// A date in the past is due now.
+		let (response, _) = fetch(
+			Response::status(503).with_header("Retry-After", "Wed, 21 Oct 2015 07:28:00 GMT"),
+		)
+		.await;
+		assert_eq!(retry_after(&response), Some(Duration::ZERO));
+	}
+}
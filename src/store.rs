@@ -0,0 +1,305 @@
+//! Storage of fetched tiles, e.g. to avoid re-fetching them across rips.
+
+use std::{
+	collections::{BTreeSet, HashMap},
+	fmt,
+	path::PathBuf,
+	sync::LazyLock,
+};
+
+use bytes::Bytes;
+use tokio::sync::Mutex;
+
+use itertools::Itertools as _;
+
+use crate::{TileSource, Zoom};
+
+/// A storage of tiles' data, keyed by their URL.
+///
+/// [`rip`](crate::rip) consults the store before fetching a tile, and puts fetched tiles in it.
+/// Stores are best-effort: a failure to store a tile shouldn't fail the rip, so the methods are
+/// infallible, and implementations should treat errors as cache misses.
+#[async_trait::async_trait]
+pub trait TileStore: fmt::Debug + Send + Sync {
+	/// Gets the data of the tile at the URL, if stored.
+	async fn get(&self, url: &str) -> Option<Bytes>;
+	/// Stores the data of the tile at the URL.
+	async fn put(&self, url: &str, data: Bytes);
+}
+
+/// A [`TileStore`] in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore(Mutex<HashMap<String, Bytes>>);
+
+#[async_trait::async_trait]
+impl TileStore for MemoryStore {
+	async fn get(&self, url: &str) -> Option<Bytes> { self.0.lock().await.get(url).cloned() }
+
+	async fn put(&self, url: &str, data: Bytes) {
+		self.0.lock().await.insert(url.to_owned(), data);
+	}
+}
+
+/// A [`TileStore`] in a directory, storing each tile in a file named after the hash of its URL.
+#[derive(Debug, Hash, Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub struct DiskStore {
+	/// The directory of the tile files.
+	pub dir: PathBuf,
+}
+
+impl DiskStore {
+	/// Creates a store in the directory, which will be created if missing.
+	pub fn new(dir: impl Into<PathBuf>) -> Self { Self { dir: dir.into() } }
+
+	/// The path of the file of the tile at the URL.
+	fn path(&self, url: &str) -> PathBuf {
+		// FNV-1a, which is stable across runs and platforms, unlike the standard library's hasher.
+		let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+			(hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+		});
+		self.dir.join(format!("{hash:016x}"))
+	}
+}
+
+#[async_trait::async_trait]
+impl TileStore for DiskStore {
+	async fn get(&self, url: &str) -> Option<Bytes> {
+		tokio::fs::read(self.path(url)).await.ok().map(Bytes::from)
+	}
+
+	async fn put(&self, url: &str, data: Bytes) {
+		let result = async {
+			tokio::fs::create_dir_all(&self.dir).await?;
+			tokio::fs::write(self.path(url), data).await
+		}
+		.await;
+		if let Err(e) = result {
+			tracing::warn!("failed to store tile {url} in {}: {e}", self.dir.display());
+		}
+	}
+}
+
+/// A tile file name of a [`TileDir`].
+static TILE_FILE: LazyLock<regex::Regex> =
+	LazyLock::new(|| regex::Regex::new(r"^x(\d+)_y(\d+)_z(\d+)\.[^.]+$").unwrap());
+
+/// A read-only [`TileStore`] of a directory of tiles that were saved separately (e.g. by an external
+/// downloader), named `x<column>_y<row>_z<zoom>.<extension>`, to stitch them without the network.
+///
+/// The tiles are keyed by the URLs of the directory's [source](TileDir::source), which isn't meant to
+/// be fetched, so a rip over the store should be of a [complete](TileDir::missing) grid.
+#[derive(Debug, Clone)]
+pub struct TileDir {
+	/// The directory of the tile files.
+	pub dir: PathBuf,
+	/// The tiles by their zoom level and position.
+	tiles:   BTreeSet<(Zoom, (usize, usize))>,
+	/// The tile files by their URLs.
+	files:   HashMap<String, PathBuf>,
+}
+
+impl TileDir {
+	/// Finds the tiles in the directory, ignoring other files.
+	pub fn scan(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+		let dir = dir.into();
+		let source = Self::source_of(&dir);
+		let (mut tiles, mut files) = (BTreeSet::new(), HashMap::new());
+		for entry in std::fs::read_dir(&dir)? {
+			let path = entry?.path();
+			let Some(captures) = path
+				.file_name()
+				.and_then(|name| name.to_str())
+				.and_then(|name| TILE_FILE.captures(name))
+			else {
+				continue;
+			};
+			let [Ok(x), Ok(y), Ok(z)] = [1, 2, 3].map(|i| captures[i].parse::<usize>()) else {
+				continue;
+			};
+			let Some(zoom) = Zoom::new(z) else {
+				continue;
+			};
+			tiles.insert((zoom, (x, y)));
+			files.insert(source.tile_url(x, y, zoom), path);
+		}
+		Ok(Self { dir, tiles, files })
+	}
+
+	/// The name of the file of the tile at the position and zoom level, of the given extension, as
+	/// the directory expects it, e.g. `x3_y4_z5.jpg`.
+	pub fn file_name((x, y): (usize, usize), zoom: Zoom, extension: &str) -> String {
+		format!("x{x}_y{y}_z{zoom}.{extension}")
+	}
+
+	/// The source whose tile URLs key the tiles.
+	pub fn source(&self) -> TileSource { Self::source_of(&self.dir) }
+
+	fn source_of(dir: &std::path::Path) -> TileSource {
+		TileSource::from(format!("file://{}", dir.display()))
+	}
+
+	/// The zoom levels of the tiles, from the lowest.
+	pub fn zooms(&self) -> Vec<Zoom> { self.tiles.iter().map(|(zoom, _)| *zoom).dedup().collect() }
+
+	/// The columns and rows of the zoom level's grid, spanning its farthest tiles, if it has any.
+	pub fn grid(&self, zoom: Zoom) -> Option<(usize, usize)> {
+		self.positions(zoom)
+			.map(|(x, y)| (x + 1, y + 1))
+			.reduce(|(columns, rows), (x, y)| (columns.max(x), rows.max(y)))
+	}
+
+	/// The positions in the zoom level's [grid](TileDir::grid) that have no tile, in row order.
+	pub fn missing(&self, zoom: Zoom) -> Vec<(usize, usize)> {
+		let Some((columns, rows)) = self.grid(zoom) else {
+			return Vec::new();
+		};
+		(0..rows)
+			.flat_map(|y| (0..columns).map(move |x| (x, y)))
+			.filter(|&position| !self.tiles.contains(&(zoom, position)))
+			.collect()
+	}
+
+	fn positions(&self, zoom: Zoom) -> impl Iterator<Item = (usize, usize)> + '_ {
+		self.tiles
+			.range((zoom, (0, 0))..=(zoom, (usize::MAX, usize::MAX)))
+			.map(|(_, position)| *position)
+	}
+}
+
+#[async_trait::async_trait]
+impl TileStore for TileDir {
+	async fn get(&self, url: &str) -> Option<Bytes> {
+		let path = self.files.get(url)?;
+		match tokio::fs::read(path).await {
+			Ok(data) => Some(Bytes::from(data)),
+			Err(e) => {
+				tracing::warn!("failed to read tile {}: {e}", path.display());
+				None
+			}
+		}
+	}
+
+	/// Doesn't store the tile, since the directory is read-only.
+	async fn put(&self, _url: &str, _data: Bytes) {}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use image::GenericImageView as _;
+
+	use super::*;
+	use crate::{
+		mock::{tile, tile_color, MockServer, TempDir, TILE_SIZE},
+		RipOptions,
+	};
+
+	/// Writes the tiles of a grid of the columns and rows at the zoom level to the directory.
+	fn write_grid(dir: &std::path::Path, (columns, rows): (usize, usize), zoom: Zoom) {
+		for (x, y) in (0..columns).cartesian_product(0..rows) {
+			let name = TileDir::file_name((x, y), zoom, "png");
+			std::fs::write(dir.join(name), tile(x, y)).unwrap();
+		}
+	}
+
+	/// Stitches the zoom level of the tile directory over the store, without the network.
+	async fn stitch(tile_dir: TileDir, zoom: Zoom) -> Result<image::DynamicImage, crate::Error> {
+		let options = RipOptions {
+			grid: tile_dir.grid(zoom),
+			tile_store: Some(Arc::new(tile_dir.clone())),
+			retry: crate::RetryPolicy::NONE,
+			..RipOptions::new(zoom)
+		};
+		crate::rip(
+			Arc::new(reqwest::Client::new()),
+			&tile_dir.source(),
+			&options,
+		)
+		.await
+	}
+
+	#[test]
+	fn file_names_scan_back() {
+		let name = TileDir::file_name((3, 4), Zoom::new(5).unwrap(), "jpg");
+		assert_eq!(name, "x3_y4_z5.jpg");
+		let captures = TILE_FILE.captures(&name).unwrap();
+		assert_eq!([&captures[1], &captures[2], &captures[3]], ["3", "4", "5"]);
+		for name in [
+			"3_4.jpg",
+			"x3_y4.jpg",
+			"x3_y4_z5",
+			"x3_y4_z5.tar.gz",
+			"xa_y4_z5.jpg",
+		] {
+			assert!(!TILE_FILE.is_match(name), "{name}");
+		}
+	}
+
+	#[tokio::test]
+	async fn reassembles_fixture() {
+		let dir = TempDir::new("tile-dir");
+		write_grid(&dir, (3, 2), Zoom::new(1).unwrap());
+		write_grid(&dir, (1, 1), Zoom::new(0).unwrap());
+		std::fs::write(dir.join("notes.txt"), "not a tile").unwrap();
+		let tile_dir = TileDir::scan(&*dir).unwrap();
+		assert_eq!(
+			tile_dir.zooms(),
+			[Zoom::new(0).unwrap(), Zoom::new(1).unwrap()]
+		);
+		assert_eq!(tile_dir.grid(Zoom::new(1).unwrap()), Some((3, 2)));
+		assert_eq!(tile_dir.grid(Zoom::new(2).unwrap()), None);
+		assert!(tile_dir.missing(Zoom::new(1).unwrap()).is_empty());
+
+		let image = stitch(tile_dir, Zoom::new(1).unwrap()).await.unwrap();
+		assert_eq!(image.dimensions(), (3 * TILE_SIZE, 2 * TILE_SIZE));
+		for (x, y) in (0..3).cartesian_product(0..2) {
+			let pixel = image.get_pixel(x as u32 * TILE_SIZE, y as u32 * TILE_SIZE);
+			assert_eq!(pixel, tile_color(x, y), "tile ({x},{y})");
+		}
+	}
+
+	#[tokio::test]
+	async fn reassembly_fails_on_missing_tile() {
+		let dir = TempDir::new("tile-dir-missing");
+		write_grid(&dir, (3, 2), Zoom::new(1).unwrap());
+		std::fs::remove_file(dir.join(TileDir::file_name((1, 1), Zoom::new(1).unwrap(), "png")))
+			.unwrap();
+		let tile_dir = TileDir::scan(&*dir).unwrap();
+		assert_eq!(tile_dir.grid(Zoom::new(1).unwrap()), Some((3, 2)));
+		assert_eq!(tile_dir.missing(Zoom::new(1).unwrap()), [(1, 1)]);
+
+		let result = stitch(tile_dir, Zoom::new(1).unwrap()).await;
+		assert!(result.is_err());
+	}
+
+	#[tokio::test]
+	async fn disk_store_round_trip() {
+		let dir = TempDir::new("disk-store");
+		let store = DiskStore::new(dir.join("cache"));
+		let data = Bytes::from_static(b"tile");
+		assert_eq!(store.get("https://example.com/a").await, None);
+		store.put("https://example.com/a", data.clone()).await;
+		assert_eq!(store.get("https://example.com/a").await, Some(data));
+		assert_eq!(store.get("https://example.com/b").await, None);
+	}
+
+	#[tokio::test]
+	async fn memory_store_saves_refetching() {
+		let server = MockServer::image(3, 2, 0);
+		let source = TileSource::from(server.base_url());
+		let options = RipOptions {
+			tile_store: Some(Arc::new(MemoryStore::default())),
+			..RipOptions::new(Zoom::new(0).unwrap())
+		};
+		let first = crate::rip(Arc::new(reqwest::Client::new()), &source, &options)
+			.await
+			.unwrap();
+		assert_eq!(server.tile_fetches(), 6);
+		let second = crate::rip(Arc::new(reqwest::Client::new()), &source, &options)
+			.await
+			.unwrap();
+		assert_eq!(server.tile_fetches(), 6);
+		assert_eq!(first.as_bytes(), second.as_bytes());
+	}
+}
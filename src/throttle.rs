@@ -0,0 +1,72 @@
+//! Limiting the download bandwidth.
+
+use std::{
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+/// A limit of the bandwidth of the tile downloads it's shared by, see
+/// [`RipOptions::bandwidth_limit`](crate::RipOptions::bandwidth_limit).
+///
+/// Every downloaded chunk reserves the time it takes to download at the limit, and the download
+/// waits until its reservation is up before reading on, so the downloads are paced to the limit
+/// together regardless of their amount.
+#[derive(Debug)]
+pub struct BandwidthLimit {
+	bytes_per_second: u64,
+	/// When the reservations made so far are up.
+	free_at:          Mutex<Instant>,
+}
+
+impl BandwidthLimit {
+	/// A limit of the given amount of bytes per second.
+	pub fn new(bytes_per_second: u64) -> Self {
+		Self {
+			bytes_per_second: bytes_per_second.max(1),
+			free_at:          Mutex::new(Instant::now()),
+		}
+	}
+
+	/// The amount of bytes per second.
+	pub fn bytes_per_second(&self) -> u64 { self.bytes_per_second }
+
+	/// Reserves the download of the given amount of bytes, and waits until the reservation is up.
+	pub(crate) async fn consume(&self, bytes: usize) {
+		let duration = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+		let until = {
+			let mut free_at = self.free_at.lock().unwrap();
+			*free_at = (*free_at).max(Instant::now()) + duration;
+			*free_at
+		};
+		tokio::time::sleep_until(until.into()).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use super::*;
+	use crate::mock::{self, MockServer};
+
+	#[tokio::test]
+	async fn paces_the_rip() {
+		let server = MockServer::image(3, 2, 0);
+		let source = crate::TileSource::from(server.base_url());
+		let bytes = (0..3)
+			.flat_map(|x| (0..2).map(move |y| mock::tile(x, y).len()))
+			.sum::<usize>() as u64;
+		// The tiles take half a second to download at the limit.
+		let options = crate::RipOptions {
+			grid: Some((3, 2)),
+			bandwidth_limit: Some(Arc::new(BandwidthLimit::new(bytes * 2))),
+			..crate::RipOptions::new(crate::Zoom::new(0).unwrap())
+		};
+		let started = Instant::now();
+		crate::rip(Arc::new(reqwest::Client::new()), &source, &options)
+			.await
+			.unwrap();
+		let elapsed = started.elapsed();
+		assert!(elapsed >= Duration::from_millis(500), "{elapsed:?}");
+	}
+}
@@ -1,5 +1,83 @@
 //! Internal utilities.
 
+use std::{fmt, time::Instant};
+
+use reqwest::header::{HeaderName, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION};
+
+/// The [target](tracing::Metadata::target) of the events [`send_request`] emits.
+pub const REQUEST_TARGET: &str = "deathrip::http";
+
+/// The headers whose values [`send_request`] redacts from its events, which are credentials.
+const REDACTED_HEADERS: [HeaderName; 3] = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE];
+
+/// Sends the request, emitting a debug event of the [`REQUEST_TARGET`] once it's answered (or fails)
+/// with its `method`, `url`, `headers` (the ones set on the request rather than the client's
+/// defaults, as a JSON object, with credentials redacted), the `status` of the response, the
+/// `error`, if any, and the `duration_ms` it took to get the response.
+pub async fn send_request(request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+	let (client, request) = request.build_split();
+	let request = request?;
+	let (method, url) = (request.method().clone(), request.url().clone());
+	let headers = serde_json::Value::Object(
+		request
+			.headers()
+			.iter()
+			.map(|(name, value)| {
+				let value = if REDACTED_HEADERS.contains(name) {
+					"[redacted]".to_owned()
+				} else {
+					String::from_utf8_lossy(value.as_bytes()).into_owned()
+				};
+				(name.to_string(), value.into())
+			})
+			.collect(),
+	);
+	let time_start = Instant::now();
+	let response = client.execute(request).await;
+	let duration_ms = u64::try_from(time_start.elapsed().as_millis()).unwrap_or(u64::MAX);
+	let (status, error) = match &response {
+		Ok(response) => (Some(response.status().as_u16()), None),
+		Err(e) => (
+			e.status().map(|status| status.as_u16()),
+			Some(e.to_string()),
+		),
+	};
+	tracing::debug!(
+		target: REQUEST_TARGET,
+		%method,
+		%url,
+		%headers,
+		status,
+		error,
+		duration_ms,
+		"sent a request"
+	);
+	response
+}
+
+/// An amount of bytes, displayed human-readably in powers of 1024, e.g. `1.5 MiB`.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+		if self.0 < 1024 {
+			return write!(f, "{} B", self.0);
+		}
+		let mut size = self.0 as f64 / 1024.0;
+		let mut unit = UNITS[0];
+		for next in &UNITS[1..] {
+			if size < 1024.0 {
+				break;
+			}
+			size /= 1024.0;
+			unit = next;
+		}
+		write!(f, "{size:.1} {unit}")
+	}
+}
+
 /// A [`String`] buffer with a mutating tail.
 #[derive(Debug, Hash, Default, Clone, PartialEq, PartialOrd, Eq, Ord)]
 pub struct StringMutTail {
@@ -7,7 +85,8 @@ pub struct StringMutTail {
 	url:        String,
 	/// The index of the tail.
 	///
-	/// The tail is at `&url[tail_index..]`.
+	/// The tail is at `&url[tail_index..]`. The index is fixed at the end of the base, so it's always
+	/// a char boundary, since only the tail is ever replaced.
 	tail_index: usize,
 }
 
@@ -29,8 +108,142 @@ impl From<&str> for StringMutTail {
 impl StringMutTail {
 	/// Sets the [tail](StringMutTail::tail_index) to the given integer.
 	pub fn with_tail_int(&mut self, integer: impl itoa::Integer) -> &str {
+		debug_assert!(
+			self.url.is_char_boundary(self.tail_index),
+			"the tail index {} of {:?} isn't a char boundary",
+			self.tail_index,
+			self.url
+		);
+		// The tail may be of any width, e.g. when going from 9 to 10 and back, since all of it is
+		// replaced.
 		self.url.truncate(self.tail_index);
 		self.url.push_str(itoa::Buffer::new().format(integer));
 		&self.url
 	}
 }
+
+/// Tracks how many operations are in-flight over time.
+#[derive(Debug)]
+pub struct InFlight(std::sync::Mutex<InFlightState>);
+
+#[derive(Debug)]
+struct InFlightState {
+	/// When the tracking started.
+	start:    Instant,
+	/// When [`current`](InFlightState::current) last changed.
+	since:    Instant,
+	/// The amount of operations in-flight.
+	current:  usize,
+	/// The highest amount of operations that were in-flight at once.
+	peak:     usize,
+	/// The sum of the amounts of operations in-flight, weighted by their duration in seconds.
+	weighted: f64,
+}
+
+impl InFlightState {
+	/// Accounts for the time since the last change, and sets the current amount.
+	fn set(&mut self, current: usize) {
+		let now = Instant::now();
+		self.weighted += self.current as f64 * (now - self.since).as_secs_f64();
+		self.since = now;
+		self.current = current;
+		self.peak = self.peak.max(current);
+	}
+}
+
+impl Default for InFlight {
+	fn default() -> Self {
+		let now = Instant::now();
+		Self(std::sync::Mutex::new(InFlightState {
+			start:    now,
+			since:    now,
+			current:  0,
+			peak:     0,
+			weighted: 0.0,
+		}))
+	}
+}
+
+impl InFlight {
+	/// Marks an operation as in-flight until the returned guard is dropped.
+	pub fn enter(&self) -> InFlightGuard<'_> {
+		let mut state = self.0.lock().unwrap();
+		let current = state.current + 1;
+		state.set(current);
+		InFlightGuard(self)
+	}
+
+	/// Returns the peak and the time-weighted average amount of in-flight operations so far.
+	pub fn stats(&self) -> (usize, f64) {
+		let mut state = self.0.lock().unwrap();
+		let current = state.current;
+		state.set(current);
+		let elapsed = (state.since - state.start).as_secs_f64();
+		let average = if elapsed > 0.0 {
+			state.weighted / elapsed
+		} else {
+			0.0
+		};
+		(state.peak, average)
+	}
+}
+
+/// Marks an operation as in-flight in [`InFlight`] while alive.
+#[derive(Debug)]
+pub struct InFlightGuard<'a>(&'a InFlight);
+
+impl Drop for InFlightGuard<'_> {
+	fn drop(&mut self) {
+		let mut state = self.0 .0.lock().unwrap();
+		let current = state.current - 1;
+		state.set(current);
+	}
+}
+
+/// Moves a random pick of `count` elements to the start of the slice, reproducibly for the same
+/// seed (a partial Fisher-Yates shuffle with a SplitMix64 generator).
+pub fn partial_shuffle<T>(slice: &mut [T], count: usize, mut seed: u64) {
+	let mut next = || {
+		seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = seed;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	};
+	for i in 0..count.min(slice.len()) {
+		let j = i + (next() % (slice.len() - i) as u64) as usize;
+		slice.swap(i, j);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tail_of_any_width() {
+		let mut url = StringMutTail::from("https://example.com/img=x0-y0-z");
+		assert_eq!(url.with_tail_int(9), "https://example.com/img=x0-y0-z9");
+		assert_eq!(url.with_tail_int(10), "https://example.com/img=x0-y0-z10");
+		assert_eq!(url.with_tail_int(100), "https://example.com/img=x0-y0-z100");
+		assert_eq!(url.with_tail_int(9), "https://example.com/img=x0-y0-z9");
+		// The base may end with characters of more than a byte.
+		let mut url = StringMutTail::from("\u{05D0}=");
+		assert_eq!(url.with_tail_int(12), "\u{05D0}=12");
+		assert_eq!(url.with_tail_int(3), "\u{05D0}=3");
+	}
+
+	#[test]
+	fn in_flight_peak() {
+		let in_flight = InFlight::default();
+		let first = in_flight.enter();
+		let second = in_flight.enter();
+		drop(first);
+		let third = in_flight.enter();
+		drop((second, third));
+		let (peak, average) = in_flight.stats();
+		assert_eq!(peak, 2);
+		assert!(average <= 2.0, "{average}");
+		assert_eq!(in_flight.0.lock().unwrap().current, 0);
+	}
+}
@@ -0,0 +1,283 @@
+//! Zoom levels, and measuring them to pick the one that's worth its size.
+
+use image::GenericImageView;
+use reqwest::Client;
+
+use crate::{determine_dimensions, Error, RetryPolicy, TileSource};
+
+/// A zoom level, where 0 is the lowest.
+#[derive(
+	Debug,
+	Default,
+	Hash,
+	Clone,
+	Copy,
+	PartialEq,
+	Eq,
+	PartialOrd,
+	Ord,
+	serde::Serialize,
+	serde::Deserialize,
+)]
+#[serde(into = "usize", try_from = "usize")]
+pub struct Zoom(usize);
+
+impl Zoom {
+	/// The lowest zoom level.
+	pub const LOWEST: Self = Self(0);
+	/// The highest zoom level. Each level doubles the resolution of the one below it, so this is far
+	/// beyond the levels of any real image, and a level above it is a mistake.
+	pub const HIGHEST: Self = Self(30);
+
+	/// The given zoom level, or `None` if it's above the [highest](Self::HIGHEST).
+	pub const fn new(level: usize) -> Option<Self> {
+		if level > Self::HIGHEST.0 {
+			return None;
+		}
+		Some(Self(level))
+	}
+
+	/// The level as a number.
+	pub const fn level(self) -> usize { self.0 }
+
+	/// The zoom level the given amount of levels below this one, or the lowest if there aren't
+	/// that many.
+	pub const fn below(self, levels: usize) -> Self { Self(self.0.saturating_sub(levels)) }
+}
+
+impl std::fmt::Display for Zoom {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { self.0.fmt(f) }
+}
+
+impl From<Zoom> for usize {
+	fn from(zoom: Zoom) -> Self { zoom.0 }
+}
+
+/// A zoom level that's not a number from the [lowest](Zoom::LOWEST) to the
+/// [highest](Zoom::HIGHEST), see [`Zoom`]'s [`FromStr`](std::str::FromStr) and
+/// [`TryFrom<usize>`](TryFrom).
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("zoom level should be a number from 0 to {}", Zoom::HIGHEST)]
+pub struct ParseZoomError;
+
+impl TryFrom<usize> for Zoom {
+	type Error = ParseZoomError;
+
+	fn try_from(level: usize) -> Result<Self, Self::Error> {
+		Self::new(level).ok_or(ParseZoomError)
+	}
+}
+
+impl std::str::FromStr for Zoom {
+	type Err = ParseZoomError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		s.parse::<usize>()
+			.map_err(|_| ParseZoomError)
+			.and_then(Self::try_from)
+	}
+}
+
+/// The measurements of a zoom level from a sample of its tiles, see [`enumerate_zooms`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomLevel {
+	/// The zoom level.
+	pub zoom:            Zoom,
+	/// The columns of tiles.
+	pub columns:         usize,
+	/// The rows of tiles.
+	pub rows:            usize,
+	/// The amount of pixels, assuming all the tiles are the size of the first.
+	pub pixels:          u64,
+	/// The estimated size of all the tiles in bytes, extrapolated from the sampled tiles.
+	pub estimated_bytes: u64,
+	/// The mean absolute difference of the luma of adjacent pixels in the sampled tiles. Upscaled
+	/// levels, which add pixels but no detail, are less sharp than the level they're upscaled from.
+	pub sharpness:       f64,
+}
+
+impl std::fmt::Display for ZoomLevel {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(
+			f,
+			"zoom {}: {}\u{00D7}{} tiles, {} pixels, ~{}",
+			self.zoom,
+			self.columns,
+			self.rows,
+			self.pixels,
+			crate::ByteSize(self.estimated_bytes)
+		)
+	}
+}
+
+impl ZoomLevel {
+	/// The lowest sharpness a level's detail is weighed by, so that flat (e.g. blank) tiles are
+	/// weighed by their pixels alone rather than as no detail.
+	const MIN_SHARPNESS: f64 = 0.01;
+
+	/// The detail of the level: its pixels weighed by their sharpness.
+	fn detail(&self) -> f64 { self.pixels as f64 * self.sharpness.max(Self::MIN_SHARPNESS) }
+}
+
+/// The default threshold of [`pick_knee_zoom`].
+pub const DEFAULT_KNEE_THRESHOLD: f64 = 1.5;
+
+/// Measures the zoom levels in the range, fetching the given amount of tiles of each (spread evenly
+/// across the image, starting with the first).
+pub async fn enumerate_zooms(
+	client: impl AsRef<Client> + 'static + Send + Clone,
+	source: &TileSource,
+	zooms: std::ops::RangeInclusive<Zoom>,
+	samples: usize,
+	retry: RetryPolicy,
+) -> Result<Vec<ZoomLevel>, Error> {
+	let mut levels = Vec::new();
+	for zoom in (zooms.start().0..=zooms.end().0).map(Zoom) {
+		let (columns, rows) =
+			determine_dimensions(Clone::clone(&client), source, zoom, 1, retry).await?;
+		let tiles = columns * rows;
+		if tiles == 0 {
+			continue;
+		}
+		let samples = samples.clamp(1, tiles);
+		let (mut tile_size, mut bytes, mut sharpness) = ((0, 0), 0, 0.0);
+		for i in 0..samples {
+			let index = i * tiles / samples;
+			let (x, y) = (index % columns, index / columns);
+			let data = crate::send_request(client.as_ref().get(source.tile_url(x, y, zoom)))
+				.await?
+				.error_for_status()?
+				.bytes()
+				.await?;
+			let tile = image::load_from_memory(&data)?;
+			if i == 0 {
+				tile_size = tile.dimensions();
+			}
+			bytes += data.len() as u64;
+			sharpness += measure_sharpness(&tile);
+		}
+		let (tile_width, tile_height) = tile_size;
+		let level = ZoomLevel {
+			zoom,
+			columns,
+			rows,
+			pixels: tiles as u64 * u64::from(tile_width) * u64::from(tile_height),
+			estimated_bytes: bytes * tiles as u64 / samples as u64,
+			sharpness: sharpness / samples as f64,
+		};
+		tracing::debug!("measured {level}, sharpness {:.2}", level.sharpness);
+		levels.push(level);
+	}
+	Ok(levels)
+}
+
+/// Picks the zoom level at the "knee" of the size-vs-detail curve of the levels (in ascending
+/// order): the highest level before one whose growth in bytes exceeds its growth in detail by more
+/// than the threshold.
+///
+/// A level that's truly finer than the one below has about 4 times its pixels, each about as sharp,
+/// for about 4 times its bytes, a ratio of about 1. A level that's upscaled from the one below
+/// spreads the same detail over those pixels, so its detail grows by less than its bytes do. A
+/// threshold of 1 picks the highest level that pays for itself, and higher thresholds tolerate
+/// more bytes per added detail.
+///
+/// Returns `None` if there are no levels.
+pub fn pick_knee_zoom(levels: &[ZoomLevel], threshold: f64) -> Option<Zoom> {
+	let mut levels = levels.iter();
+	let mut pick = levels.next()?;
+	for level in levels {
+		let bytes_growth = level.estimated_bytes as f64 / pick.estimated_bytes.max(1) as f64;
+		let detail_growth = level.detail() / pick.detail().max(f64::MIN_POSITIVE);
+		let ratio = bytes_growth / detail_growth;
+		tracing::debug!(
+			"zoom {} has {bytes_growth:.2}\u{00D7} the bytes and {detail_growth:.2}\u{00D7} the \
+			 detail of zoom {} (ratio {ratio:.2})",
+			level.zoom,
+			pick.zoom
+		);
+		if ratio > threshold {
+			break;
+		}
+		pick = level;
+	}
+	Some(pick.zoom)
+}
+
+/// The mean absolute difference of the luma of horizontally and vertically adjacent pixels.
+fn measure_sharpness(tile: &image::DynamicImage) -> f64 {
+	let luma = tile.to_luma8();
+	let (width, height) = luma.dimensions();
+	let (mut total, mut count) = (0u64, 0u64);
+	for y in 0..height {
+		for x in 0..width {
+			let pixel = luma.get_pixel(x, y)[0];
+			if x + 1 < width {
+				total += u64::from(pixel.abs_diff(luma.get_pixel(x + 1, y)[0]));
+				count += 1;
+			}
+			if y + 1 < height {
+				total += u64::from(pixel.abs_diff(luma.get_pixel(x, y + 1)[0]));
+				count += 1;
+			}
+		}
+	}
+	if count == 0 {
+		return 0.0;
+	}
+	total as f64 / count as f64
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn construction() {
+		assert_eq!(Zoom::new(0), Some(Zoom::LOWEST));
+		assert_eq!(Zoom::new(30), Some(Zoom::HIGHEST));
+		assert_eq!(Zoom::new(31), None);
+		assert_eq!(Zoom::new(usize::MAX), None);
+		assert_eq!(Zoom::new(7).map(Zoom::level), Some(7));
+		assert_eq!(Zoom::try_from(12).ok(), Zoom::new(12));
+		assert!(Zoom::try_from(31).is_err());
+	}
+
+	#[test]
+	fn parsing() {
+		assert_eq!("5".parse::<Zoom>().ok(), Zoom::new(5));
+		assert_eq!("30".parse::<Zoom>().ok(), Some(Zoom::HIGHEST));
+		for invalid in ["31", "-1", "", "five", "1.5", "99999999999999999999999"] {
+			assert!(invalid.parse::<Zoom>().is_err(), "{invalid:?}");
+		}
+		assert_eq!(
+			ParseZoomError.to_string(),
+			"zoom level should be a number from 0 to 30"
+		);
+	}
+
+	#[test]
+	fn serde() {
+		let zoom = Zoom::new(4).unwrap();
+		assert_eq!(serde_json::to_string(&zoom).unwrap(), "4");
+		assert_eq!(serde_json::from_str::<Zoom>("4").unwrap(), zoom);
+		assert!(serde_json::from_str::<Zoom>("31").is_err());
+	}
+
+	#[test]
+	fn below_saturates() {
+		let zoom = Zoom::new(5).unwrap();
+		assert_eq!(zoom.below(0), zoom);
+		assert_eq!(zoom.below(2), Zoom::new(3).unwrap());
+		assert_eq!(zoom.below(5), Zoom::LOWEST);
+		assert_eq!(zoom.below(6), Zoom::LOWEST);
+		assert_eq!(Zoom::HIGHEST.below(usize::MAX), Zoom::LOWEST);
+	}
+
+	#[test]
+	fn display() {
+		assert_eq!(Zoom::LOWEST.to_string(), "0");
+		assert_eq!(Zoom::new(12).unwrap().to_string(), "12");
+		assert_eq!(format!("{:>3}", Zoom::new(7).unwrap()), "  7");
+		assert_eq!(usize::from(Zoom::HIGHEST), 30);
+	}
+}
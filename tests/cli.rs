@@ -0,0 +1,21 @@
+//! Tests of the command-line interface as a process.
+
+use std::process::{Command, Stdio};
+
+/// The CLI exits quietly when the reader of its output closes it, e.g. `deathrip completions bash |
+/// head -c1`, rather than panicking.
+#[test]
+fn closed_output_exits_quietly() {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_deathrip"))
+		.args(["completions", "bash"])
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	// Closes the read end before the CLI is done writing.
+	drop(child.stdout.take());
+	let output = child.wait_with_output().unwrap();
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(output.status.success(), "{}: {stderr}", output.status);
+	assert!(!stderr.contains("panicked"), "{stderr}");
+}